@@ -0,0 +1,84 @@
+//! Simulated Modbus RTU slave on a pseudo terminal
+//!
+//! Prints the path of a pty that behaves like an RS485 line with a single
+//! slave attached, for testing masters and the gateway without hardware:
+//!
+//!     cargo run --example modbus_pty_slave -- 1
+//!     rs485-modbus-gateway --listen 127.0.0.1:5020 --rs485 "" /dev/pts/N
+
+extern crate libc;
+extern crate rs485;
+
+use std::ffi::CStr;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use rs485::{PortSettings, Rs485Port, SerialRs485};
+use rs485::modbus::{ExceptionCode, RegisterMap, Slave};
+
+/// Coils and holding registers backed by memory
+struct Memory {
+    coils: Vec<bool>,
+    registers: Vec<u16>,
+}
+
+fn range(addr: u16, count: usize, len: usize) -> Result<std::ops::Range<usize>, ExceptionCode> {
+    let start = addr as usize;
+    if start + count > len {
+        return Err(ExceptionCode::IllegalDataAddress);
+    }
+    Ok(start..start + count)
+}
+
+impl RegisterMap for Memory {
+    fn read_coils(&mut self, addr: u16, count: u16) -> Result<Vec<bool>, ExceptionCode> {
+        Ok(self.coils[range(addr, count as usize, self.coils.len())?].to_vec())
+    }
+
+    fn read_holding_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>, ExceptionCode> {
+        Ok(self.registers[range(addr, count as usize, self.registers.len())?].to_vec())
+    }
+
+    fn read_input_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>, ExceptionCode> {
+        self.read_holding_registers(addr, count)
+    }
+
+    fn write_coils(&mut self, addr: u16, values: &[bool]) -> Result<(), ExceptionCode> {
+        let r = range(addr, values.len(), self.coils.len())?;
+        self.coils[r].copy_from_slice(values);
+        Ok(())
+    }
+
+    fn write_registers(&mut self, addr: u16, values: &[u16]) -> Result<(), ExceptionCode> {
+        let r = range(addr, values.len(), self.registers.len())?;
+        self.registers[r].copy_from_slice(values);
+        Ok(())
+    }
+}
+
+fn main() -> io::Result<()> {
+    let unit = std::env::args().nth(1).and_then(|s| s.parse().ok()).unwrap_or(1);
+
+    // the master side of a pty accepts the same termios calls as a tty
+    let port = Rs485Port::open("/dev/ptmx", &PortSettings::new(), &SerialRs485::new())?;
+    let fd = port.as_raw_fd();
+    let path = unsafe {
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        CStr::from_ptr(libc::ptsname(fd)).to_string_lossy().into_owned()
+    };
+
+    // keep the slave side open, reads on the master fail while it is closed
+    let _keepalive = OpenOptions::new().read(true).write(true).open(&path)?;
+    println!("unit {} listening on {}", unit, path);
+
+    let mut memory = Memory {
+        coils: vec![false; 1024],
+        registers: (0..1024).collect(),
+    };
+
+    let mut slave = Slave::new(port, unit);
+    slave.serve(&mut memory).map_err(|e| io::Error::other(e.to_string()))
+}
@@ -0,0 +1,274 @@
+//! Modbus TCP to serial line gateway
+//!
+//! Accepts Modbus TCP connections and forwards their requests to devices on a
+//! single RS485 line. Requests from all connections are serialized onto the
+//! bus; timeouts and bus errors are answered with gateway exceptions.
+
+extern crate rs485;
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rs485::{DirectionMode, PortSettings, Rs485Port, SerialRs485};
+use rs485::modbus::{BROADCAST_UNIT, ExceptionCode, Framing, Master, ModbusError, Request};
+use rs485::modbus::pdu;
+use rs485::modbus::tcp::{self, Header};
+use rs485::port::{Parity, StopBits};
+
+const USAGE: &str = "\
+usage: rs485-modbus-gateway [options] <device>
+
+options:
+  --listen <addr>       TCP address to listen on (default 0.0.0.0:502)
+  --baud <rate>         baud rate (default 9600)
+  --parity <parity>     none, odd or even (default none)
+  --stop-bits <n>       1 or 2 (default 1)
+  --rs485 <conf>        RS485 configuration, e.g. enabled,rts_on_send
+                        (default enabled)
+  --direction <mode>    auto, kernel, rts, dtr or gpio:<chip>:<offset>
+                        (default auto)
+  --ascii               use Modbus ASCII instead of RTU framing
+  --timeout <ms>        response timeout (default 1000)
+  --retries <n>         retries after timeouts and checksum errors (default 0)
+  --map <tcp>=<serial>  forward TCP unit id to serial unit address; may be
+                        repeated, unmapped units are rejected once any mapping
+                        is given
+
+Without --map, TCP unit ids 1 to 247 are forwarded unchanged and unit id 0 is
+broadcast to all devices. Broadcast writes are not answered, other broadcast
+requests are rejected with an illegal function exception.";
+
+/// Gateway configuration
+struct Config {
+    device: String,
+    listen: SocketAddr,
+    settings: PortSettings,
+    rs485: SerialRs485,
+    direction: DirectionMode,
+    framing: Framing,
+    timeout: Duration,
+    retries: u32,
+    unit_map: HashMap<u8, u8>,
+}
+
+impl Config {
+    fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Config, String> {
+        let mut device = None;
+        let mut listen = SocketAddr::from(([0, 0, 0, 0], tcp::DEFAULT_PORT));
+        let mut settings = PortSettings::new();
+        let mut rs485 = SerialRs485::new();
+        rs485.set_enabled(true);
+        let mut direction = DirectionMode::Auto;
+        let mut framing = Framing::Rtu;
+        let mut timeout = Duration::from_secs(1);
+        let mut retries = 0;
+        let mut unit_map = HashMap::new();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+
+            match arg.as_str() {
+                "--listen" => listen = parse(&value()?, "listen address")?,
+                "--baud" => {
+                    settings.set_baud_rate(parse(&value()?, "baud rate")?);
+                }
+                "--parity" => {
+                    settings.set_parity(match value()?.as_str() {
+                        "none" => Parity::None,
+                        "odd" => Parity::Odd,
+                        "even" => Parity::Even,
+                        v => return Err(format!("invalid parity: {}", v)),
+                    });
+                }
+                "--stop-bits" => {
+                    settings.set_stop_bits(match value()?.as_str() {
+                        "1" => StopBits::One,
+                        "2" => StopBits::Two,
+                        v => return Err(format!("invalid stop bits: {}", v)),
+                    });
+                }
+                "--rs485" => rs485 = parse(&value()?, "RS485 configuration")?,
+                "--direction" => direction = parse(&value()?, "direction mode")?,
+                "--ascii" => framing = Framing::Ascii,
+                "--timeout" => timeout = Duration::from_millis(parse(&value()?, "timeout")?),
+                "--retries" => retries = parse(&value()?, "retries")?,
+                "--map" => {
+                    let v = value()?;
+                    let mut parts = v.splitn(2, '=');
+                    let from = parse(parts.next().unwrap_or(""), "TCP unit id")?;
+                    let to = parse(parts.next().unwrap_or(""), "serial unit address")?;
+                    unit_map.insert(from, to);
+                }
+                "-h" | "--help" => return Err(USAGE.to_owned()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ if device.is_none() => device = Some(arg),
+                _ => return Err(format!("unexpected argument: {}", arg)),
+            }
+        }
+
+        Ok(Config {
+            device: device.ok_or_else(|| USAGE.to_owned())?,
+            listen,
+            settings,
+            rs485,
+            direction,
+            framing,
+            timeout,
+            retries,
+            unit_map,
+        })
+    }
+
+    /// Serial unit address for a TCP unit id
+    ///
+    /// Without a unit map, valid serial addresses are passed through,
+    /// including the broadcast address 0.
+    fn serial_unit(&self, unit: u8) -> Option<u8> {
+        if self.unit_map.is_empty() {
+            return if unit <= 247 { Some(unit) } else { None };
+        }
+
+        self.unit_map.get(&unit).cloned()
+    }
+}
+
+fn parse<T: FromStr>(s: &str, what: &str) -> Result<T, String>
+    where T::Err: std::fmt::Display
+{
+    s.parse().map_err(|e| format!("invalid {} {:?}: {}", what, s, e))
+}
+
+/// Forward a request PDU to the bus
+///
+/// Returns the response PDU, or `None` for broadcast writes. Requests that
+/// cannot be broadcast are answered with an exception, so the client does
+/// not wait for a response that never comes.
+fn forward(config: &Config,
+           master: &Mutex<Master>,
+           unit: u8,
+           request_pdu: &[u8])
+           -> Option<Vec<u8>> {
+    let function = request_pdu.first().cloned().unwrap_or(0);
+    let exception = |code| Some(pdu::encode_exception(function, code));
+
+    let request = match Request::decode(request_pdu) {
+        Ok(request) => request,
+        Err(code) => return exception(code),
+    };
+
+    let serial_unit = match config.serial_unit(unit) {
+        Some(serial_unit) => serial_unit,
+        None => return exception(ExceptionCode::GatewayPathUnavailable),
+    };
+
+    // a poisoned lock only means another connection panicked mid-request
+    let mut master = master.lock().unwrap_or_else(|e| e.into_inner());
+
+    if serial_unit == BROADCAST_UNIT {
+        if !request.is_broadcastable() {
+            return exception(ExceptionCode::IllegalFunction);
+        }
+        if let Err(e) = master.broadcast(&request) {
+            eprintln!("broadcast failed: {}", e);
+        }
+        return None;
+    }
+
+    match master.request(serial_unit, &request) {
+        Ok(response) => {
+            Some(response.encode().unwrap_or_else(|code| pdu::encode_exception(function, code)))
+        }
+        Err(ModbusError::Exception(code)) => exception(code),
+        Err(ModbusError::InvalidRequest(_)) => exception(ExceptionCode::IllegalDataValue),
+        Err(e @ ModbusError::Io(_)) => {
+            eprintln!("unit {}: {}", serial_unit, e);
+            exception(ExceptionCode::GatewayPathUnavailable)
+        }
+        Err(e) => {
+            eprintln!("unit {}: {}", serial_unit, e);
+            exception(ExceptionCode::GatewayTargetFailedToRespond)
+        }
+    }
+}
+
+/// Serve a single TCP connection until it is closed
+fn handle_client(config: &Config, master: &Mutex<Master>, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+
+    while let Some((header, request_pdu)) = tcp::read_adu(&mut stream)? {
+        // frames of other protocols are silently discarded
+        if header.protocol_id != tcp::PROTOCOL_ID {
+            continue;
+        }
+
+        if let Some(response_pdu) = forward(config, master, header.unit, &request_pdu) {
+            tcp::write_adu(&mut stream,
+                           &Header::new(header.transaction_id, header.unit),
+                           &response_pdu)?;
+            stream.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run(config: Config) -> io::Result<()> {
+    let port = Rs485Port::open_with_direction(&config.device,
+                                              &config.settings,
+                                              &config.rs485,
+                                              &config.direction)?;
+
+    let mut master = Master::with_framing(port, config.framing);
+    master.set_timeout(config.timeout).set_retries(config.retries);
+
+    let listener = TcpListener::bind(config.listen)?;
+    eprintln!("forwarding {} to {} ({})",
+              listener.local_addr()?,
+              config.device,
+              config.framing);
+
+    let config = Arc::new(config);
+    let master = Arc::new(Mutex::new(master));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept failed: {}", e);
+                continue;
+            }
+        };
+
+        let config = config.clone();
+        let master = master.clone();
+        thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            if let Err(e) = handle_client(&config, &master, stream) {
+                eprintln!("{:?}: {}", peer, e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(config) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
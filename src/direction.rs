@@ -0,0 +1,376 @@
+//! Direction control strategies
+//!
+//! Half-duplex RS485 requires the transceiver's line driver to be enabled
+//! while sending and disabled otherwise. Depending on the board, this is
+//! handled by:
+//!
+//! * the kernel driver toggling RTS (`KernelRs485`),
+//! * userspace toggling RTS or DTR around each transmission
+//!   (`ModemLineDirection`), for UARTs without RS485 support in their driver,
+//!   most notably USB-serial adapters and older 8250 ports,
+//! * userspace toggling a GPIO (`gpio::GpioDirection`).
+//!
+//! All of these implement `DirectionControl`; `DirectionMode` allows selecting
+//! one at runtime, e.g. from a configuration file.
+//!
+//! Timing of the userspace variants is less precise than with kernel support,
+//! as the process may be scheduled out between the end of a transmission and
+//! the line being released.
+
+use libc::{self, c_int};
+use std::{error, fmt, io};
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::*;
+use gpio::{GpioDirection, GpioLine};
+use sys;
+
+// upper bound for polling the line status register after `tcdrain`
+const TEMT_POLL_TIMEOUT: Duration = Duration::from_millis(100);
+const TEMT_POLL_INTERVAL: Duration = Duration::from_micros(50);
+
+/// Waits until the transmitter is completely empty
+///
+/// `tcdrain` only waits for the driver's buffers to empty, the UART's shift
+/// register may still hold the last character. If the driver supports it, the
+/// line status register is polled afterwards until the transmitter is empty.
+pub fn wait_transmitter_empty(fd: RawFd) -> io::Result<()> {
+    sys::tcdrain(fd)?;
+
+    let deadline = Instant::now() + TEMT_POLL_TIMEOUT;
+    while let Some(false) = sys::transmitter_empty(fd)? {
+        if Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(TEMT_POLL_INTERVAL);
+    }
+
+    Ok(())
+}
+
+/// Strategy for enabling the line driver around transmissions
+///
+/// `begin_transmit` is called before data is written to the serial port
+/// `fd`, `end_transmit` after all of it has been written.
+pub trait DirectionControl {
+    /// Enable the line driver
+    fn begin_transmit(&mut self, fd: RawFd) -> io::Result<()>;
+
+    /// Wait for the transmission to complete, then disable the line driver
+    fn end_transmit(&mut self, fd: RawFd) -> io::Result<()>;
+
+    /// Whether direction control happens outside of userspace
+    ///
+    /// If `true`, `begin_transmit` and `end_transmit` do nothing and writes
+    /// need not be wrapped by them.
+    fn is_hardware_managed(&self) -> bool;
+
+    /// Keep input received while transmitting
+    ///
+    /// Set while echo cancellation needs the echo; strategies that otherwise
+    /// discard such input must stop doing so.
+    fn set_keep_echo(&mut self, _keep: bool) {}
+}
+
+impl<D: DirectionControl + ?Sized> DirectionControl for Box<D> {
+    #[inline]
+    fn begin_transmit(&mut self, fd: RawFd) -> io::Result<()> {
+        (**self).begin_transmit(fd)
+    }
+
+    #[inline]
+    fn end_transmit(&mut self, fd: RawFd) -> io::Result<()> {
+        (**self).end_transmit(fd)
+    }
+
+    #[inline]
+    fn is_hardware_managed(&self) -> bool {
+        (**self).is_hardware_managed()
+    }
+
+    #[inline]
+    fn set_keep_echo(&mut self, keep: bool) {
+        (**self).set_keep_echo(keep)
+    }
+}
+
+/// Direction control by the kernel driver
+///
+/// Configured through `SerialRs485::set_on_fd`; nothing needs to be done on
+/// each transmission.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct KernelRs485;
+
+impl DirectionControl for KernelRs485 {
+    #[inline]
+    fn begin_transmit(&mut self, _fd: RawFd) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn end_transmit(&mut self, _fd: RawFd) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn is_hardware_managed(&self) -> bool {
+        true
+    }
+}
+
+/// Modem control line usable for direction control
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModemLine {
+    /// Request to send
+    Rts,
+    /// Data terminal ready
+    Dtr,
+}
+
+impl ModemLine {
+    #[inline]
+    fn bits(self) -> c_int {
+        match self {
+            ModemLine::Rts => libc::TIOCM_RTS,
+            ModemLine::Dtr => libc::TIOCM_DTR,
+        }
+    }
+}
+
+/// Direction control through RTS or DTR, performed in userspace
+///
+/// Follows the semantics of a `SerialRs485` configuration: the line is set to
+/// the `rts_on_send` level before, and to the `rts_after_send` level after a
+/// transmission, honoring `delay_rts_before_send` and `delay_rts_after_send`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ModemLineDirection {
+    line: ModemLine,
+    on_send: bool,
+    after_send: bool,
+    rx_during_tx: bool,
+    keep_echo: bool,
+    delay_before: Duration,
+    delay_after: Duration,
+}
+
+impl ModemLineDirection {
+    /// Create from RS485 configuration
+    #[inline]
+    pub fn new(line: ModemLine, conf: &SerialRs485) -> ModemLineDirection {
+        let flags = conf.flags();
+
+        ModemLineDirection {
+            line,
+            on_send: flags.contains(SER_RS485_RTS_ON_SEND),
+            after_send: flags.contains(SER_RS485_RTS_AFTER_SEND),
+            rx_during_tx: flags.contains(SER_RS485_RX_DURING_TX),
+            keep_echo: false,
+            delay_before: Duration::from_millis(conf.delay_rts_before_send() as u64),
+            delay_after: Duration::from_millis(conf.delay_rts_after_send() as u64),
+        }
+    }
+
+    /// Set the line to its idle (after send) level
+    #[inline]
+    pub fn idle(&self, fd: RawFd) -> io::Result<()> {
+        sys::set_modem_lines(fd, self.line.bits(), self.after_send)
+    }
+}
+
+impl DirectionControl for ModemLineDirection {
+    /// Sets the line to its `rts_on_send` level, then waits for
+    /// `delay_rts_before_send`
+    fn begin_transmit(&mut self, fd: RawFd) -> io::Result<()> {
+        sys::set_modem_lines(fd, self.line.bits(), self.on_send)?;
+
+        if self.delay_before > Duration::from_millis(0) {
+            thread::sleep(self.delay_before);
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the transmitter to drain and `delay_rts_after_send` to pass,
+    /// then returns the line to its idle level
+    ///
+    /// Unless receiving during transmission is enabled or the echo is kept
+    /// for echo cancellation, anything received in the meantime is discarded
+    /// before the line is returned.
+    fn end_transmit(&mut self, fd: RawFd) -> io::Result<()> {
+        let drained = wait_transmitter_empty(fd);
+
+        if drained.is_ok() && self.delay_after > Duration::from_millis(0) {
+            thread::sleep(self.delay_after);
+        }
+
+        // discard while the line is still held, a reply may follow as soon
+        // as it is released
+        let flushed = if drained.is_ok() && !self.rx_during_tx && !self.keep_echo {
+            sys::check(unsafe { libc::tcflush(fd, libc::TCIFLUSH) })
+        } else {
+            Ok(0)
+        };
+
+        // never leave the driver enabled, it would block the whole bus
+        self.idle(fd)?;
+        drained?;
+        flushed?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn is_hardware_managed(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn set_keep_echo(&mut self, keep: bool) {
+        self.keep_echo = keep;
+    }
+}
+
+/// Runtime selection of a direction control strategy
+///
+/// The text form is one of `auto`, `kernel`, `rts`, `dtr` or
+/// `gpio:<chip>:<offset>[:active_low]`, e.g. `gpio:/dev/gpiochip0:17`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DirectionMode {
+    /// Use the kernel driver if it supports RS485, otherwise toggle RTS
+    Auto,
+    /// Kernel driver
+    Kernel,
+    /// RTS toggled from userspace
+    Rts,
+    /// DTR toggled from userspace
+    Dtr,
+    /// GPIO line toggled from userspace
+    Gpio {
+        /// GPIO chip device
+        chip: PathBuf,
+        /// Line offset on the chip
+        offset: u32,
+        /// Whether the line is active low
+        active_low: bool,
+    },
+}
+
+impl DirectionMode {
+    /// Set up direction control on the serial port `fd`
+    ///
+    /// Applies `conf` to the kernel driver where required. For the userspace
+    /// modes, kernel RS485 support is not touched; only the delays and levels
+    /// from `conf` are used.
+    pub fn setup(&self,
+                 fd: RawFd,
+                 conf: &SerialRs485)
+                 -> io::Result<Box<dyn DirectionControl + Send>> {
+        Ok(match *self {
+            DirectionMode::Auto => {
+                match conf.set_on_fd(fd) {
+                    Ok(()) => Box::new(KernelRs485),
+                    Err(Rs485Error::NotSupported) => {
+                        if conf.flags().contains(SER_RS485_ENABLED) {
+                            let rts = ModemLineDirection::new(ModemLine::Rts, conf);
+                            rts.idle(fd)?;
+                            Box::new(rts)
+                        } else {
+                            Box::new(KernelRs485)
+                        }
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            DirectionMode::Kernel => {
+                conf.set_on_fd(fd)?;
+                Box::new(KernelRs485)
+            }
+            DirectionMode::Rts | DirectionMode::Dtr => {
+                let line = if *self == DirectionMode::Rts {
+                    ModemLine::Rts
+                } else {
+                    ModemLine::Dtr
+                };
+                let ctl = ModemLineDirection::new(line, conf);
+                ctl.idle(fd)?;
+                Box::new(ctl)
+            }
+            DirectionMode::Gpio { ref chip, offset, active_low } => {
+                let line = GpioLine::request(chip, offset, active_low)?;
+                Box::new(GpioDirection::new(line, conf)?)
+            }
+        })
+    }
+}
+
+impl fmt::Display for DirectionMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DirectionMode::Auto => write!(f, "auto"),
+            DirectionMode::Kernel => write!(f, "kernel"),
+            DirectionMode::Rts => write!(f, "rts"),
+            DirectionMode::Dtr => write!(f, "dtr"),
+            DirectionMode::Gpio { ref chip, offset, active_low } => {
+                write!(f, "gpio:{}:{}", chip.display(), offset)?;
+                if active_low {
+                    write!(f, ":active_low")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Error parsing a `DirectionMode`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseDirectionModeError(String);
+
+impl fmt::Display for ParseDirectionModeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid direction control mode `{}`", self.0)
+    }
+}
+
+impl error::Error for ParseDirectionModeError {}
+
+impl FromStr for DirectionMode {
+    type Err = ParseDirectionModeError;
+
+    fn from_str(s: &str) -> Result<DirectionMode, ParseDirectionModeError> {
+        let err = || ParseDirectionModeError(s.to_owned());
+
+        Ok(match s.trim() {
+            "auto" => DirectionMode::Auto,
+            "kernel" => DirectionMode::Kernel,
+            "rts" => DirectionMode::Rts,
+            "dtr" => DirectionMode::Dtr,
+            other if other.starts_with("gpio:") => {
+                let mut parts = other[5..].split(':');
+                let chip = parts.next().filter(|c| !c.is_empty()).ok_or_else(err)?;
+                let offset = parts.next()
+                    .and_then(|o| o.parse().ok())
+                    .ok_or_else(err)?;
+                let active_low = match parts.next() {
+                    None => false,
+                    Some("active_low") => true,
+                    Some(_) => return Err(err()),
+                };
+
+                if parts.next().is_some() {
+                    return Err(err());
+                }
+
+                DirectionMode::Gpio {
+                    chip: chip.into(),
+                    offset,
+                    active_low,
+                }
+            }
+            _ => return Err(err()),
+        })
+    }
+}
@@ -0,0 +1,63 @@
+//! DMX512 lighting control
+//!
+//! DMX512 transmits packets of up to 512 slots at 250 kbaud, 8N2. Every
+//! packet starts with a break, followed by a mark after break (MAB) and a
+//! start code slot identifying the kind of data that follows.
+//!
+//! RDM (`rdm`) uses packets with their own start code on the same line to
+//! discover and configure devices.
+
+use std::time::Duration;
+
+use port::{PortSettings, StopBits};
+use super::SerialRs485;
+
+pub mod rdm;
+pub mod receiver;
+pub mod transmitter;
+
+pub use self::receiver::{DmxReceiver, Packet, PacketError, ReceiverStats};
+pub use self::transmitter::{BreakMethod, DmxTransmitter};
+
+/// DMX512 baud rate
+pub const BAUD_RATE: u32 = 250_000;
+
+/// Maximum number of slots in a packet, excluding the start code
+pub const MAX_SLOTS: usize = 512;
+
+/// Start code of dimmer level packets
+pub const NULL_START_CODE: u8 = 0x00;
+
+/// Minimum break a transmitter must generate
+pub const MIN_BREAK: Duration = Duration::from_micros(92);
+
+/// Minimum mark after break a transmitter must generate
+pub const MIN_MARK_AFTER_BREAK: Duration = Duration::from_micros(12);
+
+/// Line settings for DMX512: 250 kbaud, 8 data bits, no parity, 2 stop bits
+pub fn line_settings() -> PortSettings {
+    let mut settings = PortSettings::new();
+    settings.set_baud_rate(BAUD_RATE).set_stop_bits(StopBits::Two);
+    settings
+}
+
+/// RS485 configuration for a DMX512 transmitter
+///
+/// RTS has the same level during and after sending, keeping the line driver
+/// enabled continuously. Breaks and the idle marking state between packets
+/// are part of the signal and must be driven as well.
+pub fn transmitter_rs485() -> SerialRs485 {
+    let mut conf = SerialRs485::new();
+    conf.set_enabled(true).set_rts_on_send(true).set_rts_after_send(true);
+    conf
+}
+
+/// RS485 configuration for a DMX512 receiver
+///
+/// RTS is only asserted while sending, which a receiver never does, keeping
+/// the line driver disabled.
+pub fn receiver_rs485() -> SerialRs485 {
+    let mut conf = SerialRs485::new();
+    conf.set_enabled(true).set_rts_on_send(true);
+    conf
+}
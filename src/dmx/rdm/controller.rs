@@ -0,0 +1,519 @@
+//! RDM controller
+
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use marker::{LineEvent, Unmarker};
+use port::Rs485Port;
+use super::super::{BreakMethod, DmxTransmitter, line_settings};
+use super::{ALL_SUB_DEVICES, DeviceInfo, Message, NackReason, RdmError, ROOT_DEVICE,
+            START_CODE, Uid, controller_rs485};
+use super::command_class::*;
+use super::pid::*;
+use super::response_type::*;
+
+// encoded discovery response: separator, 12 bytes UID and 4 bytes checksum
+const DISC_PREAMBLE: u8 = 0xfe;
+const DISC_SEPARATOR: u8 = 0xaa;
+const DISC_ENCODED_LEN: usize = 16;
+
+// safety limit for ACK_OVERFLOW responses
+const MAX_OVERFLOW_RESPONSES: usize = 64;
+
+/// Outcome of a `DISC_UNIQUE_BRANCH` request
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DiscoveryResponse {
+    /// No responder in the range
+    None,
+    /// A single responder answered
+    Found(Uid),
+    /// Several responders answered at the same time
+    Collision,
+}
+
+/// RDM controller
+///
+/// Sends RDM requests on a DMX512 line and waits for the responses. DMX512
+/// packets can be sent in between through `dmx`.
+#[derive(Debug)]
+pub struct RdmController {
+    dmx: DmxTransmitter,
+    uid: Uid,
+    transaction: u8,
+    response_timeout: Duration,
+    idle_timeout: Duration,
+}
+
+impl RdmController {
+    /// Open a serial device as RDM controller with UID `uid`
+    ///
+    /// The line driver is only enabled while sending, see
+    /// `controller_rs485`.
+    pub fn open<P: AsRef<Path>>(path: P, uid: Uid) -> io::Result<RdmController> {
+        let port = Rs485Port::open(path, &line_settings(), &controller_rs485())?;
+        RdmController::new(port, uid)
+    }
+
+    /// Create a controller on an already opened port
+    ///
+    /// Breaks are generated by baud switching, as a break requested through
+    /// `TIOCSBRK` does not enable the line driver. Defaults to a response
+    /// timeout of 20 ms, which is generous compared to the 2.8 ms of the
+    /// specification, to accommodate USB adapters.
+    pub fn new(port: Rs485Port, uid: Uid) -> io::Result<RdmController> {
+        let mut dmx = DmxTransmitter::new(port)?;
+        dmx.set_break_method(BreakMethod::BaudSwitch);
+        dmx.port().set_mark_errors(true)?;
+
+        Ok(RdmController {
+            dmx,
+            uid,
+            transaction: 0,
+            response_timeout: Duration::from_millis(20),
+            idle_timeout: Duration::from_millis(5),
+        })
+    }
+
+    /// Set response timeout
+    ///
+    /// Maximum time from the end of a request to the first byte of the
+    /// response.
+    #[inline]
+    pub fn set_response_timeout(&mut self, response_timeout: Duration) -> &mut Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    /// Set idle timeout
+    ///
+    /// A response is considered complete once the line has been silent for
+    /// this long.
+    #[inline]
+    pub fn set_idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Controller UID
+    #[inline]
+    pub fn uid(&self) -> Uid {
+        self.uid
+    }
+
+    /// DMX512 transmitter sharing the line
+    #[inline]
+    pub fn dmx(&mut self) -> &mut DmxTransmitter {
+        &mut self.dmx
+    }
+
+    /// Release the DMX512 transmitter
+    #[inline]
+    pub fn into_dmx(self) -> DmxTransmitter {
+        self.dmx
+    }
+
+    /// Find all responders on the line
+    ///
+    /// Un-mutes all responders, then searches the UID space by binary
+    /// search, muting each responder found. Ranges with collisions are split
+    /// until a single responder answers.
+    pub fn discover(&mut self) -> Result<Vec<Uid>, RdmError> {
+        self.unmute(Uid::BROADCAST)?;
+
+        let mut found = Vec::new();
+        let mut ranges = vec![(0, Uid::MAX.to_u64())];
+
+        while let Some((lower, upper)) = ranges.pop() {
+            let split = match self.disc_unique_branch(Uid::from_u64(lower), Uid::from_u64(upper))? {
+                DiscoveryResponse::None => false,
+                DiscoveryResponse::Found(uid) => {
+                    let in_range = (lower..=upper).contains(&uid.to_u64());
+
+                    if found.contains(&uid) {
+                        // responder ignores being muted, narrow down around it
+                        true
+                    } else if in_range && self.confirm(uid)? {
+                        found.push(uid);
+                        // look for further responders in the same range
+                        ranges.push((lower, upper));
+                        false
+                    } else {
+                        true
+                    }
+                }
+                DiscoveryResponse::Collision => true,
+            };
+
+            if split && lower < upper {
+                let mid = lower + (upper - lower) / 2;
+                ranges.push((mid + 1, upper));
+                ranges.push((lower, mid));
+            }
+        }
+
+        found.sort();
+        Ok(found)
+    }
+
+    /// Ask all un-muted responders with a UID in `lower..=upper` to answer
+    pub fn disc_unique_branch(&mut self,
+                              lower: Uid,
+                              upper: Uid)
+                              -> Result<DiscoveryResponse, RdmError> {
+        let mut data = lower.to_bytes().to_vec();
+        data.extend_from_slice(&upper.to_bytes());
+
+        let request = self.message(Uid::BROADCAST,
+                                   ROOT_DEVICE,
+                                   DISCOVERY_COMMAND,
+                                   DISC_UNIQUE_BRANCH,
+                                   data);
+        self.send(&request)?;
+
+        let events = self.read_events()?;
+        if events.is_empty() {
+            return Ok(DiscoveryResponse::None);
+        }
+
+        Ok(decode_discovery_response(&events).map_or(DiscoveryResponse::Collision,
+                                                     DiscoveryResponse::Found))
+    }
+
+    /// Stop a responder from answering discovery requests
+    ///
+    /// Broadcasts are not answered.
+    #[inline]
+    pub fn mute(&mut self, uid: Uid) -> Result<(), RdmError> {
+        self.discovery_mute(uid, DISC_MUTE)
+    }
+
+    /// Let a responder answer discovery requests again
+    ///
+    /// Broadcasts are not answered.
+    #[inline]
+    pub fn unmute(&mut self, uid: Uid) -> Result<(), RdmError> {
+        self.discovery_mute(uid, DISC_UN_MUTE)
+    }
+
+    /// Send a request and wait for the response
+    ///
+    /// Returns the parameter data of the response, collecting all parts of
+    /// `ACK_OVERFLOW` responses. Requests to broadcast UIDs are not answered
+    /// and return no data.
+    pub fn request(&mut self,
+                   destination: Uid,
+                   sub_device: u16,
+                   command_class: u8,
+                   pid: u16,
+                   data: &[u8])
+                   -> Result<Vec<u8>, RdmError> {
+        let mut collected = Vec::new();
+
+        for _ in 0..MAX_OVERFLOW_RESPONSES {
+            let request = self.message(destination, sub_device, command_class, pid, data.to_vec());
+            let response = match self.transact(&request)? {
+                Some(response) => response,
+                None => return Ok(collected),
+            };
+
+            match response.port_id {
+                ACK => {
+                    collected.extend_from_slice(&response.data);
+                    return Ok(collected);
+                }
+                ACK_OVERFLOW => collected.extend_from_slice(&response.data),
+                ACK_TIMER => {
+                    let units = be_u16(&response.data)?;
+                    return Err(RdmError::AckTimer(Duration::from_millis(units as u64 * 100)));
+                }
+                NACK_REASON => {
+                    return Err(RdmError::Nack(NackReason::from(be_u16(&response.data)?)));
+                }
+                _ => return Err(RdmError::InvalidResponse("unknown response type")),
+            }
+        }
+
+        Err(RdmError::InvalidResponse("too many overflow responses"))
+    }
+
+    /// Get a parameter
+    #[inline]
+    pub fn get(&mut self,
+               destination: Uid,
+               sub_device: u16,
+               pid: u16,
+               data: &[u8])
+               -> Result<Vec<u8>, RdmError> {
+        self.request(destination, sub_device, GET_COMMAND, pid, data)
+    }
+
+    /// Set a parameter
+    #[inline]
+    pub fn set(&mut self,
+               destination: Uid,
+               sub_device: u16,
+               pid: u16,
+               data: &[u8])
+               -> Result<Vec<u8>, RdmError> {
+        self.request(destination, sub_device, SET_COMMAND, pid, data)
+    }
+
+    /// Read device information of the root device
+    pub fn device_info(&mut self, uid: Uid) -> Result<DeviceInfo, RdmError> {
+        DeviceInfo::decode(&self.get(uid, ROOT_DEVICE, DEVICE_INFO, &[])?)
+    }
+
+    /// Read the DMX512 start address of the root device
+    pub fn dmx_start_address(&mut self, uid: Uid) -> Result<u16, RdmError> {
+        be_u16(&self.get(uid, ROOT_DEVICE, DMX_START_ADDRESS, &[])?)
+    }
+
+    /// Set the DMX512 start address of the root device and all sub-devices
+    ///
+    /// Valid addresses are 1 to 512.
+    pub fn set_dmx_start_address(&mut self, uid: Uid, address: u16) -> Result<(), RdmError> {
+        if !(1..=512).contains(&address) {
+            return Err(RdmError::InvalidRequest("DMX start address out of range"));
+        }
+
+        self.set(uid, ALL_SUB_DEVICES, DMX_START_ADDRESS, &address.to_be_bytes()).map(|_| ())
+    }
+
+    /// Whether the root device is identifying itself
+    pub fn identify_device(&mut self, uid: Uid) -> Result<bool, RdmError> {
+        match self.get(uid, ROOT_DEVICE, IDENTIFY_DEVICE, &[])?[..] {
+            [state] => Ok(state != 0),
+            _ => Err(RdmError::InvalidResponse("IDENTIFY_DEVICE has wrong length")),
+        }
+    }
+
+    /// Start or stop physical identification of the root device
+    pub fn set_identify_device(&mut self, uid: Uid, on: bool) -> Result<(), RdmError> {
+        self.set(uid, ROOT_DEVICE, IDENTIFY_DEVICE, &[on as u8]).map(|_| ())
+    }
+
+    /// Mute a UID found by discovery
+    ///
+    /// A mute acknowledged by the device confirms the UID was not the product
+    /// of a collision. No or a corrupt acknowledgement means it was not
+    /// confirmed; other errors are returned.
+    fn confirm(&mut self, uid: Uid) -> Result<bool, RdmError> {
+        match self.mute(uid) {
+            Ok(()) => Ok(true),
+            Err(RdmError::Timeout) | Err(RdmError::Checksum) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Send `DISC_MUTE` or `DISC_UN_MUTE`
+    fn discovery_mute(&mut self, uid: Uid, pid: u16) -> Result<(), RdmError> {
+        let request = self.message(uid, ROOT_DEVICE, DISCOVERY_COMMAND, pid, Vec::new());
+
+        match self.transact(&request)? {
+            // the control field is informational only
+            Some(ref response) if response.port_id == ACK && response.data.len() >= 2 => Ok(()),
+            Some(_) => Err(RdmError::InvalidResponse("malformed mute response")),
+            None => Ok(()),
+        }
+    }
+
+    /// Build a request from this controller
+    fn message(&mut self,
+               destination: Uid,
+               sub_device: u16,
+               command_class: u8,
+               pid: u16,
+               data: Vec<u8>)
+               -> Message {
+        self.transaction = self.transaction.wrapping_add(1);
+
+        Message {
+            destination,
+            source: self.uid,
+            transaction: self.transaction,
+            port_id: 1,
+            message_count: 0,
+            sub_device,
+            command_class,
+            pid,
+            data,
+        }
+    }
+
+    /// Send a request
+    ///
+    /// Stale input is discarded beforehand; afterwards there is no time to,
+    /// as the response may follow within 176 µs.
+    fn send(&mut self, request: &Message) -> Result<(), RdmError> {
+        let packet = request.encode()?;
+        self.dmx.port().discard_input()?;
+        self.dmx.send_raw(&packet)?;
+        Ok(())
+    }
+
+    /// Send a request and receive the response
+    ///
+    /// Returns `None` for broadcasts, which are never answered.
+    fn transact(&mut self, request: &Message) -> Result<Option<Message>, RdmError> {
+        self.send(request)?;
+
+        if request.destination.is_broadcast() {
+            return Ok(None);
+        }
+
+        let events = self.read_events()?;
+        if events.is_empty() {
+            return Err(RdmError::Timeout);
+        }
+
+        // the response runs from its start code to the next break; the break
+        // itself may have been missed while the line driver was released
+        let start = match events.iter().position(|&e| e == LineEvent::Data(START_CODE)) {
+            Some(start) => start,
+            None => return Err(RdmError::InvalidResponse("no RDM start code")),
+        };
+
+        let mut packet = Vec::new();
+        for event in &events[start..] {
+            match *event {
+                LineEvent::Data(byte) => packet.push(byte),
+                LineEvent::Error(_) => return Err(RdmError::InvalidResponse("framing error")),
+                LineEvent::Break => break,
+            }
+        }
+
+        let response = Message::decode(&packet)?;
+        if !response.is_response_to(request) {
+            return Err(RdmError::InvalidResponse("response does not match request"));
+        }
+
+        Ok(Some(response))
+    }
+
+    /// Receive until the line falls silent
+    fn read_events(&mut self) -> io::Result<Vec<LineEvent>> {
+        let mut events = Vec::new();
+        let mut unmarker = Unmarker::default();
+        let mut buf = [0u8; 2 * 512];
+
+        let port = self.dmx.port();
+        let mut timeout = self.response_timeout;
+        let deadline = Instant::now() + self.response_timeout + Duration::from_millis(100);
+
+        loop {
+            let n = port.read_timeout(&mut buf, timeout)?;
+            if n == 0 {
+                break;
+            }
+
+            for &byte in &buf[..n] {
+                unmarker.feed(byte, |event| events.push(event));
+            }
+
+            // a responder jamming the line must not block us forever
+            if Instant::now() >= deadline {
+                break;
+            }
+            timeout = self.idle_timeout;
+        }
+
+        Ok(events)
+    }
+}
+
+/// Decode the response to `DISC_UNIQUE_BRANCH`
+///
+/// Returns `None` if the response is corrupt, e.g. due to a collision.
+fn decode_discovery_response(events: &[LineEvent]) -> Option<Uid> {
+    let mut data = Vec::with_capacity(events.len());
+    for event in events {
+        match *event {
+            LineEvent::Data(byte) => data.push(byte),
+            _ => return None,
+        }
+    }
+
+    let preamble = data.iter().take_while(|&&b| b == DISC_PREAMBLE).count();
+    if preamble > 7 || data.get(preamble) != Some(&DISC_SEPARATOR) {
+        return None;
+    }
+
+    let encoded = data.get(preamble + 1..preamble + 1 + DISC_ENCODED_LEN)?;
+
+    // every byte is sent twice, OR-ed with 0xaa and 0x55
+    let mut decoded = [0u8; DISC_ENCODED_LEN / 2];
+    for (i, pair) in encoded.chunks(2).enumerate() {
+        if pair[0] & 0xaa != 0xaa || pair[1] & 0x55 != 0x55 {
+            return None;
+        }
+        decoded[i] = pair[0] & pair[1];
+    }
+
+    let sum = super::checksum(&encoded[..12]);
+    if u16::from_be_bytes([decoded[6], decoded[7]]) != sum {
+        return None;
+    }
+
+    let mut uid = [0u8; 6];
+    uid.copy_from_slice(&decoded[..6]);
+    Some(Uid::from_bytes(uid))
+}
+
+/// Parse a big-endian 16 bit parameter
+fn be_u16(data: &[u8]) -> Result<u16, RdmError> {
+    match *data {
+        [hi, lo] => Ok(u16::from_be_bytes([hi, lo])),
+        _ => Err(RdmError::InvalidResponse("expected 16 bit parameter")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // response of 1234:56789abc to DISC_UNIQUE_BRANCH, E1.20 section 7.5
+    const DISC_RESPONSE: &[u8] = &[0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xaa, 0xba, 0x57,
+                                   0xbe, 0x75, 0xfe, 0x57, 0xfa, 0x7d, 0xba, 0xdf, 0xbe, 0xfd,
+                                   0xaa, 0x5d, 0xee, 0x75];
+
+    fn events(data: &[u8]) -> Vec<LineEvent> {
+        data.iter().map(|&b| LineEvent::Data(b)).collect()
+    }
+
+    #[test]
+    fn discovery_response() {
+        let uid = Some(Uid::new(0x1234, 0x5678_9abc));
+        assert_eq!(decode_discovery_response(&events(DISC_RESPONSE)), uid);
+
+        // the preamble may be shortened or left out
+        assert_eq!(decode_discovery_response(&events(&DISC_RESPONSE[7..])), uid);
+        assert_eq!(decode_discovery_response(&events(&DISC_RESPONSE[3..])), uid);
+    }
+
+    #[test]
+    fn discovery_collision() {
+        // two responders answering at once corrupt the encoding or checksum
+        let mut data = DISC_RESPONSE.to_vec();
+        data[9] &= 0xfe;
+        assert_eq!(decode_discovery_response(&events(&data)), None);
+
+        let mut data = DISC_RESPONSE.to_vec();
+        data[8] |= 0x01;
+        data[9] |= 0x01;
+        assert_eq!(decode_discovery_response(&events(&data)), None);
+
+        let mut events = events(DISC_RESPONSE);
+        events[12] = LineEvent::Error(0xfe);
+        assert_eq!(decode_discovery_response(&events), None);
+    }
+
+    #[test]
+    fn discovery_malformed() {
+        let mut data = vec![0xfe; 8];
+        data.extend_from_slice(&DISC_RESPONSE[7..]);
+        assert_eq!(decode_discovery_response(&events(&data)), None);
+        assert_eq!(decode_discovery_response(&events(&DISC_RESPONSE[..23])), None);
+        assert_eq!(decode_discovery_response(&[]), None);
+    }
+}
@@ -0,0 +1,640 @@
+//! Remote Device Management (ANSI E1.20)
+//!
+//! RDM shares the DMX512 line, using packets with the `START_CODE` start
+//! code. Unlike DMX512, it is half-duplex: responders answer requests from
+//! the controller, which requires the controller's line driver to be
+//! disabled right after each request (see `controller_rs485`).
+
+use std::{error, fmt, io};
+use std::str::FromStr;
+
+use SerialRs485;
+
+pub mod controller;
+
+pub use self::controller::{DiscoveryResponse, RdmController};
+
+/// RDM start code
+pub const START_CODE: u8 = 0xcc;
+
+/// RDM sub-start code
+pub const SUB_START_CODE: u8 = 0x01;
+
+/// Size of a message without parameter data and checksum
+pub const HEADER_LEN: usize = 24;
+
+/// Maximum size of the parameter data
+pub const MAX_PARAMETER_DATA_LEN: usize = 231;
+
+/// Sub-device number of the root device
+pub const ROOT_DEVICE: u16 = 0x0000;
+
+/// Sub-device number addressing all sub-devices
+pub const ALL_SUB_DEVICES: u16 = 0xffff;
+
+/// Command classes
+pub mod command_class {
+    /// Discovery command
+    pub const DISCOVERY_COMMAND: u8 = 0x10;
+    /// Response to a discovery command
+    pub const DISCOVERY_COMMAND_RESPONSE: u8 = 0x11;
+    /// Get command
+    pub const GET_COMMAND: u8 = 0x20;
+    /// Response to a get command
+    pub const GET_COMMAND_RESPONSE: u8 = 0x21;
+    /// Set command
+    pub const SET_COMMAND: u8 = 0x30;
+    /// Response to a set command
+    pub const SET_COMMAND_RESPONSE: u8 = 0x31;
+}
+
+/// Response types, sent in place of the port id in responses
+pub mod response_type {
+    /// Request executed
+    pub const ACK: u8 = 0x00;
+    /// Request will be executed later
+    pub const ACK_TIMER: u8 = 0x01;
+    /// Request refused
+    pub const NACK_REASON: u8 = 0x02;
+    /// Response incomplete, repeat the request for more data
+    pub const ACK_OVERFLOW: u8 = 0x03;
+}
+
+/// Parameter ids
+pub mod pid {
+    /// Find responders within a UID range
+    pub const DISC_UNIQUE_BRANCH: u16 = 0x0001;
+    /// Stop a responder from answering discovery
+    pub const DISC_MUTE: u16 = 0x0002;
+    /// Let a responder answer discovery again
+    pub const DISC_UN_MUTE: u16 = 0x0003;
+    /// Device information
+    pub const DEVICE_INFO: u16 = 0x0060;
+    /// DMX512 start address
+    pub const DMX_START_ADDRESS: u16 = 0x00f0;
+    /// Physical identification, e.g. by flashing
+    pub const IDENTIFY_DEVICE: u16 = 0x1000;
+}
+
+use self::command_class::*;
+
+/// Unique id of an RDM device
+///
+/// Consists of an ESTA manufacturer id and a device id, written as
+/// `mmmm:dddddddd` in hex.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uid {
+    manufacturer: u16,
+    device: u32,
+}
+
+impl Uid {
+    /// UID addressing all devices
+    pub const BROADCAST: Uid = Uid {
+        manufacturer: 0xffff,
+        device: 0xffff_ffff,
+    };
+
+    /// Highest UID a device may have
+    pub const MAX: Uid = Uid {
+        manufacturer: 0xffff,
+        device: 0xffff_fffe,
+    };
+
+    /// Create a UID
+    #[inline]
+    pub const fn new(manufacturer: u16, device: u32) -> Uid {
+        Uid {
+            manufacturer,
+            device,
+        }
+    }
+
+    /// UID addressing all devices of a manufacturer
+    #[inline]
+    pub const fn manufacturer_broadcast(manufacturer: u16) -> Uid {
+        Uid::new(manufacturer, 0xffff_ffff)
+    }
+
+    /// Create a UID from its 48 bit integer value
+    #[inline]
+    pub fn from_u64(value: u64) -> Uid {
+        Uid::new((value >> 32) as u16, value as u32)
+    }
+
+    /// 48 bit integer value
+    #[inline]
+    pub fn to_u64(self) -> u64 {
+        (self.manufacturer as u64) << 32 | self.device as u64
+    }
+
+    /// Create a UID from its wire representation
+    #[inline]
+    pub fn from_bytes(bytes: [u8; 6]) -> Uid {
+        Uid::new(u16::from_be_bytes([bytes[0], bytes[1]]),
+                 u32::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]))
+    }
+
+    /// Wire representation
+    #[inline]
+    pub fn to_bytes(self) -> [u8; 6] {
+        let m = self.manufacturer.to_be_bytes();
+        let d = self.device.to_be_bytes();
+        [m[0], m[1], d[0], d[1], d[2], d[3]]
+    }
+
+    /// Manufacturer id
+    #[inline]
+    pub fn manufacturer(self) -> u16 {
+        self.manufacturer
+    }
+
+    /// Device id
+    #[inline]
+    pub fn device(self) -> u32 {
+        self.device
+    }
+
+    /// Whether this UID addresses all devices, or all of a manufacturer
+    #[inline]
+    pub fn is_broadcast(self) -> bool {
+        self.device == 0xffff_ffff
+    }
+}
+
+impl fmt::Display for Uid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:08x}", self.manufacturer, self.device)
+    }
+}
+
+/// Error parsing a UID
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseUidError(String);
+
+impl fmt::Display for ParseUidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid RDM UID {:?}, expected mmmm:dddddddd", self.0)
+    }
+}
+
+impl error::Error for ParseUidError {}
+
+impl FromStr for Uid {
+    type Err = ParseUidError;
+
+    fn from_str(s: &str) -> Result<Uid, ParseUidError> {
+        let err = || ParseUidError(s.to_owned());
+        let mut parts = s.splitn(2, ':');
+
+        let manufacturer = parts.next().ok_or_else(err)?;
+        let device = parts.next().ok_or_else(err)?;
+
+        Ok(Uid::new(u16::from_str_radix(manufacturer, 16).map_err(|_| err())?,
+                    u32::from_str_radix(device, 16).map_err(|_| err())?))
+    }
+}
+
+/// Reason given by a responder for refusing a request
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NackReason {
+    /// Parameter id not implemented (0x0000)
+    UnknownPid,
+    /// Malformed parameter data (0x0001)
+    FormatError,
+    /// Hardware fault (0x0002)
+    HardwareFault,
+    /// Proxy rejected the request (0x0003)
+    ProxyReject,
+    /// Parameter is write protected (0x0004)
+    WriteProtect,
+    /// Command class not supported for this parameter (0x0005)
+    UnsupportedCommandClass,
+    /// Value out of range (0x0006)
+    DataOutOfRange,
+    /// Responder buffer full (0x0007)
+    BufferFull,
+    /// Message too large (0x0008)
+    PacketSizeUnsupported,
+    /// Sub-device does not exist (0x0009)
+    SubDeviceOutOfRange,
+    /// Proxy buffer full (0x000A)
+    ProxyBufferFull,
+    /// Any other reason code
+    Other(u16),
+}
+
+impl NackReason {
+    /// Reason code value
+    pub fn code(self) -> u16 {
+        match self {
+            NackReason::UnknownPid => 0x0000,
+            NackReason::FormatError => 0x0001,
+            NackReason::HardwareFault => 0x0002,
+            NackReason::ProxyReject => 0x0003,
+            NackReason::WriteProtect => 0x0004,
+            NackReason::UnsupportedCommandClass => 0x0005,
+            NackReason::DataOutOfRange => 0x0006,
+            NackReason::BufferFull => 0x0007,
+            NackReason::PacketSizeUnsupported => 0x0008,
+            NackReason::SubDeviceOutOfRange => 0x0009,
+            NackReason::ProxyBufferFull => 0x000a,
+            NackReason::Other(code) => code,
+        }
+    }
+}
+
+impl From<u16> for NackReason {
+    fn from(code: u16) -> NackReason {
+        match code {
+            0x0000 => NackReason::UnknownPid,
+            0x0001 => NackReason::FormatError,
+            0x0002 => NackReason::HardwareFault,
+            0x0003 => NackReason::ProxyReject,
+            0x0004 => NackReason::WriteProtect,
+            0x0005 => NackReason::UnsupportedCommandClass,
+            0x0006 => NackReason::DataOutOfRange,
+            0x0007 => NackReason::BufferFull,
+            0x0008 => NackReason::PacketSizeUnsupported,
+            0x0009 => NackReason::SubDeviceOutOfRange,
+            0x000a => NackReason::ProxyBufferFull,
+            code => NackReason::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for NackReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = match *self {
+            NackReason::UnknownPid => "unknown PID",
+            NackReason::FormatError => "format error",
+            NackReason::HardwareFault => "hardware fault",
+            NackReason::ProxyReject => "proxy reject",
+            NackReason::WriteProtect => "write protect",
+            NackReason::UnsupportedCommandClass => "unsupported command class",
+            NackReason::DataOutOfRange => "data out of range",
+            NackReason::BufferFull => "buffer full",
+            NackReason::PacketSizeUnsupported => "packet size unsupported",
+            NackReason::SubDeviceOutOfRange => "sub-device out of range",
+            NackReason::ProxyBufferFull => "proxy buffer full",
+            NackReason::Other(_) => "unknown reason",
+        };
+
+        write!(f, "{} (0x{:04x})", desc, self.code())
+    }
+}
+
+/// Error during an RDM transaction
+#[derive(Debug)]
+pub enum RdmError {
+    /// Error on the underlying port
+    Io(io::Error),
+    /// No response was received in time
+    Timeout,
+    /// A response with an invalid checksum was received
+    Checksum,
+    /// The responder refused the request
+    Nack(NackReason),
+    /// The responder will process the request later; retry after the
+    /// given time
+    AckTimer(::std::time::Duration),
+    /// The request cannot be encoded, e.g. because its data is too long
+    InvalidRequest(&'static str),
+    /// The response was malformed or did not match the request
+    InvalidResponse(&'static str),
+}
+
+impl fmt::Display for RdmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RdmError::Io(ref e) => write!(f, "I/O error: {}", e),
+            RdmError::Timeout => write!(f, "timeout waiting for response"),
+            RdmError::Checksum => write!(f, "checksum mismatch"),
+            RdmError::Nack(reason) => write!(f, "request refused: {}", reason),
+            RdmError::AckTimer(delay) => write!(f, "response delayed by {:?}", delay),
+            RdmError::InvalidRequest(reason) => write!(f, "invalid request: {}", reason),
+            RdmError::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+        }
+    }
+}
+
+impl error::Error for RdmError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            RdmError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RdmError {
+    #[inline]
+    fn from(e: io::Error) -> RdmError {
+        RdmError::Io(e)
+    }
+}
+
+/// RDM message
+///
+/// Requests and responses share the same layout; in responses, `port_id`
+/// holds the response type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// Destination UID
+    pub destination: Uid,
+    /// Source UID
+    pub source: Uid,
+    /// Transaction number, echoed by the responder
+    pub transaction: u8,
+    /// Port id (requests) or response type (responses)
+    pub port_id: u8,
+    /// Number of queued messages at the responder
+    pub message_count: u8,
+    /// Sub-device, `ROOT_DEVICE` for the device itself
+    pub sub_device: u16,
+    /// Command class
+    pub command_class: u8,
+    /// Parameter id
+    pub pid: u16,
+    /// Parameter data
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    /// Encode into a packet, including start code and checksum
+    pub fn encode(&self) -> Result<Vec<u8>, RdmError> {
+        if self.data.len() > MAX_PARAMETER_DATA_LEN {
+            return Err(RdmError::InvalidRequest("parameter data too long"));
+        }
+
+        let mut packet = Vec::with_capacity(HEADER_LEN + self.data.len() + 2);
+        packet.push(START_CODE);
+        packet.push(SUB_START_CODE);
+        packet.push((HEADER_LEN + self.data.len()) as u8);
+        packet.extend_from_slice(&self.destination.to_bytes());
+        packet.extend_from_slice(&self.source.to_bytes());
+        packet.push(self.transaction);
+        packet.push(self.port_id);
+        packet.push(self.message_count);
+        packet.extend_from_slice(&self.sub_device.to_be_bytes());
+        packet.push(self.command_class);
+        packet.extend_from_slice(&self.pid.to_be_bytes());
+        packet.push(self.data.len() as u8);
+        packet.extend_from_slice(&self.data);
+
+        let sum = checksum(&packet);
+        packet.extend_from_slice(&sum.to_be_bytes());
+
+        Ok(packet)
+    }
+
+    /// Decode a packet, starting with the start code
+    ///
+    /// Checks start codes, lengths and checksum. Trailing data after the
+    /// checksum is ignored.
+    pub fn decode(packet: &[u8]) -> Result<Message, RdmError> {
+        let invalid = RdmError::InvalidResponse;
+
+        if packet.len() < HEADER_LEN + 2 {
+            return Err(invalid("message too short"));
+        }
+        if packet[0] != START_CODE || packet[1] != SUB_START_CODE {
+            return Err(invalid("not an RDM message"));
+        }
+
+        let len = packet[2] as usize;
+        if len < HEADER_LEN || packet[23] as usize != len - HEADER_LEN {
+            return Err(invalid("inconsistent message length"));
+        }
+        if packet.len() < len + 2 {
+            return Err(invalid("message truncated"));
+        }
+
+        let sum = u16::from_be_bytes([packet[len], packet[len + 1]]);
+        if checksum(&packet[..len]) != sum {
+            return Err(RdmError::Checksum);
+        }
+
+        let uid = |at: usize| {
+            let mut bytes = [0u8; 6];
+            bytes.copy_from_slice(&packet[at..at + 6]);
+            Uid::from_bytes(bytes)
+        };
+
+        Ok(Message {
+            destination: uid(3),
+            source: uid(9),
+            transaction: packet[15],
+            port_id: packet[16],
+            message_count: packet[17],
+            sub_device: u16::from_be_bytes([packet[18], packet[19]]),
+            command_class: packet[20],
+            pid: u16::from_be_bytes([packet[21], packet[22]]),
+            data: packet[HEADER_LEN..len].to_vec(),
+        })
+    }
+
+    /// Whether this is a response to `request`
+    pub fn is_response_to(&self, request: &Message) -> bool {
+        let response_class = match request.command_class {
+            DISCOVERY_COMMAND => DISCOVERY_COMMAND_RESPONSE,
+            GET_COMMAND => GET_COMMAND_RESPONSE,
+            SET_COMMAND => SET_COMMAND_RESPONSE,
+            _ => return false,
+        };
+
+        self.command_class == response_class && self.pid == request.pid &&
+        self.transaction == request.transaction && self.destination == request.source &&
+        self.source == request.destination
+    }
+
+    /// Whether this is a discovery, get or set response
+    #[inline]
+    pub fn is_response(&self) -> bool {
+        matches!(self.command_class,
+                 DISCOVERY_COMMAND_RESPONSE | GET_COMMAND_RESPONSE | SET_COMMAND_RESPONSE)
+    }
+}
+
+/// RDM checksum: sum of all bytes, modulo 2^16
+pub fn checksum(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |acc, &b| acc.wrapping_add(b as u16))
+}
+
+/// Contents of a `DEVICE_INFO` response
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// RDM protocol version, 0x0100 for E1.20
+    pub protocol_version: u16,
+    /// Manufacturer specific model id
+    pub model_id: u16,
+    /// Product category
+    pub product_category: u16,
+    /// Manufacturer specific software version
+    pub software_version: u32,
+    /// Number of DMX512 slots used
+    pub footprint: u16,
+    /// Current personality
+    pub personality: u8,
+    /// Number of personalities
+    pub personality_count: u8,
+    /// DMX512 start address, 0xFFFF if the footprint is zero
+    pub start_address: u16,
+    /// Number of sub-devices
+    pub sub_device_count: u16,
+    /// Number of sensors
+    pub sensor_count: u8,
+}
+
+impl DeviceInfo {
+    /// Size of the parameter data
+    pub const LEN: usize = 19;
+
+    /// Decode from parameter data
+    pub fn decode(data: &[u8]) -> Result<DeviceInfo, RdmError> {
+        if data.len() != DeviceInfo::LEN {
+            return Err(RdmError::InvalidResponse("DEVICE_INFO has wrong length"));
+        }
+
+        let u16_at = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+
+        Ok(DeviceInfo {
+            protocol_version: u16_at(0),
+            model_id: u16_at(2),
+            product_category: u16_at(4),
+            software_version: u32::from_be_bytes([data[6], data[7], data[8], data[9]]),
+            footprint: u16_at(10),
+            personality: data[12],
+            personality_count: data[13],
+            start_address: u16_at(14),
+            sub_device_count: u16_at(16),
+            sensor_count: data[18],
+        })
+    }
+}
+
+/// RS485 configuration for an RDM controller
+///
+/// RTS is asserted only while sending, with no delay afterwards: responders
+/// may start answering 176 µs after the end of a request, so the line driver
+/// has to be released immediately. Between packets, the line is held in the
+/// marking state by the bias network RDM requires at the controller.
+pub fn controller_rs485() -> SerialRs485 {
+    let mut conf = SerialRs485::new();
+    conf.set_enabled(true).set_rts_on_send(true);
+    conf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // GET DEVICE_INFO from cba9:87654321 to 1234:56789abc, E1.20 section 6
+    const GET_DEVICE_INFO: &[u8] = &[0xcc, 0x01, 0x18, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc,
+                                     0xcb, 0xa9, 0x87, 0x65, 0x43, 0x21, 0x00, 0x01, 0x00,
+                                     0x00, 0x00, 0x20, 0x00, 0x60, 0x00, 0x06, 0x94];
+
+    fn get_device_info() -> Message {
+        Message {
+            destination: Uid::new(0x1234, 0x5678_9abc),
+            source: Uid::new(0xcba9, 0x8765_4321),
+            transaction: 0,
+            port_id: 1,
+            message_count: 0,
+            sub_device: ROOT_DEVICE,
+            command_class: GET_COMMAND,
+            pid: pid::DEVICE_INFO,
+            data: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn packet_checksum() {
+        assert_eq!(checksum(&GET_DEVICE_INFO[..24]), 0x0694);
+        assert_eq!(checksum(&[0xff; 258]), 0x00fe);
+    }
+
+    #[test]
+    fn encode_decode() {
+        let request = get_device_info();
+        assert_eq!(request.encode().unwrap(), GET_DEVICE_INFO);
+        assert_eq!(Message::decode(GET_DEVICE_INFO).unwrap(), request);
+
+        let response = Message {
+            destination: request.source,
+            source: request.destination,
+            port_id: response_type::ACK,
+            command_class: GET_COMMAND_RESPONSE,
+            data: vec![0x01, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x04,
+                       0x01, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00],
+            ..request.clone()
+        };
+        let packet = response.encode().unwrap();
+        assert_eq!(packet.len(), HEADER_LEN + DeviceInfo::LEN + 2);
+        assert_eq!(packet[2] as usize, HEADER_LEN + DeviceInfo::LEN);
+
+        let decoded = Message::decode(&packet).unwrap();
+        assert_eq!(decoded, response);
+        assert!(decoded.is_response());
+        assert!(decoded.is_response_to(&request));
+        assert!(!request.is_response_to(&decoded));
+
+        // not a request, so nothing can be a response to it
+        let invalid = Message {
+            command_class: 0xff,
+            ..request.clone()
+        };
+        assert!(!decoded.is_response_to(&invalid));
+
+        let info = DeviceInfo::decode(&decoded.data).unwrap();
+        assert_eq!(info.protocol_version, 0x0100);
+        assert_eq!(info.footprint, 4);
+        assert_eq!(info.start_address, 1);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut packet = GET_DEVICE_INFO.to_vec();
+        packet[25] ^= 0x01;
+        assert!(matches!(Message::decode(&packet), Err(RdmError::Checksum)));
+
+        assert!(matches!(Message::decode(&GET_DEVICE_INFO[..25]),
+                         Err(RdmError::InvalidResponse(_))));
+
+        let mut packet = GET_DEVICE_INFO.to_vec();
+        packet[0] = 0x00;
+        assert!(matches!(Message::decode(&packet), Err(RdmError::InvalidResponse(_))));
+
+        let mut packet = GET_DEVICE_INFO.to_vec();
+        packet[23] = 0x01;
+        assert!(matches!(Message::decode(&packet), Err(RdmError::InvalidResponse(_))));
+
+        // trailing data after the checksum is ignored
+        let mut packet = GET_DEVICE_INFO.to_vec();
+        packet.push(0xff);
+        assert_eq!(Message::decode(&packet).unwrap(), get_device_info());
+    }
+
+    #[test]
+    fn encode_too_long() {
+        let mut request = get_device_info();
+        request.data = vec![0; MAX_PARAMETER_DATA_LEN];
+        assert_eq!(request.encode().unwrap().len(), 257);
+
+        request.data.push(0);
+        assert!(matches!(request.encode(), Err(RdmError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn uid() {
+        let uid = Uid::new(0x1234, 0x5678_9abc);
+        assert_eq!(uid.to_bytes(), [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+        assert_eq!(Uid::from_bytes(uid.to_bytes()), uid);
+        assert_eq!(Uid::from_u64(0x1234_5678_9abc), uid);
+        assert_eq!(uid.to_string(), "1234:56789abc");
+        assert_eq!("1234:56789abc".parse::<Uid>().unwrap(), uid);
+        assert!("123456789abc".parse::<Uid>().is_err());
+        assert!(Uid::BROADCAST.is_broadcast());
+        assert!(!Uid::MAX.is_broadcast());
+    }
+}
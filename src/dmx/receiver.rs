@@ -0,0 +1,458 @@
+//! DMX512 receiver
+
+use std::{error, fmt, io};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use marker::{LineEvent, Unmarker};
+use port::Rs485Port;
+use super::{MAX_SLOTS, NULL_START_CODE, line_settings, receiver_rs485};
+
+/// Problem with a received packet
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PacketError {
+    /// No start code, or fewer slots than the configured minimum
+    Short,
+    /// A character was received with a framing or parity error
+    ///
+    /// `slot` is the index of the first bad character, 0 being the start
+    /// code.
+    FramingError {
+        /// Index of the corrupted slot
+        slot: usize,
+    },
+    /// More than `MAX_SLOTS` slots; the excess was dropped
+    Overlong,
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PacketError::Short => write!(f, "short packet"),
+            PacketError::FramingError { slot } => write!(f, "framing error in slot {}", slot),
+            PacketError::Overlong => write!(f, "packet exceeds {} slots", MAX_SLOTS),
+        }
+    }
+}
+
+impl error::Error for PacketError {}
+
+/// Received DMX512 packet
+///
+/// Timestamps are taken when data is read from the driver and thus only
+/// accurate to the scheduling latency of the receiving thread.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    /// Start code, `None` if the packet ended right after the break
+    pub start_code: Option<u8>,
+    /// Slot values, slot 1 at index 0
+    pub slots: Vec<u8>,
+    /// Time the break was received
+    pub received: Instant,
+    /// Time from the break to the last slot
+    pub duration: Duration,
+    /// Time since the previous break, if any
+    pub interval: Option<Duration>,
+    /// Problem with the packet, if any
+    pub error: Option<PacketError>,
+}
+
+impl Packet {
+    /// Whether the packet was received without problems
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Reception statistics
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReceiverStats {
+    /// Completed packets, including bad ones
+    pub packets: u64,
+    /// Packets flagged as `PacketError::Short`
+    pub short_packets: u64,
+    /// Packets flagged as `PacketError::FramingError`
+    pub framing_errors: u64,
+    /// Packets flagged as `PacketError::Overlong`
+    pub overlong_packets: u64,
+    /// Bytes received outside of a packet, i.e. before the first break
+    pub discarded_bytes: u64,
+    /// Break to break time of the latest packet
+    pub last_interval: Option<Duration>,
+    /// Shortest break to break time seen
+    pub min_interval: Option<Duration>,
+    /// Longest break to break time seen
+    pub max_interval: Option<Duration>,
+}
+
+// packet being received
+#[derive(Debug)]
+struct Partial {
+    start_code: Option<u8>,
+    slots: Vec<u8>,
+    received: Instant,
+    last: Instant,
+    error: Option<PacketError>,
+}
+
+/// DMX512 receiver
+///
+/// Detects breaks through the error markers inserted by the driver (see
+/// `Rs485Port::set_mark_errors`) and assembles the data between them into
+/// packets. A packet is complete once the next break arrives or the line has
+/// been idle for the idle timeout.
+///
+/// A framing error on a zero byte is indistinguishable from a break and is
+/// treated as one.
+#[derive(Debug)]
+pub struct DmxReceiver {
+    port: Rs485Port,
+    idle_timeout: Duration,
+    min_slots: usize,
+    unmarker: Unmarker,
+    partial: Option<Partial>,
+    last_break: Option<Instant>,
+    complete: VecDeque<Packet>,
+    latest: Option<Packet>,
+    stats: ReceiverStats,
+}
+
+impl DmxReceiver {
+    /// Open a serial device as DMX512 receiver
+    ///
+    /// The line driver is kept disabled, see `receiver_rs485`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<DmxReceiver> {
+        let port = Rs485Port::open(path, &line_settings(), &receiver_rs485())?;
+        DmxReceiver::new(port)
+    }
+
+    /// Create a receiver on an already opened port
+    ///
+    /// Reconfigures the port for 250 kbaud, 8N2 and enables error marking.
+    /// Defaults to an idle timeout of 20 ms and no minimum slot count.
+    pub fn new(mut port: Rs485Port) -> io::Result<DmxReceiver> {
+        port.reconfigure(&line_settings())?;
+        port.set_mark_errors(true)?;
+        port.discard_input()?;
+
+        Ok(DmxReceiver {
+            port,
+            idle_timeout: Duration::from_millis(20),
+            min_slots: 0,
+            unmarker: Unmarker::default(),
+            partial: None,
+            last_break: None,
+            complete: VecDeque::new(),
+            latest: None,
+            stats: ReceiverStats::default(),
+        })
+    }
+
+    /// Set idle timeout
+    ///
+    /// A packet in progress is considered complete once no data has arrived
+    /// for this long. DMX512 allows gaps of up to one second between slots;
+    /// longer timeouts delay the last packet before a pause.
+    #[inline]
+    pub fn set_idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Set minimum number of slots
+    ///
+    /// Packets with fewer slots are flagged as `PacketError::Short`.
+    #[inline]
+    pub fn set_min_slots(&mut self, min_slots: usize) -> &mut Self {
+        self.min_slots = min_slots;
+        self
+    }
+
+    /// Wait up to `timeout` for the next packet
+    ///
+    /// Returns packets of any start code, including bad ones. Returns `None`
+    /// if no packet was completed in time.
+    pub fn receive(&mut self, timeout: Duration) -> io::Result<Option<Packet>> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 2 * (MAX_SLOTS + 1)];
+
+        loop {
+            if let Some(packet) = self.complete.pop_front() {
+                return Ok(Some(packet));
+            }
+
+            let now = Instant::now();
+            let mut wait = deadline.saturating_duration_since(now);
+            if let Some(ref partial) = self.partial {
+                wait = wait.min((partial.last + self.idle_timeout).saturating_duration_since(now));
+            }
+
+            let n = self.port.read_timeout(&mut buf, wait)?;
+            let now = Instant::now();
+
+            if n > 0 {
+                self.process(&buf[..n], now);
+                continue;
+            }
+
+            let idle = match self.partial {
+                Some(ref partial) => now >= partial.last + self.idle_timeout,
+                None => false,
+            };
+            if idle {
+                self.finish();
+            } else if now >= deadline {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Latest valid packet with the null start code
+    #[inline]
+    pub fn latest(&self) -> Option<&Packet> {
+        self.latest.as_ref()
+    }
+
+    /// Slot values of the latest valid packet with the null start code
+    ///
+    /// Empty until such a packet has been received.
+    #[inline]
+    pub fn universe(&self) -> &[u8] {
+        self.latest.as_ref().map_or(&[], |packet| &packet.slots)
+    }
+
+    /// Reception statistics
+    #[inline]
+    pub fn stats(&self) -> &ReceiverStats {
+        &self.stats
+    }
+
+    /// Reset reception statistics
+    #[inline]
+    pub fn reset_stats(&mut self) {
+        self.stats = ReceiverStats::default();
+    }
+
+    /// Underlying port
+    #[inline]
+    pub fn port(&mut self) -> &mut Rs485Port {
+        &mut self.port
+    }
+
+    /// Release the underlying port
+    ///
+    /// Error marking stays enabled.
+    #[inline]
+    pub fn into_port(self) -> Rs485Port {
+        self.port
+    }
+
+    /// Feed received data with error markers through the packet assembler
+    fn process(&mut self, data: &[u8], now: Instant) {
+        let mut unmarker = self.unmarker;
+
+        for &byte in data {
+            unmarker.feed(byte, |event| match event {
+                LineEvent::Data(byte) => self.slot(byte, false, now),
+                LineEvent::Error(byte) => self.slot(byte, true, now),
+                LineEvent::Break => self.brk(now),
+            });
+        }
+
+        self.unmarker = unmarker;
+    }
+
+    /// Handle a break: complete the current packet and start a new one
+    fn brk(&mut self, now: Instant) {
+        self.finish();
+
+        self.partial = Some(Partial {
+            start_code: None,
+            slots: Vec::with_capacity(MAX_SLOTS),
+            received: now,
+            last: now,
+            error: None,
+        });
+    }
+
+    /// Handle a received character
+    fn slot(&mut self, byte: u8, bad: bool, now: Instant) {
+        let partial = match self.partial {
+            Some(ref mut partial) => partial,
+            None => {
+                self.stats.discarded_bytes += 1;
+                return;
+            }
+        };
+
+        partial.last = now;
+
+        if bad && partial.error.is_none() {
+            let slot = partial.start_code.map_or(0, |_| partial.slots.len() + 1);
+            partial.error = Some(PacketError::FramingError { slot });
+        }
+
+        if partial.start_code.is_none() {
+            partial.start_code = Some(byte);
+        } else if partial.slots.len() < MAX_SLOTS {
+            partial.slots.push(byte);
+        } else if partial.error.is_none() {
+            partial.error = Some(PacketError::Overlong);
+        }
+    }
+
+    /// Complete the current packet, if any
+    fn finish(&mut self) {
+        let partial = match self.partial.take() {
+            Some(partial) => partial,
+            None => return,
+        };
+
+        let short = partial.start_code.is_none() || partial.slots.len() < self.min_slots;
+        let error = partial.error.or(if short { Some(PacketError::Short) } else { None });
+
+        let interval = self.last_break.map(|prev| partial.received - prev);
+        self.last_break = Some(partial.received);
+
+        let packet = Packet {
+            start_code: partial.start_code,
+            slots: partial.slots,
+            received: partial.received,
+            duration: partial.last - partial.received,
+            interval,
+            error,
+        };
+
+        let stats = &mut self.stats;
+        stats.packets += 1;
+        match packet.error {
+            Some(PacketError::Short) => stats.short_packets += 1,
+            Some(PacketError::FramingError { .. }) => stats.framing_errors += 1,
+            Some(PacketError::Overlong) => stats.overlong_packets += 1,
+            None => (),
+        }
+
+        if let Some(interval) = interval {
+            stats.last_interval = Some(interval);
+            stats.min_interval = Some(stats.min_interval.map_or(interval, |m| m.min(interval)));
+            stats.max_interval = Some(stats.max_interval.map_or(interval, |m| m.max(interval)));
+        }
+
+        if packet.is_valid() && packet.start_code == Some(NULL_START_CODE) {
+            self.latest = Some(packet.clone());
+        }
+
+        self.complete.push_back(packet);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::pty_port;
+
+    const BREAK: [u8; 3] = [0xff, 0x00, 0x00];
+
+    fn receiver() -> DmxReceiver {
+        let (_master, port) = pty_port();
+        DmxReceiver::new(port).unwrap()
+    }
+
+    fn packet(start_code: u8, slots: &[u8]) -> Vec<u8> {
+        let mut data = BREAK.to_vec();
+        data.push(start_code);
+        data.extend_from_slice(slots);
+        data
+    }
+
+    #[test]
+    fn packets_between_breaks() {
+        let mut rx = receiver();
+        let now = Instant::now();
+
+        rx.process(&[0x01, 0x02], now);
+        rx.process(&packet(NULL_START_CODE, &[0x10, 0xff, 0xff, 0x20]), now);
+        rx.process(&BREAK, now + Duration::from_millis(25));
+
+        let packet = rx.complete.pop_front().unwrap();
+        assert_eq!(packet.start_code, Some(NULL_START_CODE));
+        assert_eq!(packet.slots, [0x10, 0xff, 0x20]);
+        assert!(packet.is_valid());
+        assert_eq!(rx.universe(), &[0x10, 0xff, 0x20]);
+
+        rx.finish();
+        let packet = rx.complete.pop_front().unwrap();
+        assert_eq!(packet.start_code, None);
+        assert_eq!(packet.error, Some(PacketError::Short));
+        assert_eq!(packet.interval, Some(Duration::from_millis(25)));
+
+        assert_eq!(rx.stats().packets, 2);
+        assert_eq!(rx.stats().short_packets, 1);
+        assert_eq!(rx.stats().discarded_bytes, 2);
+        assert_eq!(rx.stats().last_interval, Some(Duration::from_millis(25)));
+    }
+
+    #[test]
+    fn break_split_across_reads() {
+        let mut rx = receiver();
+        let now = Instant::now();
+        let data = packet(NULL_START_CODE, &[0x01, 0x02]);
+
+        for chunk in data.chunks(1) {
+            rx.process(chunk, now);
+        }
+        rx.process(&BREAK[..2], now);
+        rx.process(&BREAK[2..], now);
+
+        assert_eq!(rx.stats().packets, 1);
+        assert_eq!(rx.stats().discarded_bytes, 0);
+        assert_eq!(rx.universe(), &[0x01, 0x02]);
+        assert!(rx.partial.is_some());
+    }
+
+    #[test]
+    fn short_packet() {
+        let mut rx = receiver();
+        rx.set_min_slots(24);
+        let now = Instant::now();
+
+        rx.process(&packet(NULL_START_CODE, &[0; 23]), now);
+        rx.finish();
+
+        let packet = rx.complete.pop_front().unwrap();
+        assert_eq!(packet.error, Some(PacketError::Short));
+        assert_eq!(rx.stats().short_packets, 1);
+        assert!(rx.latest().is_none());
+    }
+
+    #[test]
+    fn overlong_packet() {
+        let mut rx = receiver();
+        let now = Instant::now();
+
+        rx.process(&packet(NULL_START_CODE, &[0x55; MAX_SLOTS + 2]), now);
+        rx.finish();
+
+        let packet = rx.complete.pop_front().unwrap();
+        assert_eq!(packet.error, Some(PacketError::Overlong));
+        assert_eq!(packet.slots.len(), MAX_SLOTS);
+        assert_eq!(rx.stats().overlong_packets, 1);
+        assert!(rx.latest().is_none());
+    }
+
+    #[test]
+    fn framing_error() {
+        let mut rx = receiver();
+        let now = Instant::now();
+
+        rx.process(&packet(NULL_START_CODE, &[0x01, 0xff, 0x00, 0x02, 0x03]), now);
+        rx.finish();
+
+        let packet = rx.complete.pop_front().unwrap();
+        assert_eq!(packet.error, Some(PacketError::FramingError { slot: 2 }));
+        assert_eq!(packet.slots, [0x01, 0x02, 0x03]);
+        assert_eq!(rx.stats().framing_errors, 1);
+    }
+}
@@ -0,0 +1,255 @@
+//! DMX512 transmitter
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use port::{PortSettings, Rs485Port};
+use timing::LineTiming;
+use super::{MAX_SLOTS, NULL_START_CODE, line_settings, transmitter_rs485};
+
+// standard rates tried when generating breaks by baud switching, fastest
+// first
+const BREAK_BAUD_RATES: &[u32] = &[115_200, 57_600, 38_400, 19_200, 9_600, 4_800, 2_400, 1_200];
+
+/// How the break before each packet is generated
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BreakMethod {
+    /// Hold the line low with `TIOCSBRK`/`TIOCCBRK` for the break time
+    ///
+    /// Timing depends on the scheduler, breaks and MABs are usually some
+    /// tens of microseconds longer than configured.
+    Ioctl,
+    /// Send a zero byte at a lower baud rate
+    ///
+    /// The start bit and eight data bits form the break. Gives precise breaks
+    /// on drivers that ignore or delay break requests, at the cost of two
+    /// reconfigurations per packet.
+    BaudSwitch,
+}
+
+impl Default for BreakMethod {
+    #[inline]
+    fn default() -> BreakMethod {
+        BreakMethod::Ioctl
+    }
+}
+
+/// DMX512 transmitter
+///
+/// Holds a universe of slot values and sends it as packets with the
+/// configured start code.
+#[derive(Debug)]
+pub struct DmxTransmitter {
+    port: Rs485Port,
+    break_method: BreakMethod,
+    break_time: Duration,
+    mark_after_break: Duration,
+    // start code, followed by the slots
+    packet: Vec<u8>,
+}
+
+impl DmxTransmitter {
+    /// Open a serial device as DMX512 transmitter
+    ///
+    /// The line driver is kept enabled continuously, see
+    /// `transmitter_rs485`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<DmxTransmitter> {
+        let port = Rs485Port::open(path, &line_settings(), &transmitter_rs485())?;
+        DmxTransmitter::new(port)
+    }
+
+    /// Create a transmitter on an already opened port
+    ///
+    /// Reconfigures the port for 250 kbaud, 8N2. Defaults to breaks of
+    /// 176 µs, a mark after break of 16 µs and 512 slots with the null start
+    /// code, all zero.
+    pub fn new(mut port: Rs485Port) -> io::Result<DmxTransmitter> {
+        port.reconfigure(&line_settings())?;
+
+        let mut packet = vec![0; MAX_SLOTS + 1];
+        packet[0] = NULL_START_CODE;
+
+        Ok(DmxTransmitter {
+            port,
+            break_method: BreakMethod::default(),
+            break_time: Duration::from_micros(176),
+            mark_after_break: Duration::from_micros(16),
+            packet,
+        })
+    }
+
+    /// Set break generation method
+    #[inline]
+    pub fn set_break_method(&mut self, break_method: BreakMethod) -> &mut Self {
+        self.break_method = break_method;
+        self
+    }
+
+    /// Set break duration
+    ///
+    /// Should be at least `MIN_BREAK`. With `BreakMethod::BaudSwitch`, the
+    /// break is rounded up to nine bit times of a standard baud rate.
+    #[inline]
+    pub fn set_break_time(&mut self, break_time: Duration) -> &mut Self {
+        self.break_time = break_time;
+        self
+    }
+
+    /// Set mark after break duration
+    ///
+    /// Should be at least `MIN_MARK_AFTER_BREAK`.
+    #[inline]
+    pub fn set_mark_after_break(&mut self, mark_after_break: Duration) -> &mut Self {
+        self.mark_after_break = mark_after_break;
+        self
+    }
+
+    /// Set start code
+    #[inline]
+    pub fn set_start_code(&mut self, start_code: u8) -> &mut Self {
+        self.packet[0] = start_code;
+        self
+    }
+
+    /// Start code
+    #[inline]
+    pub fn start_code(&self) -> u8 {
+        self.packet[0]
+    }
+
+    /// Replace all slots
+    ///
+    /// The number of slots sent per packet is the length of `slots`, shorter
+    /// packets allow higher refresh rates. Fails if more than `MAX_SLOTS`
+    /// are given.
+    pub fn set_slots(&mut self, slots: &[u8]) -> io::Result<()> {
+        if slots.len() > MAX_SLOTS {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "more than 512 DMX slots"));
+        }
+
+        self.packet.truncate(1);
+        self.packet.extend_from_slice(slots);
+        Ok(())
+    }
+
+    /// Slot values
+    ///
+    /// Slot 1 is at index 0.
+    #[inline]
+    pub fn slots(&self) -> &[u8] {
+        &self.packet[1..]
+    }
+
+    /// Mutable slot values
+    #[inline]
+    pub fn slots_mut(&mut self) -> &mut [u8] {
+        &mut self.packet[1..]
+    }
+
+    /// Estimated time to transmit one packet
+    ///
+    /// Includes break, mark after break, start code and slots, assuming no
+    /// gaps between characters.
+    pub fn packet_time(&self) -> Duration {
+        LineTiming::new(self.port.settings()).frame_time(self.packet.len()) + self.break_time +
+        self.mark_after_break
+    }
+
+    /// Underlying port
+    #[inline]
+    pub fn port(&mut self) -> &mut Rs485Port {
+        &mut self.port
+    }
+
+    /// Release the underlying port
+    #[inline]
+    pub fn into_port(self) -> Rs485Port {
+        self.port
+    }
+
+    /// Send a single packet
+    ///
+    /// Returns once the packet has been transmitted completely.
+    pub fn send_packet(&mut self) -> io::Result<()> {
+        self.send_break()?;
+        self.port.write_all(&self.packet)?;
+        self.port.drain()
+    }
+
+    /// Send a packet other than the universe
+    ///
+    /// `packet` starts with the start code. Used for alternate start code
+    /// packets such as RDM, which do not replace the slot values.
+    pub fn send_raw(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.send_break()?;
+        self.port.write_all(packet)?;
+        self.port.drain()
+    }
+
+    /// Send packets at a fixed rate
+    ///
+    /// `update` is called with the slot values before each packet and may
+    /// change them; returning `false` stops the loop. If a packet takes
+    /// longer than the period, the next one is sent immediately.
+    pub fn run<F>(&mut self, packets_per_second: u32, mut update: F) -> io::Result<()>
+        where F: FnMut(&mut [u8]) -> bool
+    {
+        if packets_per_second == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "packet rate must not be 0"));
+        }
+
+        let period = Duration::from_secs(1) / packets_per_second;
+        let mut next = Instant::now();
+
+        while update(self.slots_mut()) {
+            self.send_packet()?;
+
+            next += period;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            } else {
+                // do not try to catch up on missed packets
+                next = now;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Generate break and mark after break
+    fn send_break(&mut self) -> io::Result<()> {
+        match self.break_method {
+            BreakMethod::Ioctl => {
+                self.port.set_break(true)?;
+                thread::sleep(self.break_time);
+                self.port.set_break(false)?;
+            }
+            BreakMethod::BaudSwitch => {
+                let mut settings = PortSettings::new();
+                settings.set_baud_rate(break_baud_rate(self.break_time));
+
+                self.port.reconfigure(&settings)?;
+                self.port.write_all(&[0])?;
+                self.port.drain()?;
+                // the stop bit and the reconfiguration count towards the MAB
+                self.port.reconfigure(&line_settings())?;
+            }
+        }
+
+        thread::sleep(self.mark_after_break);
+        Ok(())
+    }
+}
+
+/// Fastest standard baud rate at which nine bits last at least `break_time`
+fn break_baud_rate(break_time: Duration) -> u32 {
+    let nanos = break_time.as_nanos().max(1);
+
+    BREAK_BAUD_RATES.iter()
+        .cloned()
+        .find(|&rate| 9_000_000_000 / rate as u128 >= nanos)
+        .unwrap_or(BREAK_BAUD_RATES[BREAK_BAUD_RATES.len() - 1])
+}
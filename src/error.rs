@@ -0,0 +1,158 @@
+//! Error type for RS485 ioctls
+
+use libc;
+use std::{error, fmt, io};
+
+/// Error returned when reading or applying RS485 settings fails
+///
+/// Distinguishes the common failure modes of the RS485 ioctls. Converts back
+/// into an `io::Error` carrying the original `errno` for use with the `Rs485`
+/// trait.
+#[derive(Debug)]
+pub enum Rs485Error {
+    /// The serial driver has no RS485 support (`ENOTTY`)
+    NotSupported,
+    /// The driver rejected the configuration (`EINVAL`)
+    InvalidConfiguration,
+    /// The file descriptor is not open (`EBADF`)
+    BadFileDescriptor,
+    /// Insufficient permissions to change the configuration (`EPERM`,
+    /// `EACCES`)
+    PermissionDenied,
+    /// Any other I/O error
+    Io(io::Error),
+}
+
+impl Rs485Error {
+    /// Create from the last OS error
+    #[inline]
+    pub fn last_os_error() -> Rs485Error {
+        io::Error::last_os_error().into()
+    }
+
+    /// Corresponding `errno` value, if any
+    ///
+    /// `PermissionDenied` always reports `EPERM`.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match *self {
+            Rs485Error::NotSupported => Some(libc::ENOTTY),
+            Rs485Error::InvalidConfiguration => Some(libc::EINVAL),
+            Rs485Error::BadFileDescriptor => Some(libc::EBADF),
+            Rs485Error::PermissionDenied => Some(libc::EPERM),
+            Rs485Error::Io(ref e) => e.raw_os_error(),
+        }
+    }
+}
+
+impl fmt::Display for Rs485Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rs485Error::NotSupported => {
+                write!(f,
+                       "serial driver does not support RS485 (check that the \
+                        UART is enabled and RS485 is configured in the device \
+                        tree)")
+            }
+            Rs485Error::InvalidConfiguration => {
+                write!(f,
+                       "RS485 configuration rejected by driver (unsupported \
+                        flag combination or RTS pin not pinmuxed)")
+            }
+            Rs485Error::BadFileDescriptor => write!(f, "not an open file descriptor"),
+            Rs485Error::PermissionDenied => {
+                write!(f, "insufficient permissions to configure RS485")
+            }
+            Rs485Error::Io(ref e) => write!(f, "RS485 ioctl failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for Rs485Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Rs485Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Rs485Error {
+    fn from(e: io::Error) -> Rs485Error {
+        match e.raw_os_error() {
+            Some(libc::ENOTTY) => Rs485Error::NotSupported,
+            Some(libc::EINVAL) => Rs485Error::InvalidConfiguration,
+            Some(libc::EBADF) => Rs485Error::BadFileDescriptor,
+            Some(libc::EPERM) | Some(libc::EACCES) => Rs485Error::PermissionDenied,
+            _ => Rs485Error::Io(e),
+        }
+    }
+}
+
+impl From<Rs485Error> for io::Error {
+    fn from(e: Rs485Error) -> io::Error {
+        match (e.raw_os_error(), e) {
+            (_, Rs485Error::Io(e)) => e,
+            (Some(errno), _) => io::Error::from_raw_os_error(errno),
+            (None, e) => io::Error::other(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(errno: i32) -> Rs485Error {
+        io::Error::from_raw_os_error(errno).into()
+    }
+
+    #[test]
+    fn errno_round_trip() {
+        for &errno in &[libc::ENOTTY, libc::EINVAL, libc::EBADF, libc::EPERM, libc::EIO] {
+            let err = io::Error::from(classify(errno));
+            assert_eq!(err.raw_os_error(), Some(errno));
+        }
+    }
+
+    #[test]
+    fn variants() {
+        match classify(libc::ENOTTY) {
+            Rs485Error::NotSupported => (),
+            e => panic!("{:?}", e),
+        }
+        match classify(libc::EINVAL) {
+            Rs485Error::InvalidConfiguration => (),
+            e => panic!("{:?}", e),
+        }
+        match classify(libc::EBADF) {
+            Rs485Error::BadFileDescriptor => (),
+            e => panic!("{:?}", e),
+        }
+        match classify(libc::EIO) {
+            Rs485Error::Io(ref e) => assert_eq!(e.raw_os_error(), Some(libc::EIO)),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn permission_denied() {
+        for &errno in &[libc::EPERM, libc::EACCES] {
+            let err = classify(errno);
+            match err {
+                Rs485Error::PermissionDenied => (),
+                ref e => panic!("{:?}", e),
+            }
+            assert_eq!(io::Error::from(err).kind(), io::ErrorKind::PermissionDenied);
+        }
+    }
+
+    #[test]
+    fn non_os_error() {
+        let err = Rs485Error::from(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+        assert_eq!(err.raw_os_error(), None);
+
+        let err = io::Error::from(err);
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "timeout");
+    }
+}
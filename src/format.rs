@@ -0,0 +1,260 @@
+//! Compact text representation of RS485 settings
+//!
+//! Settings are written as a comma-separated list of flag names and
+//! `key=value` pairs, e.g. `enabled,rts_on_send,delay_before=2`. Delays are
+//! given in milliseconds, addresses in decimal or `0x`-prefixed hex. Flags
+//! without a name are written as a single `0x`-prefixed hex value.
+
+use std::{error, fmt};
+use std::str::FromStr;
+
+use super::*;
+
+// names of all flags that can appear in text form, in output order
+const FLAG_NAMES: &[(&str, Rs485Flags)] = &[("enabled", SER_RS485_ENABLED),
+                                            ("rts_on_send", SER_RS485_RTS_ON_SEND),
+                                            ("rts_after_send", SER_RS485_RTS_AFTER_SEND),
+                                            ("rx_during_tx", SER_RS485_RX_DURING_TX),
+                                            ("terminate_bus", SER_RS485_TERMINATE_BUS),
+                                            ("addrb", SER_RS485_ADDRB),
+                                            ("addr_recv", SER_RS485_ADDR_RECV),
+                                            ("addr_dest", SER_RS485_ADDR_DEST),
+                                            ("mode_rs422", SER_RS485_MODE_RS422)];
+
+/// Error parsing the text form of RS485 settings
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseRs485Error {
+    /// A flag or key name was not recognized
+    UnknownName(String),
+    /// A value could not be parsed
+    InvalidValue(String),
+}
+
+impl fmt::Display for ParseRs485Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseRs485Error::UnknownName(ref name) => {
+                write!(f, "unknown RS485 setting `{}`", name)
+            }
+            ParseRs485Error::InvalidValue(ref item) => {
+                write!(f, "invalid value in RS485 setting `{}`", item)
+            }
+        }
+    }
+}
+
+impl error::Error for ParseRs485Error {}
+
+fn flag_by_name(name: &str) -> Result<Rs485Flags, ParseRs485Error> {
+    if name.starts_with("0x") || name.starts_with("0X") {
+        return parse_u32(name, name).map(Rs485Flags::from_bits_retain);
+    }
+
+    FLAG_NAMES.iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, flag)| flag)
+        .ok_or_else(|| ParseRs485Error::UnknownName(name.to_owned()))
+}
+
+fn parse_u32(item: &str, value: &str) -> Result<u32, ParseRs485Error> {
+    let rv = if value.starts_with("0x") || value.starts_with("0X") {
+        u32::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse()
+    };
+
+    rv.map_err(|_| ParseRs485Error::InvalidValue(item.to_owned()))
+}
+
+fn parse_u8(item: &str, value: &str) -> Result<u8, ParseRs485Error> {
+    let v = parse_u32(item, value)?;
+
+    if v > u8::MAX as u32 {
+        return Err(ParseRs485Error::InvalidValue(item.to_owned()));
+    }
+
+    Ok(v as u8)
+}
+
+// splits a comma-separated list, ignoring whitespace and empty items
+fn items(s: &str) -> impl Iterator<Item = &str> {
+    s.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+impl fmt::Display for Rs485Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        for &(name, flag) in FLAG_NAMES {
+            if self.contains(flag) {
+                if !first {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}", name)?;
+                first = false;
+            }
+        }
+
+        let unnamed = self.bits() & !Rs485Flags::all().bits();
+        if unnamed != 0 {
+            if !first {
+                write!(f, ",")?;
+            }
+            write!(f, "0x{:x}", unnamed)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Rs485Flags {
+    type Err = ParseRs485Error;
+
+    fn from_str(s: &str) -> Result<Rs485Flags, ParseRs485Error> {
+        let mut flags = Rs485Flags::empty();
+
+        for item in items(s) {
+            flags |= flag_by_name(item)?;
+        }
+
+        Ok(flags)
+    }
+}
+
+impl fmt::Display for SerialRs485 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // address flags are written as key-value pairs instead
+        let mut parts = vec![(self.flags - SER_RS485_ADDR_RECV - SER_RS485_ADDR_DEST)
+                                 .to_string()];
+
+        if self.delay_rts_before_send != 0 {
+            parts.push(format!("delay_before={}", self.delay_rts_before_send));
+        }
+        if self.delay_rts_after_send != 0 {
+            parts.push(format!("delay_after={}", self.delay_rts_after_send));
+        }
+        if let Some(addr) = self.addr_recv() {
+            parts.push(format!("addr_recv=0x{:02x}", addr));
+        }
+        if let Some(addr) = self.addr_dest() {
+            parts.push(format!("addr_dest=0x{:02x}", addr));
+        }
+
+        parts.retain(|p| !p.is_empty());
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl FromStr for SerialRs485 {
+    type Err = ParseRs485Error;
+
+    fn from_str(s: &str) -> Result<SerialRs485, ParseRs485Error> {
+        let mut conf = SerialRs485::new();
+
+        for item in items(s) {
+            let mut kv = item.splitn(2, '=');
+            let key = kv.next().unwrap_or("").trim();
+
+            match kv.next().map(str::trim) {
+                // the address flags are only valid along with an address
+                None if key == "addr_recv" || key == "addr_dest" => {
+                    return Err(ParseRs485Error::InvalidValue(item.to_owned()));
+                }
+                None => conf.flags |= flag_by_name(key)?,
+                Some(value) => {
+                    match key {
+                        "delay_before" => {
+                            conf.delay_rts_before_send_ms(parse_u32(item, value)?);
+                        }
+                        "delay_after" => {
+                            conf.delay_rts_after_send_ms(parse_u32(item, value)?);
+                        }
+                        "addr_recv" => {
+                            conf.set_addr_recv(Some(parse_u8(item, value)?));
+                        }
+                        "addr_dest" => {
+                            conf.set_addr_dest(Some(parse_u8(item, value)?));
+                        }
+                        _ => return Err(ParseRs485Error::UnknownName(key.to_owned())),
+                    }
+                }
+            }
+        }
+
+        Ok(conf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str) -> SerialRs485 {
+        let conf: SerialRs485 = text.parse().unwrap();
+        assert_eq!(conf.to_string(), text);
+        conf
+    }
+
+    #[test]
+    fn flags_round_trip() {
+        let flags = SER_RS485_ENABLED | SER_RS485_RTS_ON_SEND | SER_RS485_ADDR_RECV |
+                    SER_RS485_MODE_RS422;
+        assert_eq!(flags.to_string(), "enabled,rts_on_send,addr_recv,mode_rs422");
+        assert_eq!(flags.to_string().parse::<Rs485Flags>().unwrap(), flags);
+
+        assert_eq!(Rs485Flags::empty().to_string(), "");
+        assert_eq!("".parse::<Rs485Flags>().unwrap(), Rs485Flags::empty());
+        assert_eq!(Rs485Flags::all().to_string().parse::<Rs485Flags>().unwrap(),
+                   Rs485Flags::all());
+    }
+
+    #[test]
+    fn unnamed_flags() {
+        let flags = SER_RS485_ENABLED | Rs485Flags::from_bits_retain(0x0c08);
+        assert_eq!(flags.to_string(), "enabled,0xc08");
+        assert_eq!(flags.to_string().parse::<Rs485Flags>().unwrap(), flags);
+
+        assert_eq!(Rs485Flags::from_bits_retain(0x8000_0000).to_string(), "0x80000000");
+        assert!("0xfoo".parse::<Rs485Flags>().is_err());
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let conf = round_trip("enabled,rts_on_send,delay_before=2,delay_after=10");
+        assert_eq!(conf.delay_rts_before_send(), 2);
+        assert_eq!(conf.delay_rts_after_send(), 10);
+
+        let conf = round_trip("enabled,addrb,addr_recv=0x00,addr_dest=0x2a");
+        assert_eq!(conf.addr_recv(), Some(0x00));
+        assert_eq!(conf.addr_dest(), Some(0x2a));
+
+        round_trip("");
+        round_trip("enabled,terminate_bus,mode_rs422,0x400");
+    }
+
+    #[test]
+    fn settings_parse() {
+        let conf: SerialRs485 = " enabled , addr_recv=42,delay_before=0x10 ".parse().unwrap();
+        assert_eq!(conf.addr_recv(), Some(42));
+        assert_eq!(conf.delay_rts_before_send(), 16);
+        assert_eq!(conf.to_string(), "enabled,delay_before=16,addr_recv=0x2a");
+    }
+
+    #[test]
+    fn settings_errors() {
+        assert_eq!("enabled,bogus".parse::<SerialRs485>(),
+                   Err(ParseRs485Error::UnknownName("bogus".to_owned())));
+        assert_eq!("bogus=1".parse::<SerialRs485>(),
+                   Err(ParseRs485Error::UnknownName("bogus".to_owned())));
+        assert_eq!("addr_recv=256".parse::<SerialRs485>(),
+                   Err(ParseRs485Error::InvalidValue("addr_recv=256".to_owned())));
+        assert_eq!("delay_before=-1".parse::<SerialRs485>(),
+                   Err(ParseRs485Error::InvalidValue("delay_before=-1".to_owned())));
+
+        // an address flag without its address would not round-trip
+        assert_eq!("enabled,addr_recv".parse::<SerialRs485>(),
+                   Err(ParseRs485Error::InvalidValue("addr_recv".to_owned())));
+        assert_eq!("addr_dest".parse::<SerialRs485>(),
+                   Err(ParseRs485Error::InvalidValue("addr_dest".to_owned())));
+    }
+}
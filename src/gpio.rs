@@ -0,0 +1,357 @@
+//! GPIO-based driver enable control
+//!
+//! On some boards the transceiver's driver enable (DE) and receiver enable
+//! (RE) pins are wired to a GPIO instead of a UART's RTS pin. `GpioLine`
+//! drives such a pin through the Linux GPIO character device (v2 uAPI), while
+//! `GpioDirection` toggles it around transmissions.
+//!
+//! Any `OutputLine` can be used in place of a `GpioLine`, e.g. to mock the
+//! GPIO in tests.
+
+use libc::{self, c_char};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use super::SerialRs485;
+use direction::{DirectionControl, wait_transmitter_empty};
+use ioctl;
+use sys;
+
+/// A digital output, e.g. a GPIO pin
+pub trait OutputLine {
+    /// Drive the line to its active (`true`) or inactive level
+    fn set_active(&mut self, active: bool) -> io::Result<()>;
+}
+
+impl<L: OutputLine + ?Sized> OutputLine for &mut L {
+    #[inline]
+    fn set_active(&mut self, active: bool) -> io::Result<()> {
+        (**self).set_active(active)
+    }
+}
+
+impl<L: OutputLine + ?Sized> OutputLine for Box<L> {
+    #[inline]
+    fn set_active(&mut self, active: bool) -> io::Result<()> {
+        (**self).set_active(active)
+    }
+}
+
+// constants and structures from `linux/gpio.h`
+const GPIO_MAX_NAME_SIZE: usize = 32;
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+const GPIO_V2_LINE_FLAG_ACTIVE_LOW: u64 = 1 << 1;
+const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+
+const GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES: u32 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GpioV2LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GpioV2LineAttribute {
+    id: u32,
+    padding: u32,
+    // union of `flags`, `values` and `debounce_period_us`
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GpioV2LineConfigAttribute {
+    attr: GpioV2LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GpioV2LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [GpioV2LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GpioV2LineRequest {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [c_char; GPIO_MAX_NAME_SIZE],
+    config: GpioV2LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+// layout checks against `linux/gpio.h`
+const _: () = assert!(::std::mem::size_of::<GpioV2LineValues>() == 16);
+const _: () = assert!(::std::mem::size_of::<GpioV2LineConfigAttribute>() == 24);
+const _: () = assert!(::std::mem::size_of::<GpioV2LineConfig>() == 272);
+const _: () = assert!(::std::mem::size_of::<GpioV2LineRequest>() == 592);
+
+const GPIO_V2_GET_LINE_IOCTL: libc::c_ulong = ioctl::iowr::<GpioV2LineRequest>(0xb4, 0x07);
+const GPIO_V2_LINE_SET_VALUES_IOCTL: libc::c_ulong = ioctl::iowr::<GpioV2LineValues>(0xb4,
+                                                                                       0x0f);
+
+/// Build the request for a single output line, initially inactive
+fn line_request(offset: u32, active_low: bool) -> GpioV2LineRequest {
+    let mut req: GpioV2LineRequest = unsafe { ::std::mem::zeroed() };
+    req.offsets[0] = offset;
+    req.num_lines = 1;
+
+    for (dst, &src) in req.consumer.iter_mut().zip(b"rs485") {
+        *dst = src as c_char;
+    }
+
+    req.config.flags = GPIO_V2_LINE_FLAG_OUTPUT;
+    if active_low {
+        req.config.flags |= GPIO_V2_LINE_FLAG_ACTIVE_LOW;
+    }
+
+    // initial value: inactive
+    req.config.num_attrs = 1;
+    req.config.attrs[0] = GpioV2LineConfigAttribute {
+        attr: GpioV2LineAttribute {
+            id: GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES,
+            padding: 0,
+            value: 0,
+        },
+        mask: 1,
+    };
+
+    req
+}
+
+/// Single output line requested from a GPIO chip
+///
+/// The line is released when dropped.
+#[derive(Debug)]
+pub struct GpioLine {
+    line: File,
+}
+
+impl GpioLine {
+    /// Request a line as an output
+    ///
+    /// `chip` is the GPIO chip device (e.g. `/dev/gpiochip0`), `offset` the
+    /// line number on that chip. With `active_low`, the line is driven low
+    /// when active. The line starts out inactive.
+    pub fn request<P: AsRef<Path>>(chip: P, offset: u32, active_low: bool) -> io::Result<GpioLine> {
+        let chip = OpenOptions::new().read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC)
+            .open(chip)?;
+
+        let mut req = line_request(offset, active_low);
+
+        sys::check(unsafe { libc::ioctl(chip.as_raw_fd(), GPIO_V2_GET_LINE_IOCTL, &mut req) })?;
+
+        Ok(GpioLine { line: unsafe { File::from_raw_fd(req.fd) } })
+    }
+}
+
+impl OutputLine for GpioLine {
+    fn set_active(&mut self, active: bool) -> io::Result<()> {
+        let values = GpioV2LineValues {
+            bits: active as u64,
+            mask: 1,
+        };
+
+        sys::check(unsafe {
+            libc::ioctl(self.line.as_raw_fd(), GPIO_V2_LINE_SET_VALUES_IOCTL, &values)
+        })?;
+        Ok(())
+    }
+}
+
+impl AsRawFd for GpioLine {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.line.as_raw_fd()
+    }
+}
+
+/// Direction control through a driver enable output line
+///
+/// Activates the line before, and deactivates it after a transmission,
+/// honoring `delay_rts_before_send` and `delay_rts_after_send` the same way
+/// the kernel does for RTS.
+///
+/// The serial port's own RS485 settings are not used.
+#[derive(Debug)]
+pub struct GpioDirection<L> {
+    line: L,
+    delay_before: Duration,
+    delay_after: Duration,
+}
+
+impl<L: OutputLine> GpioDirection<L> {
+    /// Create with delays taken from an RS485 configuration
+    ///
+    /// The line is set inactive immediately.
+    pub fn new(mut line: L, conf: &SerialRs485) -> io::Result<GpioDirection<L>> {
+        line.set_active(false)?;
+
+        Ok(GpioDirection {
+            line,
+            delay_before: Duration::from_millis(conf.delay_rts_before_send() as u64),
+            delay_after: Duration::from_millis(conf.delay_rts_after_send() as u64),
+        })
+    }
+
+    /// Underlying output line
+    #[inline]
+    pub fn line(&self) -> &L {
+        &self.line
+    }
+
+    /// Release the output line
+    #[inline]
+    pub fn into_line(self) -> L {
+        self.line
+    }
+}
+
+impl<L: OutputLine> DirectionControl for GpioDirection<L> {
+    /// Activate the line and wait for `delay_rts_before_send`
+    fn begin_transmit(&mut self, _fd: RawFd) -> io::Result<()> {
+        self.line.set_active(true)?;
+
+        if self.delay_before > Duration::from_millis(0) {
+            thread::sleep(self.delay_before);
+        }
+
+        Ok(())
+    }
+
+    /// Wait for transmission to finish, then deactivate the line
+    ///
+    /// Deactivates the line after the transmitter on `fd` has been drained and
+    /// `delay_rts_after_send` has passed.
+    fn end_transmit(&mut self, fd: RawFd) -> io::Result<()> {
+        let drained = wait_transmitter_empty(fd);
+
+        if drained.is_ok() && self.delay_after > Duration::from_millis(0) {
+            thread::sleep(self.delay_after);
+        }
+
+        // never leave the driver enabled, it would block the whole bus
+        self.line.set_active(false)?;
+        drained
+    }
+
+    #[inline]
+    fn is_hardware_managed(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libc;
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use port::{PortSettings, Rs485Port};
+
+    /// Output line recording each change, along with the number of bytes
+    /// that had arrived on the other side of the serial port by then
+    struct MockLine {
+        peer: RawFd,
+        changes: Arc<Mutex<Vec<(bool, Instant, usize)>>>,
+    }
+
+    impl OutputLine for MockLine {
+        fn set_active(&mut self, active: bool) -> io::Result<()> {
+            let mut pending: libc::c_int = 0;
+            sys::check(unsafe { libc::ioctl(self.peer, libc::FIONREAD, &mut pending) })?;
+            self.changes.lock().unwrap().push((active, Instant::now(), pending as usize));
+            Ok(())
+        }
+    }
+
+    /// Open a pseudo terminal, returning its master and the slave's path
+    fn pty() -> (File, String) {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(fd >= 0);
+            assert_eq!(libc::grantpt(fd), 0);
+            assert_eq!(libc::unlockpt(fd), 0);
+            let name = CStr::from_ptr(libc::ptsname(fd)).to_str().unwrap().to_owned();
+            (File::from_raw_fd(fd), name)
+        }
+    }
+
+    #[test]
+    fn request_active_low() {
+        let req = line_request(17, true);
+        assert_eq!(req.offsets[0], 17);
+        assert_eq!(req.num_lines, 1);
+        assert_eq!(req.config.flags,
+                   GPIO_V2_LINE_FLAG_OUTPUT | GPIO_V2_LINE_FLAG_ACTIVE_LOW);
+
+        // the kernel inverts the logical value, so inactive is 0 either way
+        assert_eq!(req.config.num_attrs, 1);
+        assert_eq!(req.config.attrs[0].attr.id, GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES);
+        assert_eq!(req.config.attrs[0].attr.value, 0);
+        assert_eq!(req.config.attrs[0].mask, 1);
+    }
+
+    #[test]
+    fn request_active_high() {
+        let req = line_request(3, false);
+        assert_eq!(req.config.flags, GPIO_V2_LINE_FLAG_OUTPUT);
+        assert_eq!(req.config.attrs[0].attr.value, 0);
+    }
+
+    #[test]
+    fn delays_around_write() {
+        let (master, slave) = pty();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let line = MockLine {
+            peer: master.as_raw_fd(),
+            changes: changes.clone(),
+        };
+
+        let mut conf = SerialRs485::new();
+        conf.delay_rts_before_send_ms(20).delay_rts_after_send_ms(30);
+
+        let mut port = Rs485Port::open(&slave, &PortSettings::new(), &SerialRs485::new()).unwrap();
+        port.set_direction_control(GpioDirection::new(line, &conf).unwrap());
+
+        let before = Instant::now();
+        port.write_all(b"hello").unwrap();
+
+        // inactive from the start, active with nothing sent yet, inactive
+        // again once everything has been sent and both delays have passed
+        let changes = changes.lock().unwrap();
+        assert_eq!(changes.len(), 3);
+
+        let (initial, _, _) = changes[0];
+        let (active, activated, sent_before) = changes[1];
+        let (inactive, deactivated, sent_after) = changes[2];
+        assert!(!initial && active && !inactive);
+        assert!(activated >= before);
+        assert_eq!(sent_before, 0);
+        assert_eq!(sent_after, 5);
+        assert!(deactivated - activated >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,160 @@
+//! Architecture-specific ioctl request numbers
+//!
+//! Most architectures use the plain `asm-generic` values for the RS485
+//! ioctls. MIPS and SPARC define them through `_IOR`/`_IOWR` instead, which
+//! in turn use a different bit layout than `asm-generic/ioctl.h`. The same
+//! layout applies to PowerPC, which matters for `_IOC` encoded requests like
+//! the GPIO ones.
+
+use libc::c_ulong;
+use std::mem;
+
+use super::SerialRs485;
+
+/// Bit layout of an `_IOC` encoded request number
+#[derive(Copy, Clone, Debug)]
+struct IocLayout {
+    size_bits: u32,
+    read: u32,
+    write: u32,
+}
+
+// `asm-generic/ioctl.h`
+const GENERIC_IOC: IocLayout = IocLayout {
+    size_bits: 14,
+    read: 2,
+    write: 1,
+};
+
+// `arch/{mips,sparc,powerpc,alpha}/include/uapi/asm/ioctl.h`
+const MIPS_SPARC_IOC: IocLayout = IocLayout {
+    size_bits: 13,
+    read: 2,
+    write: 4,
+};
+
+#[cfg(any(target_arch = "mips",
+          target_arch = "mips64",
+          target_arch = "mips32r6",
+          target_arch = "mips64r6",
+          target_arch = "sparc",
+          target_arch = "sparc64",
+          target_arch = "powerpc",
+          target_arch = "powerpc64"))]
+const TARGET_IOC: IocLayout = MIPS_SPARC_IOC;
+
+#[cfg(not(any(target_arch = "mips",
+              target_arch = "mips64",
+              target_arch = "mips32r6",
+              target_arch = "mips64r6",
+              target_arch = "sparc",
+              target_arch = "sparc64",
+              target_arch = "powerpc",
+              target_arch = "powerpc64")))]
+const TARGET_IOC: IocLayout = GENERIC_IOC;
+
+const NR_SHIFT: u32 = 0;
+const TYPE_SHIFT: u32 = 8;
+const SIZE_SHIFT: u32 = 16;
+
+const SERIAL_RS485_SIZE: u32 = mem::size_of::<SerialRs485>() as u32;
+
+/// Equivalent of the kernel's `_IOC` macro
+const fn ioc(layout: IocLayout, dir: u32, ty: u8, nr: u8, size: u32) -> u32 {
+    (dir << (SIZE_SHIFT + layout.size_bits))
+        | ((ty as u32) << TYPE_SHIFT)
+        | ((nr as u32) << NR_SHIFT)
+        | (size << SIZE_SHIFT)
+}
+
+/// `_IOR(ty, nr, struct serial_rs485)`
+const fn ior_rs485(layout: IocLayout, ty: u8, nr: u8) -> u32 {
+    ioc(layout, layout.read, ty, nr, SERIAL_RS485_SIZE)
+}
+
+/// `_IOWR(ty, nr, struct serial_rs485)`
+const fn iowr_rs485(layout: IocLayout, ty: u8, nr: u8) -> u32 {
+    ioc(layout, layout.read | layout.write, ty, nr, SERIAL_RS485_SIZE)
+}
+
+/// `_IOWR(ty, nr, T)` for the target architecture
+pub const fn iowr<T>(ty: u8, nr: u8) -> c_ulong {
+    ioc(TARGET_IOC,
+        TARGET_IOC.read | TARGET_IOC.write,
+        ty,
+        nr,
+        mem::size_of::<T>() as u32) as c_ulong
+}
+
+/// `_IOR(ty, nr, T)` for the target architecture
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+pub const fn ior<T>(ty: u8, nr: u8) -> c_ulong {
+    ioc(TARGET_IOC, TARGET_IOC.read, ty, nr, mem::size_of::<T>() as u32) as c_ulong
+}
+
+/// `_IOW(ty, nr, T)` for the target architecture
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+pub const fn iow<T>(ty: u8, nr: u8) -> c_ulong {
+    ioc(TARGET_IOC, TARGET_IOC.write, ty, nr, mem::size_of::<T>() as u32) as c_ulong
+}
+
+#[cfg(any(target_arch = "mips",
+          target_arch = "mips64",
+          target_arch = "mips32r6",
+          target_arch = "mips64r6"))]
+mod arch {
+    use super::{MIPS_SPARC_IOC, ior_rs485, iowr_rs485};
+
+    pub const TIOCGRS485: u32 = ior_rs485(MIPS_SPARC_IOC, b'T', 0x2e);
+    pub const TIOCSRS485: u32 = iowr_rs485(MIPS_SPARC_IOC, b'T', 0x2f);
+}
+
+#[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+mod arch {
+    use super::{MIPS_SPARC_IOC, ior_rs485, iowr_rs485};
+
+    pub const TIOCGRS485: u32 = ior_rs485(MIPS_SPARC_IOC, b'T', 0x41);
+    pub const TIOCSRS485: u32 = iowr_rs485(MIPS_SPARC_IOC, b'T', 0x42);
+}
+
+// asm-generic, which includes x86, ARM, RISC-V and PowerPC (the latter uses
+// the `_IOC` layout of MIPS for other requests, but not for these)
+#[cfg(not(any(target_arch = "mips",
+              target_arch = "mips64",
+              target_arch = "mips32r6",
+              target_arch = "mips64r6",
+              target_arch = "sparc",
+              target_arch = "sparc64")))]
+mod arch {
+    pub const TIOCGRS485: u32 = 0x542e;
+    pub const TIOCSRS485: u32 = 0x542f;
+}
+
+pub const TIOCGRS485: c_ulong = arch::TIOCGRS485 as c_ulong;
+pub const TIOCSRS485: c_ulong = arch::TIOCSRS485 as c_ulong;
+
+// known values for every supported architecture, checked regardless of target
+const _: () = assert!(ior_rs485(MIPS_SPARC_IOC, b'T', 0x2e) == 0x4020_542e);
+const _: () = assert!(iowr_rs485(MIPS_SPARC_IOC, b'T', 0x2f) == 0xc020_542f);
+const _: () = assert!(ior_rs485(MIPS_SPARC_IOC, b'T', 0x41) == 0x4020_5441);
+const _: () = assert!(iowr_rs485(MIPS_SPARC_IOC, b'T', 0x42) == 0xc020_5442);
+const _: () = assert!(ior_rs485(GENERIC_IOC, b'T', 0x2e) == 0x8020_542e);
+const _: () = assert!(iowr_rs485(GENERIC_IOC, b'T', 0x2f) == 0xc020_542f);
+
+// values chosen for the target, as found in its `asm/ioctls.h`
+#[cfg(any(target_arch = "mips",
+          target_arch = "mips64",
+          target_arch = "mips32r6",
+          target_arch = "mips64r6"))]
+const _: () = assert!(TIOCGRS485 == 0x4020_542e && TIOCSRS485 == 0xc020_542f);
+
+#[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+const _: () = assert!(TIOCGRS485 == 0x4020_5441 && TIOCSRS485 == 0xc020_5442);
+
+#[cfg(not(any(target_arch = "mips",
+              target_arch = "mips64",
+              target_arch = "mips32r6",
+              target_arch = "mips64r6",
+              target_arch = "sparc",
+              target_arch = "sparc64")))]
+const _: () = assert!(TIOCGRS485 == 0x542e && TIOCSRS485 == 0x542f);
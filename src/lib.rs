@@ -40,27 +40,75 @@
 #[macro_use]
 extern crate bitflags;
 extern crate libc;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_test;
 
-use libc::c_ulong;
-use std::{mem, io};
+pub mod direction;
+pub mod dmx;
+mod error;
+mod format;
+pub mod gpio;
+mod ioctl;
+mod marker;
+pub mod mbus;
+pub mod mdb;
+pub mod modbus;
+pub mod mstp;
+pub mod port;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod sys;
+#[cfg(test)]
+mod testutil;
+pub mod timing;
+pub mod transact;
+
+pub use direction::{DirectionControl, DirectionMode, KernelRs485, ModemLine,
+                    ModemLineDirection};
+pub use error::Rs485Error;
+pub use gpio::{GpioDirection, GpioLine, OutputLine};
+pub use format::ParseRs485Error;
+pub use port::{BaudRateMismatch, BusCollision, PortSettings, Rs485Port};
+pub use timing::LineTiming;
+pub use transact::{Reply, ReplyEnd, ReplyPolicy};
+use ioctl::{TIOCGRS485, TIOCSRS485};
+use std::{fmt, mem, io};
+use std::ops::Deref;
 use std::os::unix::io::{AsRawFd, RawFd};
 
-// constants stolen from C libs
-const TIOCSRS485: c_ulong = 0x542f;
-const TIOCGRS485: c_ulong = 0x542e;
+pub use flags::*;
 
-// bitflags used by rs485 functionality
-bitflags! {
-    pub struct Rs485Flags: u32 {
-        const SER_RS485_ENABLED        = (1 << 0);
-        const SER_RS485_RTS_ON_SEND    = (1 << 1);
-        const SER_RS485_RTS_AFTER_SEND = (1 << 2);
-        const SER_RS485_RX_DURING_TX   = (1 << 4);
+// bitflags used by rs485 functionality; bitflags' generated code still uses
+// `try!`, hence the wrapping module
+#[allow(deprecated)]
+mod flags {
+    bitflags! {
+        pub struct Rs485Flags: u32 {
+            const SER_RS485_ENABLED        = (1 << 0);
+            const SER_RS485_RTS_ON_SEND    = (1 << 1);
+            const SER_RS485_RTS_AFTER_SEND = (1 << 2);
+            const SER_RS485_RX_DURING_TX   = (1 << 4);
+            const SER_RS485_TERMINATE_BUS  = (1 << 5);
+            const SER_RS485_ADDRB          = (1 << 6);
+            const SER_RS485_ADDR_RECV      = (1 << 7);
+            const SER_RS485_ADDR_DEST      = (1 << 8);
+            const SER_RS485_MODE_RS422     = (1 << 9);
+        }
+    }
+
+    impl Rs485Flags {
+        /// Create from raw bits, keeping bits without a name
+        #[inline]
+        pub(crate) fn from_bits_retain(bits: u32) -> Rs485Flags {
+            Rs485Flags { bits }
+        }
     }
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 /// RS485 serial configuration
 ///
 /// Internally, this structure is the same as a [`struct serial_rs485`]
@@ -69,9 +117,31 @@ pub struct SerialRs485 {
     flags: Rs485Flags,
     delay_rts_before_send: u32,
     delay_rts_after_send: u32,
-    _padding: [u32; 5],
+    addr_recv: u8,
+    addr_dest: u8,
+    _padding0: [u8; 2],
+    _padding1: [u32; 4],
 }
 
+// layout checks against the kernel's `struct serial_rs485`
+const _: () = assert!(mem::size_of::<SerialRs485>() == 32);
+const _: () = assert!(mem::align_of::<SerialRs485>() == 4);
+const _: () = assert!(mem::offset_of!(SerialRs485, flags) == 0);
+const _: () = assert!(mem::offset_of!(SerialRs485, delay_rts_before_send) == 4);
+const _: () = assert!(mem::offset_of!(SerialRs485, delay_rts_after_send) == 8);
+const _: () = assert!(mem::offset_of!(SerialRs485, addr_recv) == 12);
+const _: () = assert!(mem::offset_of!(SerialRs485, addr_dest) == 13);
+const _: () = assert!(mem::offset_of!(SerialRs485, _padding1) == 16);
+
+impl Default for SerialRs485 {
+    #[inline]
+    fn default() -> SerialRs485 {
+        SerialRs485::new()
+    }
+}
+
+// explicit lifetimes on the setters are kept as they were first published
+#[allow(clippy::needless_lifetimes)]
 impl SerialRs485 {
     /// Create a new, empty set of serial settings
     ///
@@ -86,13 +156,13 @@ impl SerialRs485 {
     /// Settings will be loaded from the file descriptor, which must be a
     /// valid serial device support RS485 extensions
     #[inline]
-    pub fn from_fd(fd: RawFd) -> io::Result<SerialRs485> {
+    pub fn from_fd(fd: RawFd) -> Result<SerialRs485, Rs485Error> {
         let mut conf = SerialRs485::new();
 
         let rval = unsafe { libc::ioctl(fd, TIOCGRS485, &mut conf as *mut SerialRs485) };
 
         if rval == -1 {
-            return Err(io::Error::last_os_error());
+            return Err(Rs485Error::last_os_error());
         }
 
         Ok(conf)
@@ -177,16 +247,136 @@ impl SerialRs485 {
         self
     }
 
+    /// Enable bus termination
+    ///
+    /// On hardware with switchable termination resistors, this turns on the
+    /// termination (usually through a GPIO configured in the device tree).
+    #[inline]
+    pub fn set_terminate_bus<'a>(&'a mut self, terminate_bus: bool) -> &'a mut Self {
+        if terminate_bus {
+            self.flags |= SER_RS485_TERMINATE_BUS;
+        } else {
+            self.flags &= !SER_RS485_TERMINATE_BUS;
+        }
+        self
+    }
+
+    /// Enable RS422 mode
+    ///
+    /// Puts the transceiver into full-duplex RS422 mode instead of RS485. RTS
+    /// is not toggled around transmissions in this mode.
+    #[inline]
+    pub fn set_mode_rs422<'a>(&'a mut self, mode_rs422: bool) -> &'a mut Self {
+        if mode_rs422 {
+            self.flags |= SER_RS485_MODE_RS422;
+        } else {
+            self.flags &= !SER_RS485_MODE_RS422;
+        }
+        self
+    }
+
+    /// Enable 9-bit addressing mode
+    ///
+    /// Uses the ninth bit of each character to mark address bytes on
+    /// multidrop buses. Requires a UART that supports it.
+    #[inline]
+    pub fn set_addrb<'a>(&'a mut self, addrb: bool) -> &'a mut Self {
+        if addrb {
+            self.flags |= SER_RS485_ADDRB;
+        } else {
+            self.flags &= !SER_RS485_ADDRB;
+        }
+        self
+    }
+
+    /// Set receive address filter
+    ///
+    /// With `Some(addr)`, the UART will only receive frames addressed to
+    /// `addr`; `None` turns the filter off. Only effective if 9-bit addressing
+    /// is enabled through `set_addrb`.
+    #[inline]
+    pub fn set_addr_recv<'a>(&'a mut self, addr_recv: Option<u8>) -> &'a mut Self {
+        match addr_recv {
+            Some(addr) => {
+                self.flags |= SER_RS485_ADDR_RECV;
+                self.addr_recv = addr;
+            }
+            None => {
+                self.flags &= !SER_RS485_ADDR_RECV;
+                self.addr_recv = 0;
+            }
+        }
+        self
+    }
+
+    /// Set destination address
+    ///
+    /// With `Some(addr)`, the UART will send `addr` as an address byte before
+    /// transmitting; `None` clears the destination. Only effective if 9-bit
+    /// addressing is enabled through `set_addrb`.
+    #[inline]
+    pub fn set_addr_dest<'a>(&'a mut self, addr_dest: Option<u8>) -> &'a mut Self {
+        match addr_dest {
+            Some(addr) => {
+                self.flags |= SER_RS485_ADDR_DEST;
+                self.addr_dest = addr;
+            }
+            None => {
+                self.flags &= !SER_RS485_ADDR_DEST;
+                self.addr_dest = 0;
+            }
+        }
+        self
+    }
+
+    /// Currently set flags
+    #[inline]
+    pub fn flags(&self) -> Rs485Flags {
+        self.flags
+    }
+
+    /// Delay before sending, in ms
+    #[inline]
+    pub fn delay_rts_before_send(&self) -> u32 {
+        self.delay_rts_before_send
+    }
+
+    /// Delay after sending, in ms
+    #[inline]
+    pub fn delay_rts_after_send(&self) -> u32 {
+        self.delay_rts_after_send
+    }
+
+    /// Receive address filter, if set
+    #[inline]
+    pub fn addr_recv(&self) -> Option<u8> {
+        if self.flags.contains(SER_RS485_ADDR_RECV) {
+            Some(self.addr_recv)
+        } else {
+            None
+        }
+    }
+
+    /// Destination address, if set
+    #[inline]
+    pub fn addr_dest(&self) -> Option<u8> {
+        if self.flags.contains(SER_RS485_ADDR_DEST) {
+            Some(self.addr_dest)
+        } else {
+            None
+        }
+    }
+
     /// Apply settings to file descriptor
     ///
     /// Applies the constructed configuration a raw filedescriptor using
     /// `ioctl`.
     #[inline]
-    pub fn set_on_fd(&self, fd: RawFd) -> io::Result<()> {
+    pub fn set_on_fd(&self, fd: RawFd) -> Result<(), Rs485Error> {
         let rval = unsafe { libc::ioctl(fd, TIOCSRS485, self as *const SerialRs485) };
 
         if rval == -1 {
-            return Err(io::Error::last_os_error());
+            return Err(Rs485Error::last_os_error());
         }
 
         Ok(())
@@ -194,6 +384,124 @@ impl SerialRs485 {
 }
 
 
+/// Comparison of requested and applied RS485 settings
+///
+/// Drivers silently adjust settings they cannot honor, e.g. `serial_core`
+/// clamps delays to 100 ms and unsupported flags are dropped. A report is
+/// produced by `Rs485::apply_rs485_conf_verified` by reading back the
+/// configuration after setting it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rs485Report {
+    /// Configuration that was written
+    pub requested: SerialRs485,
+    /// Configuration read back from the driver
+    pub applied: SerialRs485,
+}
+
+impl Rs485Report {
+    /// Whether the driver applied the configuration unchanged
+    #[inline]
+    pub fn is_exact(&self) -> bool {
+        self.dropped_flags().is_empty() && self.added_flags().is_empty() &&
+        !self.delay_rts_before_send_changed() &&
+        !self.delay_rts_after_send_changed() && !self.addr_recv_changed() &&
+        !self.addr_dest_changed()
+    }
+
+    /// Flags that were requested, but not applied
+    #[inline]
+    pub fn dropped_flags(&self) -> Rs485Flags {
+        self.requested.flags & !self.applied.flags
+    }
+
+    /// Flags that were applied, but not requested
+    #[inline]
+    pub fn added_flags(&self) -> Rs485Flags {
+        self.applied.flags & !self.requested.flags
+    }
+
+    /// Whether the driver changed the delay before sending
+    #[inline]
+    pub fn delay_rts_before_send_changed(&self) -> bool {
+        self.requested.delay_rts_before_send != self.applied.delay_rts_before_send
+    }
+
+    /// Whether the driver changed the delay after sending
+    #[inline]
+    pub fn delay_rts_after_send_changed(&self) -> bool {
+        self.requested.delay_rts_after_send != self.applied.delay_rts_after_send
+    }
+
+    /// Whether the driver changed the receive address filter
+    #[inline]
+    pub fn addr_recv_changed(&self) -> bool {
+        self.requested.addr_recv() != self.applied.addr_recv()
+    }
+
+    /// Whether the driver changed the destination address
+    #[inline]
+    pub fn addr_dest_changed(&self) -> bool {
+        self.requested.addr_dest() != self.applied.addr_dest()
+    }
+
+    /// Turn report into an error if the configuration was not applied exactly
+    ///
+    /// Returns the applied configuration on success.
+    #[inline]
+    pub fn check(self) -> Result<SerialRs485, Rs485Report> {
+        if self.is_exact() {
+            Ok(self.applied)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for Rs485Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_exact() {
+            return write!(f, "RS485 configuration applied unchanged");
+        }
+
+        write!(f, "driver changed RS485 configuration:")?;
+
+        if !self.dropped_flags().is_empty() {
+            write!(f, " dropped flags {:?};", self.dropped_flags())?;
+        }
+        if !self.added_flags().is_empty() {
+            write!(f, " added flags {:?};", self.added_flags())?;
+        }
+        if self.delay_rts_before_send_changed() {
+            write!(f,
+                   " delay_rts_before_send {} ms -> {} ms;",
+                   self.requested.delay_rts_before_send,
+                   self.applied.delay_rts_before_send)?;
+        }
+        if self.delay_rts_after_send_changed() {
+            write!(f,
+                   " delay_rts_after_send {} ms -> {} ms;",
+                   self.requested.delay_rts_after_send,
+                   self.applied.delay_rts_after_send)?;
+        }
+        if self.addr_recv_changed() {
+            write!(f,
+                   " addr_recv {:?} -> {:?};",
+                   self.requested.addr_recv(),
+                   self.applied.addr_recv())?;
+        }
+        if self.addr_dest_changed() {
+            write!(f,
+                   " addr_dest {:?} -> {:?};",
+                   self.requested.addr_dest(),
+                   self.applied.addr_dest())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Rs485Report {}
+
 /// Rs485 controls
 ///
 /// A convenient trait for controlling Rs485 parameters.
@@ -207,24 +515,323 @@ pub trait Rs485 {
     /// Update RS485 configuration
     ///
     /// Combines `get_rs485_conf` and `set_rs485_conf` through a closure
+    #[allow(clippy::unused_unit)]
     fn update_rs485_conf<F: FnOnce(&mut SerialRs485) -> ()>(&self, f: F) -> io::Result<()>;
+
+    /// Set RS485 parameters and verify them
+    ///
+    /// Sets the configuration, then reads it back to report any changes the
+    /// driver made.
+    fn apply_rs485_conf_verified(&self, conf: &SerialRs485) -> io::Result<Rs485Report>;
+
+    /// Temporarily set RS485 parameters
+    ///
+    /// Saves the current configuration, then applies `conf`. The saved
+    /// configuration is restored once the returned guard is dropped, including
+    /// during unwinding after a panic.
+    fn rs485_scoped(&self, conf: &SerialRs485) -> io::Result<Rs485Guard<'_, Self>>;
+}
+
+/// Restores a previous RS485 configuration when dropped
+///
+/// Returned by `Rs485::rs485_scoped`. Dereferences to the underlying target.
+#[derive(Debug)]
+pub struct Rs485Guard<'a, T: Rs485 + ?Sized + 'a> {
+    target: &'a T,
+    original: SerialRs485,
+}
+
+impl<'a, T: Rs485 + ?Sized + 'a> Rs485Guard<'a, T> {
+    /// Configuration that will be restored
+    #[inline]
+    pub fn original(&self) -> &SerialRs485 {
+        &self.original
+    }
+}
+
+impl<'a, T: Rs485 + ?Sized + 'a> Deref for Rs485Guard<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.target
+    }
+}
+
+impl<'a, T: Rs485 + ?Sized + 'a> Drop for Rs485Guard<'a, T> {
+    fn drop(&mut self) {
+        // there is no way to report errors from here; the port may well have
+        // been closed already
+        let _ = self.target.set_rs485_conf(&self.original);
+    }
 }
 
 impl<T: AsRawFd> Rs485 for T {
     #[inline]
     fn get_rs485_conf(&self) -> io::Result<SerialRs485> {
-        SerialRs485::from_fd(self.as_raw_fd())
+        Ok(SerialRs485::from_fd(self.as_raw_fd())?)
     }
 
     #[inline]
     fn set_rs485_conf(&self, conf: &SerialRs485) -> io::Result<()> {
-        conf.set_on_fd(self.as_raw_fd())
+        Ok(conf.set_on_fd(self.as_raw_fd())?)
     }
 
     #[inline]
+    #[allow(clippy::unused_unit)]
     fn update_rs485_conf<F: FnOnce(&mut SerialRs485) -> ()>(&self, f: F) -> io::Result<()> {
         let mut conf = self.get_rs485_conf()?;
         f(&mut conf);
         self.set_rs485_conf(&conf)
     }
+
+    #[inline]
+    fn apply_rs485_conf_verified(&self, conf: &SerialRs485) -> io::Result<Rs485Report> {
+        self.set_rs485_conf(conf)?;
+
+        Ok(Rs485Report {
+            requested: *conf,
+            applied: self.get_rs485_conf()?,
+        })
+    }
+
+    #[inline]
+    fn rs485_scoped(&self, conf: &SerialRs485) -> io::Result<Rs485Guard<'_, Self>> {
+        let original = self.get_rs485_conf()?;
+        self.set_rs485_conf(conf)?;
+
+        Ok(Rs485Guard {
+            target: self,
+            original,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::panic::{self, AssertUnwindSafe};
+    use testutil::pty;
+
+    // stands in for a serial port with RS485 support
+    struct FakePort {
+        conf: RefCell<SerialRs485>,
+    }
+
+    impl Rs485 for FakePort {
+        fn get_rs485_conf(&self) -> io::Result<SerialRs485> {
+            Ok(*self.conf.borrow())
+        }
+
+        fn set_rs485_conf(&self, conf: &SerialRs485) -> io::Result<()> {
+            *self.conf.borrow_mut() = *conf;
+            Ok(())
+        }
+
+        fn update_rs485_conf<F: FnOnce(&mut SerialRs485)>(&self, f: F) -> io::Result<()> {
+            f(&mut self.conf.borrow_mut());
+            Ok(())
+        }
+
+        fn apply_rs485_conf_verified(&self, conf: &SerialRs485) -> io::Result<Rs485Report> {
+            self.set_rs485_conf(conf)?;
+            Ok(Rs485Report {
+                requested: *conf,
+                applied: *conf,
+            })
+        }
+
+        fn rs485_scoped(&self, conf: &SerialRs485) -> io::Result<Rs485Guard<'_, Self>> {
+            let original = self.get_rs485_conf()?;
+            self.set_rs485_conf(conf)?;
+            Ok(Rs485Guard {
+                target: self,
+                original,
+            })
+        }
+    }
+
+    fn fake_port() -> (FakePort, SerialRs485, SerialRs485) {
+        let mut original = SerialRs485::new();
+        original.set_enabled(true).set_rts_on_send(true);
+        let mut scoped = original;
+        scoped.set_rts_on_send(false).set_rts_after_send(true).delay_rts_after_send_ms(3);
+
+        (FakePort { conf: RefCell::new(original) }, original, scoped)
+    }
+
+    #[test]
+    fn terminate_bus() {
+        let mut conf = SerialRs485::new();
+        conf.set_terminate_bus(true);
+        assert_eq!(conf.flags(), SER_RS485_TERMINATE_BUS);
+        conf.set_terminate_bus(false);
+        assert!(conf.flags().is_empty());
+    }
+
+    #[test]
+    fn mode_rs422() {
+        let mut conf = SerialRs485::new();
+        conf.set_enabled(true).set_mode_rs422(true);
+        assert_eq!(conf.flags(), SER_RS485_ENABLED | SER_RS485_MODE_RS422);
+        conf.set_mode_rs422(false);
+        assert_eq!(conf.flags(), SER_RS485_ENABLED);
+    }
+
+    #[test]
+    fn addrb() {
+        let mut conf = SerialRs485::new();
+        conf.set_addrb(true);
+        assert_eq!(conf.flags(), SER_RS485_ADDRB);
+        assert_eq!(conf.addr_recv(), None);
+        assert_eq!(conf.addr_dest(), None);
+        conf.set_addrb(false);
+        assert!(conf.flags().is_empty());
+    }
+
+    #[test]
+    fn addresses() {
+        let mut conf = SerialRs485::new();
+        conf.set_addrb(true).set_addr_recv(Some(0x12)).set_addr_dest(Some(0x34));
+        assert_eq!(conf.flags(), SER_RS485_ADDRB | SER_RS485_ADDR_RECV | SER_RS485_ADDR_DEST);
+        assert_eq!(conf.addr_recv(), Some(0x12));
+        assert_eq!(conf.addr_dest(), Some(0x34));
+
+        conf.set_addr_recv(Some(0));
+        assert_eq!(conf.addr_recv(), Some(0));
+
+        conf.set_addr_recv(None);
+        assert_eq!(conf.flags(), SER_RS485_ADDRB | SER_RS485_ADDR_DEST);
+        assert_eq!(conf.addr_recv(), None);
+        assert_eq!(conf.addr_dest(), Some(0x34));
+
+        conf.set_addr_dest(None);
+        assert_eq!(conf.flags(), SER_RS485_ADDRB);
+        assert_eq!(conf, *SerialRs485::new().set_addrb(true));
+    }
+
+    #[test]
+    fn flags_independent() {
+        let mut conf = SerialRs485::new();
+        conf.set_enabled(true)
+            .set_rts_on_send(true)
+            .set_rx_during_tx(true)
+            .set_terminate_bus(true)
+            .set_mode_rs422(true);
+        conf.set_rts_on_send(false).set_terminate_bus(false);
+        assert_eq!(conf.flags(),
+                   SER_RS485_ENABLED | SER_RS485_RX_DURING_TX | SER_RS485_MODE_RS422);
+    }
+
+    #[test]
+    fn report_exact() {
+        let mut conf = SerialRs485::new();
+        conf.set_enabled(true).delay_rts_before_send_ms(5);
+        let report = Rs485Report {
+            requested: conf,
+            applied: conf,
+        };
+
+        assert!(report.is_exact());
+        assert_eq!(report.check(), Ok(conf));
+        assert_eq!(report.to_string(), "RS485 configuration applied unchanged");
+    }
+
+    #[test]
+    fn report_adjusted() {
+        let mut requested = SerialRs485::new();
+        requested.set_enabled(true)
+            .set_rts_on_send(true)
+            .set_terminate_bus(true)
+            .delay_rts_before_send_ms(500)
+            .delay_rts_after_send_ms(2)
+            .set_addrb(true)
+            .set_addr_recv(Some(0x12));
+
+        // serial_core clamps delays to 100 ms, the driver lacks termination
+        // and forces RTS after sending
+        let mut applied = requested;
+        applied.set_terminate_bus(false)
+            .set_rts_after_send(true)
+            .delay_rts_before_send_ms(100);
+
+        let report = Rs485Report { requested, applied };
+        assert!(!report.is_exact());
+        assert_eq!(report.dropped_flags(), SER_RS485_TERMINATE_BUS);
+        assert_eq!(report.added_flags(), SER_RS485_RTS_AFTER_SEND);
+        assert!(report.delay_rts_before_send_changed());
+        assert!(!report.delay_rts_after_send_changed());
+        assert!(!report.addr_recv_changed());
+        assert!(!report.addr_dest_changed());
+        assert_eq!(report.check(), Err(report));
+        assert_eq!(report.to_string(),
+                   "driver changed RS485 configuration: dropped flags SER_RS485_TERMINATE_BUS; \
+                    added flags SER_RS485_RTS_AFTER_SEND; delay_rts_before_send 500 ms -> 100 ms;");
+    }
+
+    #[test]
+    fn report_addresses() {
+        let mut requested = SerialRs485::new();
+        requested.set_addrb(true).set_addr_recv(Some(0x12)).set_addr_dest(Some(0x34));
+
+        let mut applied = requested;
+        applied.set_addr_recv(None).set_addr_dest(Some(0x35));
+
+        let report = Rs485Report { requested, applied };
+        assert!(!report.is_exact());
+        assert_eq!(report.dropped_flags(), SER_RS485_ADDR_RECV);
+        assert!(report.added_flags().is_empty());
+        assert!(report.addr_recv_changed());
+        assert!(report.addr_dest_changed());
+    }
+
+    #[test]
+    fn scoped_restores_on_drop() {
+        let (port, original, scoped) = fake_port();
+
+        {
+            let guard = port.rs485_scoped(&scoped).unwrap();
+            assert_eq!(guard.original(), &original);
+            assert_eq!(guard.get_rs485_conf().unwrap(), scoped);
+        }
+
+        assert_eq!(port.get_rs485_conf().unwrap(), original);
+    }
+
+    #[test]
+    fn scoped_restores_on_error() {
+        let (port, original, scoped) = fake_port();
+
+        let rv = (|| -> io::Result<()> {
+            let _guard = port.rs485_scoped(&scoped)?;
+            assert_eq!(port.get_rs485_conf()?, scoped);
+            Err(io::Error::new(io::ErrorKind::TimedOut, "no reply"))?;
+            unreachable!()
+        })();
+
+        assert_eq!(rv.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(port.get_rs485_conf().unwrap(), original);
+    }
+
+    #[test]
+    fn scoped_restores_on_panic() {
+        let (port, original, scoped) = fake_port();
+
+        let rv = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = port.rs485_scoped(&scoped).unwrap();
+            panic!("transaction failed");
+        }));
+
+        assert!(rv.is_err());
+        assert_eq!(port.get_rs485_conf().unwrap(), original);
+    }
+
+    #[test]
+    fn scoped_unsupported() {
+        let (master, _) = pty();
+
+        let err = master.rs485_scoped(&SerialRs485::new()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTTY));
+    }
 }
@@ -0,0 +1,114 @@
+//! Decoding of received data with error marking enabled
+
+/// Event in a byte stream received with error marking enabled
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineEvent {
+    /// Character received without error
+    Data(u8),
+    /// Character received with a framing or parity error
+    Error(u8),
+    /// Break, or a framing error on a zero character
+    Break,
+}
+
+/// Decoder for the markers inserted by `Rs485Port::set_mark_errors`
+///
+/// Keeps its state between chunks of received data.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Unmarker {
+    // number of marker bytes (`0xff`, `0x00`) seen so far
+    pending: u8,
+}
+
+impl Unmarker {
+    /// Feed a received byte, passing decoded events to `emit`
+    pub fn feed<F: FnMut(LineEvent)>(&mut self, byte: u8, mut emit: F) {
+        self.pending = match (self.pending, byte) {
+            (0, 0xff) => 1,
+            (0, _) => {
+                emit(LineEvent::Data(byte));
+                0
+            }
+            (1, 0xff) => {
+                emit(LineEvent::Data(0xff));
+                0
+            }
+            (1, 0x00) => 2,
+            (1, _) => {
+                // not a valid marker, pass it on unchanged
+                emit(LineEvent::Data(0xff));
+                emit(LineEvent::Data(byte));
+                0
+            }
+            (_, 0x00) => {
+                emit(LineEvent::Break);
+                0
+            }
+            (_, _) => {
+                emit(LineEvent::Error(byte));
+                0
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(unmarker: &mut Unmarker, data: &[u8]) -> Vec<LineEvent> {
+        let mut events = Vec::new();
+        for &byte in data {
+            unmarker.feed(byte, |event| events.push(event));
+        }
+        events
+    }
+
+    #[test]
+    fn plain_data() {
+        let events = decode(&mut Unmarker::default(), &[0x00, 0x01, 0xfe]);
+        assert_eq!(events,
+                   [LineEvent::Data(0x00), LineEvent::Data(0x01), LineEvent::Data(0xfe)]);
+    }
+
+    #[test]
+    fn escaped_ff() {
+        let events = decode(&mut Unmarker::default(), &[0x01, 0xff, 0xff, 0x02]);
+        assert_eq!(events,
+                   [LineEvent::Data(0x01), LineEvent::Data(0xff), LineEvent::Data(0x02)]);
+    }
+
+    #[test]
+    fn break_marker() {
+        let events = decode(&mut Unmarker::default(), &[0xff, 0x00, 0x00, 0x05]);
+        assert_eq!(events, [LineEvent::Break, LineEvent::Data(0x05)]);
+    }
+
+    #[test]
+    fn error_marker() {
+        let events = decode(&mut Unmarker::default(), &[0xff, 0x00, 0x42, 0xff, 0x00, 0xff]);
+        assert_eq!(events, [LineEvent::Error(0x42), LineEvent::Error(0xff)]);
+    }
+
+    #[test]
+    fn invalid_marker() {
+        let events = decode(&mut Unmarker::default(), &[0xff, 0x01]);
+        assert_eq!(events, [LineEvent::Data(0xff), LineEvent::Data(0x01)]);
+    }
+
+    #[test]
+    fn marker_split_across_reads() {
+        let mut unmarker = Unmarker::default();
+
+        assert_eq!(decode(&mut unmarker, &[0x01, 0xff]), [LineEvent::Data(0x01)]);
+        assert_eq!(decode(&mut unmarker, &[0x00]), []);
+        assert_eq!(decode(&mut unmarker, &[0x00, 0x02]),
+                   [LineEvent::Break, LineEvent::Data(0x02)]);
+
+        assert_eq!(decode(&mut unmarker, &[0xff]), []);
+        assert_eq!(decode(&mut unmarker, &[0xff]), [LineEvent::Data(0xff)]);
+
+        assert_eq!(decode(&mut unmarker, &[0xff, 0x00]), []);
+        assert_eq!(decode(&mut unmarker, &[0x33]), [LineEvent::Error(0x33)]);
+    }
+}
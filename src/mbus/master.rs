@@ -0,0 +1,363 @@
+//! M-Bus master
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use port::Rs485Port;
+use timing::LineTiming;
+use super::{ACK, BROADCAST_ADDRESS, BROADCAST_REPLY_ADDRESS, Frame, MAX_USER_DATA_LEN,
+            MbusError, NETWORK_LAYER_ADDRESS, SecondaryAddress, SecondaryPattern, ci, control};
+use super::records::VariableData;
+
+// lower bound for the inter-character timeout, see modbus::master
+const MIN_INTER_CHAR_TIMEOUT: Duration = Duration::from_millis(5);
+
+// upper bound on telegrams read by `read_data`
+const MAX_TELEGRAMS: usize = 16;
+
+/// Outcome of a selection by secondary address
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SelectResponse {
+    /// No slave matched
+    None,
+    /// Exactly one slave matched and is now selected
+    Selected,
+    /// Several slaves matched; all of them are selected
+    Collision,
+}
+
+/// M-Bus master
+///
+/// Talks to slaves through a level converter. Requests are repeated after
+/// timeouts and checksum errors, keeping the frame count bit so slaves
+/// repeat their last response instead of advancing.
+#[derive(Debug)]
+pub struct MbusMaster {
+    port: Rs485Port,
+    timeout: Duration,
+    retries: u32,
+    inter_char_timeout: Duration,
+    // frame count bit to send next, per address
+    fcb: [bool; 256],
+}
+
+impl MbusMaster {
+    /// Create a master
+    ///
+    /// Timing is derived from the port's line settings, see `line_settings`.
+    /// Defaults to a response timeout of 330 bit times plus 50 ms, the
+    /// longest response delay slaves may have, and two retries.
+    pub fn new(port: Rs485Port) -> MbusMaster {
+        let timing = LineTiming::new(port.settings());
+
+        MbusMaster {
+            port,
+            timeout: timing.bit_time() * 330 + Duration::from_millis(50),
+            retries: 2,
+            inter_char_timeout: (timing.char_time() * 3).max(MIN_INTER_CHAR_TIMEOUT),
+            fcb: [true; 256],
+        }
+    }
+
+    /// Set response timeout
+    ///
+    /// Level converters attached through USB may need a longer timeout.
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set number of retries after a timeout or checksum error
+    #[inline]
+    pub fn set_retries(&mut self, retries: u32) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Set inter-character timeout
+    ///
+    /// A response is considered complete once the line has been silent for
+    /// this long, even if it is shorter than announced. Defaults to three
+    /// character times, but at least 5 ms.
+    #[inline]
+    pub fn set_inter_char_timeout(&mut self, inter_char_timeout: Duration) -> &mut Self {
+        self.inter_char_timeout = inter_char_timeout;
+        self
+    }
+
+    /// Underlying port
+    #[inline]
+    pub fn port(&mut self) -> &mut Rs485Port {
+        &mut self.port
+    }
+
+    /// Release the underlying port
+    #[inline]
+    pub fn into_port(self) -> Rs485Port {
+        self.port
+    }
+
+    /// Initialize a slave (SND_NKE)
+    ///
+    /// Resets the frame count bit. Sent to `NETWORK_LAYER_ADDRESS`, deselects
+    /// the slave selected by secondary address. No acknowledgement is
+    /// expected for `BROADCAST_ADDRESS`.
+    pub fn send_nke(&mut self, address: u8) -> Result<(), MbusError> {
+        let frame = Frame::Short {
+            control: control::SND_NKE,
+            address,
+        };
+        self.fcb[address as usize] = true;
+
+        if address == BROADCAST_ADDRESS {
+            return self.send(&frame.encode()?);
+        }
+
+        self.transact(&frame.encode()?).and_then(expect_ack)
+    }
+
+    /// Send user data to a slave (SND_UD)
+    ///
+    /// `data` follows the CI field. No acknowledgement is expected for
+    /// `BROADCAST_ADDRESS`.
+    pub fn snd_ud(&mut self, address: u8, ci: u8, data: &[u8]) -> Result<(), MbusError> {
+        let frame = Frame::Long {
+            control: control::SND_UD | self.fcb_bit(address),
+            address,
+            ci,
+            data: data.to_vec(),
+        };
+
+        if address == BROADCAST_ADDRESS {
+            return self.send(&frame.encode()?);
+        }
+
+        self.transact(&frame.encode()?).and_then(expect_ack)?;
+        self.toggle_fcb(address);
+
+        Ok(())
+    }
+
+    /// Request class 2 data (REQ_UD2)
+    ///
+    /// Returns the data of a single response telegram; see `read_data` for
+    /// slaves spreading their records over several telegrams.
+    pub fn req_ud2(&mut self, address: u8) -> Result<VariableData, MbusError> {
+        if address == BROADCAST_ADDRESS {
+            return Err(MbusError::InvalidRequest("broadcast requests get no response"));
+        }
+
+        let frame = Frame::Short {
+            control: control::REQ_UD2 | self.fcb_bit(address),
+            address,
+        };
+
+        let response = self.transact(&frame.encode()?)?;
+        let (resp_control, resp_address, resp_ci, data) = match response {
+            Frame::Long { control, address, ci, data } => (control, address, ci, data),
+            _ => return Err(MbusError::InvalidResponse("expected RSP_UD long frame")),
+        };
+
+        if resp_control & !(control::ACD | control::DFC) != control::RSP_UD {
+            return Err(MbusError::InvalidResponse("expected RSP_UD long frame"));
+        }
+        // slaves answer requests to special addresses with their own
+        if resp_address != address && address != NETWORK_LAYER_ADDRESS &&
+           address != BROADCAST_REPLY_ADDRESS {
+            return Err(MbusError::InvalidResponse("address mismatch"));
+        }
+
+        self.toggle_fcb(address);
+
+        if resp_ci == ci::RESPONSE_FIXED {
+            return Err(MbusError::InvalidResponse("fixed data structure is not supported"));
+        }
+        VariableData::decode(resp_ci, &data)
+    }
+
+    /// Read all class 2 data of a slave
+    ///
+    /// Repeats REQ_UD2 while the slave announces more records, merging them
+    /// into a single result with the header of the first telegram.
+    pub fn read_data(&mut self, address: u8) -> Result<VariableData, MbusError> {
+        let mut data = self.req_ud2(address)?;
+
+        for _ in 1..MAX_TELEGRAMS {
+            if !data.more_records_follow {
+                break;
+            }
+
+            let next = self.req_ud2(address)?;
+            data.records.extend(next.records);
+            data.manufacturer_data.extend(next.manufacturer_data);
+            data.more_records_follow = next.more_records_follow;
+        }
+
+        Ok(data)
+    }
+
+    /// Select slaves matching `pattern` for `NETWORK_LAYER_ADDRESS`
+    ///
+    /// Deselects all other slaves. Waits the full response timeout to
+    /// detect collisions; no retries are made.
+    pub fn select(&mut self, pattern: &SecondaryPattern) -> Result<SelectResponse, MbusError> {
+        let frame = Frame::Long {
+            control: control::SND_UD | self.fcb_bit(NETWORK_LAYER_ADDRESS),
+            address: NETWORK_LAYER_ADDRESS,
+            ci: ci::SELECT_SLAVE,
+            data: pattern.to_bytes().to_vec(),
+        };
+
+        self.send(&frame.encode()?)?;
+
+        // collect every response within the timeout
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0u8; 64];
+        let mut received = Vec::new();
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            if wait == Duration::from_millis(0) {
+                break;
+            }
+            let n = self.port.read_timeout(&mut buf, wait)?;
+            if n == 0 {
+                break;
+            }
+            received.extend_from_slice(&buf[..n]);
+        }
+
+        Ok(match received.len() {
+            0 => SelectResponse::None,
+            1 if received[0] == ACK => {
+                self.toggle_fcb(NETWORK_LAYER_ADDRESS);
+                SelectResponse::Selected
+            }
+            _ => SelectResponse::Collision,
+        })
+    }
+
+    /// Select a single slave by its secondary address
+    pub fn select_secondary(&mut self, address: SecondaryAddress) -> Result<(), MbusError> {
+        match self.select(&address.into())? {
+            SelectResponse::Selected => Ok(()),
+            SelectResponse::None => Err(MbusError::Timeout),
+            SelectResponse::Collision => Err(MbusError::Collision),
+        }
+    }
+
+    /// Find all slaves by wildcard search on their secondary addresses
+    ///
+    /// Narrows down the identification number digit by digit wherever
+    /// several slaves respond, then reads each slave found to learn its
+    /// full address. Slaves sharing an identification number cannot be
+    /// told apart and are skipped, as are slaves whose response lacks the
+    /// long header carrying the address. Leaves the last slave found
+    /// selected.
+    pub fn search_secondary(&mut self) -> Result<Vec<SecondaryAddress>, MbusError> {
+        let mut found = Vec::new();
+        self.search(SecondaryPattern::ANY, 0, &mut found)?;
+        found.sort();
+        found.dedup();
+        Ok(found)
+    }
+
+    fn search(&mut self,
+              pattern: SecondaryPattern,
+              digit: usize,
+              found: &mut Vec<SecondaryAddress>)
+              -> Result<(), MbusError> {
+        match self.select(&pattern)? {
+            SelectResponse::None => (),
+            SelectResponse::Selected => {
+                // a response without long header or in an unsupported format
+                // must not end the search for the others
+                match self.req_ud2(NETWORK_LAYER_ADDRESS) {
+                    Ok(data) => {
+                        if let Some(address) = data.header.and_then(|header| header.address) {
+                            found.push(address);
+                        }
+                    }
+                    Err(MbusError::InvalidResponse(_)) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            SelectResponse::Collision if digit < pattern.digits.len() => {
+                for value in 0..10 {
+                    let mut narrowed = pattern;
+                    narrowed.digits[digit] = Some(value);
+                    self.search(narrowed, digit + 1, found)?;
+                }
+            }
+            SelectResponse::Collision => (),
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn fcb_bit(&self, address: u8) -> u8 {
+        if self.fcb[address as usize] { control::FCB } else { 0 }
+    }
+
+    #[inline]
+    fn toggle_fcb(&mut self, address: u8) {
+        self.fcb[address as usize] = !self.fcb[address as usize];
+    }
+
+    /// Send a request and receive the response, retrying as configured
+    fn transact(&mut self, request: &[u8]) -> Result<Frame, MbusError> {
+        let mut attempt = 0;
+
+        loop {
+            let rv = self.send(request)
+                .and_then(|_| self.receive())
+                .and_then(|resp| Frame::decode(&resp));
+
+            match rv {
+                Err(MbusError::Timeout) |
+                Err(MbusError::Checksum) if attempt < self.retries => attempt += 1,
+                rv => return rv,
+            }
+        }
+    }
+
+    fn send(&mut self, frame: &[u8]) -> Result<(), MbusError> {
+        self.port.discard_input()?;
+        self.port.write_all(frame)?;
+        self.port.drain()?;
+        Ok(())
+    }
+
+    /// Receive a frame
+    ///
+    /// Reads until the frame is complete according to its length, or the line
+    /// falls silent.
+    fn receive(&mut self) -> Result<Vec<u8>, MbusError> {
+        let mut buf = vec![0u8; MAX_USER_DATA_LEN + 8];
+        let mut len = self.port.read_timeout(&mut buf, self.timeout)?;
+
+        if len == 0 {
+            return Err(MbusError::Timeout);
+        }
+
+        while Frame::expected_len(&buf[..len]).is_none_or(|expected| len < expected) &&
+              len < buf.len() {
+            let n = self.port.read_timeout(&mut buf[len..], self.inter_char_timeout)?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+fn expect_ack(frame: Frame) -> Result<(), MbusError> {
+    match frame {
+        Frame::Ack => Ok(()),
+        _ => Err(MbusError::InvalidResponse("expected acknowledgement")),
+    }
+}
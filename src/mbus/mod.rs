@@ -0,0 +1,531 @@
+//! Wired M-Bus (EN 13757-2/-3)
+//!
+//! M-Bus connects utility meters to a master through a level converter,
+//! which often presents an RS485 or RS232 interface to the host. Frames are
+//! sent at 300 to 9600 baud, 8E1; 2400 baud is the most common rate.
+//!
+//! Slaves are addressed either by a primary address (1 to 250) or, after
+//! selecting them through the network layer address, by their secondary
+//! address. `records` decodes the variable data structure slaves respond
+//! with.
+
+use std::{error, fmt, io};
+use std::str::FromStr;
+
+use port::{Parity, PortSettings};
+
+pub mod master;
+pub mod records;
+
+pub use self::master::{MbusMaster, SelectResponse};
+pub use self::records::{DataRecord, Function, Quantity, Unit, Value, VariableData};
+
+/// Default baud rate
+pub const DEFAULT_BAUD_RATE: u32 = 2400;
+
+/// Single character acknowledgement
+pub const ACK: u8 = 0xe5;
+
+/// Address of unconfigured slaves
+pub const UNCONFIGURED_ADDRESS: u8 = 0;
+
+/// Highest primary address a slave may have
+pub const MAX_PRIMARY_ADDRESS: u8 = 250;
+
+/// Address of the slave selected by secondary address
+pub const NETWORK_LAYER_ADDRESS: u8 = 253;
+
+/// Broadcast address, all slaves reply
+///
+/// Only useful with a single slave on the bus.
+pub const BROADCAST_REPLY_ADDRESS: u8 = 254;
+
+/// Broadcast address, no slave replies
+pub const BROADCAST_ADDRESS: u8 = 255;
+
+/// Maximum size of the user data in a long frame, including the CI field
+pub const MAX_USER_DATA_LEN: usize = 253;
+
+const SHORT_START: u8 = 0x10;
+const LONG_START: u8 = 0x68;
+const STOP: u8 = 0x16;
+
+/// Control field values (EN 13757-2 clause 5.5)
+pub mod control {
+    /// Initialization of a slave, resets the frame count bit
+    pub const SND_NKE: u8 = 0x40;
+    /// Send user data to a slave
+    pub const SND_UD: u8 = 0x53;
+    /// Request class 2 data
+    pub const REQ_UD2: u8 = 0x5b;
+    /// Request class 1 (alarm) data
+    pub const REQ_UD1: u8 = 0x5a;
+    /// Response with user data; bits 4 and 5 are ACD and DFC
+    pub const RSP_UD: u8 = 0x08;
+    /// Frame count bit, toggled between successful transactions
+    pub const FCB: u8 = 0x20;
+    /// Access demand bit in responses, class 1 data is available
+    pub const ACD: u8 = 0x20;
+    /// Data flow control bit in responses, slave cannot accept further data
+    pub const DFC: u8 = 0x10;
+}
+
+/// Control information field values (EN 13757-3 clause 5)
+pub mod ci {
+    /// Application reset
+    pub const APPLICATION_RESET: u8 = 0x50;
+    /// Data send, user data in variable data structure
+    pub const DATA_SEND: u8 = 0x51;
+    /// Selection of a slave by secondary address
+    pub const SELECT_SLAVE: u8 = 0x52;
+    /// Variable data response with long header
+    pub const RESPONSE_LONG: u8 = 0x72;
+    /// Fixed data response
+    pub const RESPONSE_FIXED: u8 = 0x73;
+    /// Variable data response without header
+    pub const RESPONSE_NONE: u8 = 0x78;
+    /// Variable data response with short header
+    pub const RESPONSE_SHORT: u8 = 0x7a;
+}
+
+/// Line settings for M-Bus: 8 data bits, even parity, 1 stop bit
+pub fn line_settings(baud_rate: u32) -> PortSettings {
+    let mut settings = PortSettings::new();
+    settings.set_baud_rate(baud_rate).set_parity(Parity::Even);
+    settings
+}
+
+/// M-Bus frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// Single character acknowledgement
+    Ack,
+    /// Short frame, used for SND_NKE and requests
+    Short {
+        /// Control field
+        control: u8,
+        /// Primary address
+        address: u8,
+    },
+    /// Long frame with user data, or control frame if `data` is empty
+    Long {
+        /// Control field
+        control: u8,
+        /// Primary address
+        address: u8,
+        /// Control information field
+        ci: u8,
+        /// User data following the CI field
+        data: Vec<u8>,
+    },
+}
+
+impl Frame {
+    /// Encode into its wire representation
+    ///
+    /// Fails if the user data exceeds `MAX_USER_DATA_LEN`.
+    pub fn encode(&self) -> Result<Vec<u8>, MbusError> {
+        match *self {
+            Frame::Ack => Ok(vec![ACK]),
+            Frame::Short { control, address } => {
+                Ok(vec![SHORT_START, control, address, checksum(&[control, address]), STOP])
+            }
+            Frame::Long { control, address, ci, ref data } => {
+                if data.len() + 1 > MAX_USER_DATA_LEN {
+                    return Err(MbusError::InvalidRequest("user data too long"));
+                }
+
+                let len = (data.len() + 3) as u8;
+                let mut frame = Vec::with_capacity(data.len() + 9);
+                frame.extend_from_slice(&[LONG_START, len, len, LONG_START, control, address, ci]);
+                frame.extend_from_slice(data);
+                let sum = checksum(&frame[4..]);
+                frame.push(sum);
+                frame.push(STOP);
+
+                Ok(frame)
+            }
+        }
+    }
+
+    /// Decode a complete frame
+    pub fn decode(frame: &[u8]) -> Result<Frame, MbusError> {
+        match frame.first() {
+            Some(&ACK) if frame.len() == 1 => Ok(Frame::Ack),
+            Some(&SHORT_START) if frame.len() == 5 => {
+                if frame[4] != STOP {
+                    return Err(MbusError::InvalidResponse("missing stop character"));
+                }
+                if checksum(&frame[1..3]) != frame[3] {
+                    return Err(MbusError::Checksum);
+                }
+
+                Ok(Frame::Short {
+                    control: frame[1],
+                    address: frame[2],
+                })
+            }
+            Some(&LONG_START) if frame.len() >= 9 => {
+                let len = frame[1] as usize;
+                if frame[2] != frame[1] || frame[3] != LONG_START || len < 3 {
+                    return Err(MbusError::InvalidResponse("malformed long frame header"));
+                }
+                if frame.len() != len + 6 {
+                    return Err(MbusError::InvalidResponse("length mismatch"));
+                }
+                if frame[len + 5] != STOP {
+                    return Err(MbusError::InvalidResponse("missing stop character"));
+                }
+                if checksum(&frame[4..len + 4]) != frame[len + 4] {
+                    return Err(MbusError::Checksum);
+                }
+
+                Ok(Frame::Long {
+                    control: frame[4],
+                    address: frame[5],
+                    ci: frame[6],
+                    data: frame[7..len + 4].to_vec(),
+                })
+            }
+            _ => Err(MbusError::InvalidResponse("not a frame")),
+        }
+    }
+
+    /// Expected total length of the frame starting in `buf`
+    ///
+    /// Returns `None` if more data is needed to tell, or `buf` does not start
+    /// with a start character.
+    pub fn expected_len(buf: &[u8]) -> Option<usize> {
+        match buf.first() {
+            Some(&ACK) => Some(1),
+            Some(&SHORT_START) => Some(5),
+            Some(&LONG_START) if buf.len() >= 2 => Some(buf[1] as usize + 6),
+            _ => None,
+        }
+    }
+}
+
+/// Checksum of a frame: arithmetic sum of control field through user data
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Convert a three letter manufacturer code (EN 62056-21) into its id
+///
+/// Returns `None` unless `code` consists of three letters A to Z.
+pub fn manufacturer_id(code: &str) -> Option<u16> {
+    let bytes = code.as_bytes();
+    if bytes.len() != 3 || !bytes.iter().all(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    Some(bytes.iter().fold(0u16, |id, &b| id << 5 | (b.to_ascii_uppercase() - b'@') as u16))
+}
+
+/// Convert a manufacturer id into its three letter code
+pub fn manufacturer_code(id: u16) -> String {
+    (0..3)
+        .rev()
+        .map(|i| (((id >> (5 * i)) & 0x1f) as u8 + b'@') as char)
+        .collect()
+}
+
+/// Secondary address of a slave
+///
+/// Identifies a slave by its identification number, manufacturer, version and
+/// medium.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SecondaryAddress {
+    /// Identification number, 0 to 99999999
+    pub id: u32,
+    /// Manufacturer id, see `manufacturer_code`
+    pub manufacturer: u16,
+    /// Version or generation
+    pub version: u8,
+    /// Medium, e.g. 0x04 for heat or 0x07 for water
+    pub medium: u8,
+}
+
+impl SecondaryAddress {
+    /// Decode from its wire representation
+    ///
+    /// Fails if the identification number is not valid BCD.
+    pub fn from_bytes(bytes: [u8; 8]) -> Result<SecondaryAddress, MbusError> {
+        let id = bytes[..4].iter().rev().try_fold(0u32, |id, &b| {
+            if b >> 4 > 9 || b & 0xf > 9 {
+                return None;
+            }
+            Some(id * 100 + (b >> 4) as u32 * 10 + (b & 0xf) as u32)
+        });
+
+        Ok(SecondaryAddress {
+            id: id.ok_or(MbusError::InvalidResponse("identification number is not BCD"))?,
+            manufacturer: u16::from_le_bytes([bytes[4], bytes[5]]),
+            version: bytes[6],
+            medium: bytes[7],
+        })
+    }
+
+    /// Wire representation
+    pub fn to_bytes(self) -> [u8; 8] {
+        SecondaryPattern::from(self).to_bytes()
+    }
+
+    /// Three letter manufacturer code
+    #[inline]
+    pub fn manufacturer_code(self) -> String {
+        manufacturer_code(self.manufacturer)
+    }
+}
+
+impl fmt::Display for SecondaryAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{:08}{:04X}{:02X}{:02X}",
+               self.id,
+               self.manufacturer,
+               self.version,
+               self.medium)
+    }
+}
+
+/// Error parsing a secondary address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseSecondaryAddressError(String);
+
+impl fmt::Display for ParseSecondaryAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "invalid M-Bus secondary address {:?}, expected 16 characters \
+                iiiiiiiimmmmvvtt",
+               self.0)
+    }
+}
+
+impl error::Error for ParseSecondaryAddressError {}
+
+impl FromStr for SecondaryAddress {
+    type Err = ParseSecondaryAddressError;
+
+    /// Parse the form used by most tools: 8 decimal digits of identification
+    /// number, followed by manufacturer, version and medium in hex
+    fn from_str(s: &str) -> Result<SecondaryAddress, ParseSecondaryAddressError> {
+        let err = || ParseSecondaryAddressError(s.to_owned());
+        if s.len() != 16 || !s.is_ascii() || !s[..8].bytes().all(|b| b.is_ascii_digit()) {
+            return Err(err());
+        }
+
+        Ok(SecondaryAddress {
+            id: s[..8].parse().map_err(|_| err())?,
+            manufacturer: u16::from_str_radix(&s[8..12], 16).map_err(|_| err())?,
+            version: u8::from_str_radix(&s[12..14], 16).map_err(|_| err())?,
+            medium: u8::from_str_radix(&s[14..16], 16).map_err(|_| err())?,
+        })
+    }
+}
+
+/// Secondary address with wildcards, used for selection
+///
+/// `None` matches any value. Digits of the identification number are stored
+/// most significant first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SecondaryPattern {
+    /// Digits of the identification number
+    pub digits: [Option<u8>; 8],
+    /// Manufacturer id
+    pub manufacturer: Option<u16>,
+    /// Version
+    pub version: Option<u8>,
+    /// Medium
+    pub medium: Option<u8>,
+}
+
+impl SecondaryPattern {
+    /// Pattern matching all slaves
+    pub const ANY: SecondaryPattern = SecondaryPattern {
+        digits: [None; 8],
+        manufacturer: None,
+        version: None,
+        medium: None,
+    };
+
+    /// Wire representation, wildcards replaced by `F` nibbles and bytes
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+
+        for (i, pair) in self.digits.chunks(2).enumerate() {
+            let high = pair[0].map_or(0xf, |d| d & 0xf);
+            let low = pair[1].map_or(0xf, |d| d & 0xf);
+            bytes[3 - i] = high << 4 | low;
+        }
+
+        let manufacturer = self.manufacturer.unwrap_or(0xffff).to_le_bytes();
+        bytes[4] = manufacturer[0];
+        bytes[5] = manufacturer[1];
+        bytes[6] = self.version.unwrap_or(0xff);
+        bytes[7] = self.medium.unwrap_or(0xff);
+
+        bytes
+    }
+}
+
+impl Default for SecondaryPattern {
+    #[inline]
+    fn default() -> SecondaryPattern {
+        SecondaryPattern::ANY
+    }
+}
+
+impl From<SecondaryAddress> for SecondaryPattern {
+    fn from(address: SecondaryAddress) -> SecondaryPattern {
+        let mut digits = [None; 8];
+        let mut id = address.id;
+        for digit in digits.iter_mut().rev() {
+            *digit = Some((id % 10) as u8);
+            id /= 10;
+        }
+
+        SecondaryPattern {
+            digits,
+            manufacturer: Some(address.manufacturer),
+            version: Some(address.version),
+            medium: Some(address.medium),
+        }
+    }
+}
+
+/// Error during an M-Bus transaction
+#[derive(Debug)]
+pub enum MbusError {
+    /// Error on the underlying port
+    Io(io::Error),
+    /// No response was received in time
+    Timeout,
+    /// A frame with an invalid checksum was received
+    Checksum,
+    /// Several slaves responded at once
+    Collision,
+    /// The request cannot be encoded, e.g. because its data is too long
+    InvalidRequest(&'static str),
+    /// The response was malformed or did not match the request
+    InvalidResponse(&'static str),
+}
+
+impl fmt::Display for MbusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MbusError::Io(ref e) => write!(f, "I/O error: {}", e),
+            MbusError::Timeout => write!(f, "timeout waiting for response"),
+            MbusError::Checksum => write!(f, "checksum mismatch"),
+            MbusError::Collision => write!(f, "collision between several responses"),
+            MbusError::InvalidRequest(reason) => write!(f, "invalid request: {}", reason),
+            MbusError::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+        }
+    }
+}
+
+impl error::Error for MbusError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            MbusError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MbusError {
+    fn from(e: io::Error) -> MbusError {
+        if e.kind() == io::ErrorKind::TimedOut {
+            return MbusError::Timeout;
+        }
+
+        MbusError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RSP_UD of a water meter, the example from EN 1434-3
+    const EXAMPLE_RSP_UD: &[u8] = &[0x68, 0x1f, 0x1f, 0x68, 0x08, 0x02, 0x72, 0x78, 0x56,
+                                    0x34, 0x12, 0x24, 0x40, 0x01, 0x07, 0x55, 0x00, 0x00,
+                                    0x00, 0x03, 0x13, 0x15, 0x31, 0x00, 0xda, 0x02, 0x3b,
+                                    0x13, 0x01, 0x8b, 0x60, 0x04, 0x37, 0x18, 0x02, 0x18,
+                                    0x16];
+
+    #[test]
+    fn long_frame_checksum() {
+        assert_eq!(checksum(&EXAMPLE_RSP_UD[4..35]), 0x18);
+
+        let frame = Frame::decode(EXAMPLE_RSP_UD).unwrap();
+        match frame {
+            Frame::Long { control, address, ci, ref data } => {
+                assert_eq!(control, control::RSP_UD);
+                assert_eq!(address, 0x02);
+                assert_eq!(ci, ci::RESPONSE_LONG);
+                assert_eq!(data[..], EXAMPLE_RSP_UD[7..35]);
+            }
+            _ => panic!("expected long frame"),
+        }
+        assert_eq!(frame.encode().unwrap(), EXAMPLE_RSP_UD);
+
+        let mut corrupt = EXAMPLE_RSP_UD.to_vec();
+        corrupt[20] ^= 0x01;
+        assert!(matches!(Frame::decode(&corrupt), Err(MbusError::Checksum)));
+    }
+
+    #[test]
+    fn short_frame() {
+        // REQ_UD2 with FCB set to address 1
+        let frame = Frame::Short {
+            control: control::REQ_UD2 | control::FCB,
+            address: 0x01,
+        };
+        assert_eq!(frame.encode().unwrap(), [0x10, 0x7b, 0x01, 0x7c, 0x16]);
+        assert_eq!(Frame::decode(&[0x10, 0x7b, 0x01, 0x7c, 0x16]).unwrap(), frame);
+        assert!(matches!(Frame::decode(&[0x10, 0x7b, 0x01, 0x7d, 0x16]),
+                         Err(MbusError::Checksum)));
+    }
+
+    #[test]
+    fn expected_len() {
+        assert_eq!(Frame::expected_len(&[ACK]), Some(1));
+        assert_eq!(Frame::expected_len(&[0x10]), Some(5));
+        assert_eq!(Frame::expected_len(&EXAMPLE_RSP_UD[..1]), None);
+        assert_eq!(Frame::expected_len(&EXAMPLE_RSP_UD[..4]), Some(EXAMPLE_RSP_UD.len()));
+    }
+
+    #[test]
+    fn manufacturer() {
+        assert_eq!(manufacturer_id("PAD"), Some(0x4024));
+        assert_eq!(manufacturer_id("pad"), Some(0x4024));
+        assert_eq!(manufacturer_id("PA1"), None);
+        assert_eq!(manufacturer_code(0x4024), "PAD");
+    }
+
+    #[test]
+    fn secondary_address() {
+        let bytes = [0x78, 0x56, 0x34, 0x12, 0x24, 0x40, 0x01, 0x07];
+        let address = SecondaryAddress::from_bytes(bytes).unwrap();
+        assert_eq!(address.id, 12345678);
+        assert_eq!(address.manufacturer, 0x4024);
+        assert_eq!(address.version, 0x01);
+        assert_eq!(address.medium, 0x07);
+        assert_eq!(address.to_bytes(), bytes);
+        assert_eq!(address.to_string().parse::<SecondaryAddress>().unwrap(), address);
+
+        assert!(SecondaryAddress::from_bytes([0x7a, 0x56, 0x34, 0x12, 0x24, 0x40, 0x01, 0x07])
+            .is_err());
+    }
+
+    #[test]
+    fn secondary_pattern() {
+        assert_eq!(SecondaryPattern::ANY.to_bytes(), [0xff; 8]);
+
+        let mut pattern = SecondaryPattern::ANY;
+        pattern.digits[0] = Some(1);
+        pattern.digits[1] = Some(2);
+        assert_eq!(pattern.to_bytes(), [0xff, 0xff, 0xff, 0x12, 0xff, 0xff, 0xff, 0xff]);
+    }
+}
@@ -0,0 +1,884 @@
+//! Variable data structure (EN 13757-3)
+//!
+//! Responses with CI field `0x72`, `0x7a` or `0x78` consist of an optional
+//! header followed by data records. Each record starts with a data
+//! information block (DIF and DIFEs) describing the coding of the value and a
+//! value information block (VIF and VIFEs) describing what is measured, in
+//! which unit.
+//!
+//! Values are reported in base units with a decimal exponent, e.g. a VIF of
+//! MWh yields `Unit::WattHour` with an exponent increased by 6.
+
+use std::fmt;
+
+use super::{MbusError, SecondaryAddress, ci};
+
+// DIF values with special meaning
+const DIF_MANUFACTURER_DATA: u8 = 0x0f;
+const DIF_MORE_RECORDS_FOLLOW: u8 = 0x1f;
+const DIF_IDLE_FILLER: u8 = 0x2f;
+
+// VIF values selecting extension tables or special coding
+const VIF_PLAIN_TEXT: u8 = 0x7c;
+const VIF_TABLE_FB: u8 = 0x7b;
+const VIF_TABLE_FD: u8 = 0x7d;
+const VIF_ANY: u8 = 0x7e;
+const VIF_MANUFACTURER: u8 = 0x7f;
+
+// a DIB or VIB has at most 10 extension bytes
+const MAX_EXTENSIONS: usize = 10;
+
+/// Decoded variable data response
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariableData {
+    /// Header, if the CI field announced one
+    pub header: Option<Header>,
+    /// Data records
+    pub records: Vec<DataRecord>,
+    /// Manufacturer specific data following the records
+    pub manufacturer_data: Vec<u8>,
+    /// The slave has more records, to be read with another REQ_UD2
+    pub more_records_follow: bool,
+}
+
+/// Header of a variable data response
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// Secondary address, only present in the long header
+    pub address: Option<SecondaryAddress>,
+    /// Access number, incremented by the slave on every response
+    pub access_number: u8,
+    /// Status byte; bits 0 and 1 report application errors, bit 2 low
+    /// power and bit 3 a permanent error
+    pub status: u8,
+    /// Signature, 0 unless encryption is used
+    pub signature: u16,
+}
+
+/// Function field of a data record
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Function {
+    /// Instantaneous value
+    Instantaneous,
+    /// Maximum value
+    Maximum,
+    /// Minimum value
+    Minimum,
+    /// Value during error state
+    Error,
+}
+
+/// What a data record measures
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Quantity {
+    /// Energy
+    Energy,
+    /// Volume
+    Volume,
+    /// Mass
+    Mass,
+    /// Time the meter has been powered
+    OnTime,
+    /// Time the meter has been operating
+    OperatingTime,
+    /// Power
+    Power,
+    /// Volume flow
+    VolumeFlow,
+    /// Mass flow
+    MassFlow,
+    /// Flow temperature
+    FlowTemperature,
+    /// Return temperature
+    ReturnTemperature,
+    /// Difference between flow and return temperature
+    TemperatureDifference,
+    /// External temperature
+    ExternalTemperature,
+    /// Pressure
+    Pressure,
+    /// Date
+    Date,
+    /// Date and time
+    DateTime,
+    /// Units of a heat cost allocator
+    HcaUnits,
+    /// Averaging duration
+    AveragingDuration,
+    /// Age of the value
+    ActualityDuration,
+    /// Fabrication number
+    FabricationNumber,
+    /// (Enhanced) identification number
+    Identification,
+    /// Primary address
+    BusAddress,
+    /// Credit
+    Credit,
+    /// Debit
+    Debit,
+    /// Access number
+    AccessNumber,
+    /// Medium
+    Medium,
+    /// Manufacturer
+    Manufacturer,
+    /// Parameter set identification
+    ParameterSet,
+    /// Model or version
+    ModelVersion,
+    /// Hardware version
+    HardwareVersion,
+    /// Firmware version
+    FirmwareVersion,
+    /// Software version
+    SoftwareVersion,
+    /// Customer location
+    CustomerLocation,
+    /// Customer
+    Customer,
+    /// Device specific error flags
+    ErrorFlags,
+    /// Error mask
+    ErrorMask,
+    /// Digital output
+    DigitalOutput,
+    /// Digital input
+    DigitalInput,
+    /// Baud rate
+    BaudRate,
+    /// Response delay in bit times
+    ResponseDelay,
+    /// Number of retries
+    Retry,
+    /// Storage interval
+    StorageInterval,
+    /// Time since last readout
+    DurationSinceReadout,
+    /// Dimensionless value
+    Dimensionless,
+    /// Voltage
+    Voltage,
+    /// Current
+    Current,
+    /// Reset counter
+    ResetCounter,
+    /// Cumulation counter
+    CumulationCounter,
+    /// Operating time of the battery
+    OperatingTimeBattery,
+    /// Quantity given as text by the slave
+    PlainText(String),
+    /// Manufacturer specific VIF
+    ManufacturerSpecific,
+    /// Any VIF, only used in readout requests
+    Any,
+    /// VIF not known to this decoder, see `DataRecord::vib`
+    Unknown,
+}
+
+/// Unit of a data record
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Unit {
+    /// No unit
+    None,
+    /// Watt hours
+    WattHour,
+    /// Joules
+    Joule,
+    /// Cubic metres
+    CubicMetre,
+    /// Kilograms
+    Kilogram,
+    /// Seconds
+    Second,
+    /// Minutes
+    Minute,
+    /// Hours
+    Hour,
+    /// Days
+    Day,
+    /// Months
+    Month,
+    /// Years
+    Year,
+    /// Watts
+    Watt,
+    /// Joules per hour
+    JoulePerHour,
+    /// Cubic metres per hour
+    CubicMetrePerHour,
+    /// Cubic metres per minute
+    CubicMetrePerMinute,
+    /// Cubic metres per second
+    CubicMetrePerSecond,
+    /// Kilograms per hour
+    KilogramPerHour,
+    /// Degrees Celsius
+    Celsius,
+    /// Kelvin
+    Kelvin,
+    /// Bar
+    Bar,
+    /// Volts
+    Volt,
+    /// Amperes
+    Ampere,
+    /// Baud
+    Baud,
+    /// Nominal local legal currency units
+    Currency,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Unit::None => "",
+            Unit::WattHour => "Wh",
+            Unit::Joule => "J",
+            Unit::CubicMetre => "m³",
+            Unit::Kilogram => "kg",
+            Unit::Second => "s",
+            Unit::Minute => "min",
+            Unit::Hour => "h",
+            Unit::Day => "d",
+            Unit::Month => "month",
+            Unit::Year => "a",
+            Unit::Watt => "W",
+            Unit::JoulePerHour => "J/h",
+            Unit::CubicMetrePerHour => "m³/h",
+            Unit::CubicMetrePerMinute => "m³/min",
+            Unit::CubicMetrePerSecond => "m³/s",
+            Unit::KilogramPerHour => "kg/h",
+            Unit::Celsius => "°C",
+            Unit::Kelvin => "K",
+            Unit::Bar => "bar",
+            Unit::Volt => "V",
+            Unit::Ampere => "A",
+            Unit::Baud => "Bd",
+            Unit::Currency => "currency units",
+        })
+    }
+}
+
+/// Calendar date (type G)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Date {
+    /// Year
+    pub year: u16,
+    /// Month, 1 to 12
+    pub month: u8,
+    /// Day of month, 1 to 31
+    pub day: u8,
+}
+
+/// Date and time to the minute (type F)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DateTime {
+    /// Date
+    pub date: Date,
+    /// Hour, 0 to 23
+    pub hour: u8,
+    /// Minute, 0 to 59
+    pub minute: u8,
+    /// Daylight saving time is in effect
+    pub summer_time: bool,
+    /// The slave flagged the time as invalid
+    pub invalid: bool,
+}
+
+/// Value of a data record
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// No data
+    None,
+    /// Binary or BCD integer
+    Integer(i64),
+    /// 32 bit floating point value
+    Real(f32),
+    /// Text; M-Bus transmits it last character first, this is in reading
+    /// order
+    Text(String),
+    /// Date
+    Date(Date),
+    /// Date and time
+    DateTime(DateTime),
+    /// Data that could not be decoded, e.g. BCD with invalid digits
+    Bytes(Vec<u8>),
+}
+
+/// Data record of a variable data response
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataRecord {
+    /// Function
+    pub function: Function,
+    /// Storage number, 0 for the current value
+    pub storage: u64,
+    /// Tariff
+    pub tariff: u32,
+    /// Subunit (device)
+    pub subunit: u16,
+    /// What is measured
+    pub quantity: Quantity,
+    /// Unit of the value
+    pub unit: Unit,
+    /// Decimal exponent to apply to the value
+    pub exponent: i8,
+    /// Value
+    pub value: Value,
+    /// Raw data information block
+    pub dib: Vec<u8>,
+    /// Raw value information block
+    pub vib: Vec<u8>,
+}
+
+impl DataRecord {
+    /// Numeric value with the exponent applied
+    ///
+    /// Returns `None` for values that are not numbers.
+    pub fn scaled_value(&self) -> Option<f64> {
+        let value = match self.value {
+            Value::Integer(v) => v as f64,
+            Value::Real(v) => v as f64,
+            _ => return None,
+        };
+
+        Some(value * 10f64.powi(self.exponent as i32))
+    }
+}
+
+impl VariableData {
+    /// Decode the user data of a response with CI field `ci`
+    ///
+    /// `data` is the user data following the CI field.
+    pub fn decode(ci: u8, data: &[u8]) -> Result<VariableData, MbusError> {
+        let (header, offset) = match ci {
+            ci::RESPONSE_LONG => {
+                if data.len() < 12 {
+                    return Err(MbusError::InvalidResponse("truncated header"));
+                }
+                let mut address = [0u8; 8];
+                address.copy_from_slice(&data[..8]);
+                (Some(Header {
+                    address: Some(SecondaryAddress::from_bytes(address)?),
+                    access_number: data[8],
+                    status: data[9],
+                    signature: u16::from_le_bytes([data[10], data[11]]),
+                }),
+                 12)
+            }
+            ci::RESPONSE_SHORT => {
+                if data.len() < 4 {
+                    return Err(MbusError::InvalidResponse("truncated header"));
+                }
+                (Some(Header {
+                    address: None,
+                    access_number: data[0],
+                    status: data[1],
+                    signature: u16::from_le_bytes([data[2], data[3]]),
+                }),
+                 4)
+            }
+            ci::RESPONSE_NONE => (None, 0),
+            _ => return Err(MbusError::InvalidResponse("not a variable data response")),
+        };
+
+        let mut reader = Reader {
+            data,
+            pos: offset,
+        };
+        let mut rv = VariableData {
+            header,
+            records: Vec::new(),
+            manufacturer_data: Vec::new(),
+            more_records_follow: false,
+        };
+
+        while let Some(dif) = reader.peek() {
+            match dif {
+                DIF_IDLE_FILLER => reader.pos += 1,
+                DIF_MANUFACTURER_DATA | DIF_MORE_RECORDS_FOLLOW => {
+                    rv.manufacturer_data = data[reader.pos + 1..].to_vec();
+                    rv.more_records_follow = dif == DIF_MORE_RECORDS_FOLLOW;
+                    break;
+                }
+                _ => rv.records.push(reader.record()?),
+            }
+        }
+
+        Ok(rv)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    #[inline]
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).cloned()
+    }
+
+    fn byte(&mut self) -> Result<u8, MbusError> {
+        let b = self.peek().ok_or(MbusError::InvalidResponse("truncated data record"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], MbusError> {
+        if self.data.len() - self.pos < n {
+            return Err(MbusError::InvalidResponse("truncated data record"));
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    /// Read a byte and the extension bytes following it
+    fn block(&mut self) -> Result<&'a [u8], MbusError> {
+        let start = self.pos;
+        let mut b = self.byte()?;
+
+        while b & 0x80 != 0 {
+            if self.pos - start > MAX_EXTENSIONS {
+                return Err(MbusError::InvalidResponse("too many extension bytes"));
+            }
+            b = self.byte()?;
+        }
+
+        Ok(&self.data[start..self.pos])
+    }
+
+    fn record(&mut self) -> Result<DataRecord, MbusError> {
+        let dib = self.block()?;
+        let dif = dib[0];
+
+        if dif & 0x0f == 0x0f {
+            return Err(MbusError::InvalidResponse("unsupported special function DIF"));
+        }
+
+        let function = match (dif >> 4) & 0x03 {
+            0 => Function::Instantaneous,
+            1 => Function::Maximum,
+            2 => Function::Minimum,
+            _ => Function::Error,
+        };
+
+        let mut storage = ((dif >> 6) & 0x01) as u64;
+        let mut tariff = 0u32;
+        let mut subunit = 0u16;
+        for (i, &dife) in dib[1..].iter().enumerate() {
+            storage |= ((dife & 0x0f) as u64) << (1 + 4 * i);
+            tariff |= (((dife >> 4) & 0x03) as u32) << (2 * i);
+            subunit |= (((dife >> 6) & 0x01) as u16) << i;
+        }
+
+        let start = self.pos;
+        let vif = self.byte()?;
+        let (mut quantity, unit, mut exponent, mut extended) = match vif & 0x7f {
+            VIF_PLAIN_TEXT => (Quantity::Unknown, Unit::None, 0, vif & 0x80 != 0),
+            VIF_TABLE_FB | VIF_TABLE_FD => {
+                let code = self.byte()?;
+                let (quantity, unit, exponent) = if vif & 0x7f == VIF_TABLE_FD {
+                    table_fd(code & 0x7f)
+                } else {
+                    table_fb(code & 0x7f)
+                };
+                (quantity, unit, exponent, code & 0x80 != 0)
+            }
+            code => {
+                let (quantity, unit, exponent) = table_primary(code);
+                (quantity, unit, exponent, vif & 0x80 != 0)
+            }
+        };
+
+        // combinable extensions
+        while extended {
+            if self.pos - start > MAX_EXTENSIONS {
+                return Err(MbusError::InvalidResponse("too many extension bytes"));
+            }
+            let vife = self.byte()?;
+            if vife & 0x78 == 0x70 {
+                // multiplicative correction factor 10^(nnn-6)
+                exponent += (vife & 0x07) as i8 - 6;
+            }
+            extended = vife & 0x80 != 0;
+        }
+
+        if vif & 0x7f == VIF_PLAIN_TEXT {
+            let len = self.byte()? as usize;
+            quantity = Quantity::PlainText(text(self.bytes(len)?));
+        }
+
+        let vib = self.data[start..self.pos].to_vec();
+        let value = self.value(dif & 0x0f, &quantity)?;
+
+        Ok(DataRecord {
+            function,
+            storage,
+            tariff,
+            subunit,
+            quantity,
+            unit,
+            exponent,
+            value,
+            dib: dib.to_vec(),
+            vib,
+        })
+    }
+
+    fn value(&mut self, coding: u8, quantity: &Quantity) -> Result<Value, MbusError> {
+        Ok(match coding {
+            0x00 | 0x08 => Value::None,
+            0x02 if *quantity == Quantity::Date => date(self.bytes(2)?),
+            0x04 if *quantity == Quantity::DateTime => date_time(self.bytes(4)?),
+            0x01..=0x04 => integer(self.bytes(coding as usize)?),
+            0x05 => {
+                let b = self.bytes(4)?;
+                Value::Real(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            }
+            0x06 => integer(self.bytes(6)?),
+            0x07 => integer(self.bytes(8)?),
+            0x09..=0x0c => bcd(self.bytes((coding - 0x08) as usize)?),
+            0x0e => bcd(self.bytes(6)?),
+            0x0d => {
+                let lvar = self.byte()?;
+                match lvar {
+                    0x00..=0xbf => Value::Text(text(self.bytes(lvar as usize)?)),
+                    0xc0..=0xc9 => bcd(self.bytes((lvar - 0xc0) as usize)?),
+                    0xd0..=0xd9 => match bcd(self.bytes((lvar - 0xd0) as usize)?) {
+                        Value::Integer(v) => Value::Integer(-v),
+                        other => other,
+                    },
+                    0xe0..=0xef => integer(self.bytes((lvar - 0xe0) as usize)?),
+                    0xf0..=0xf4 => Value::Bytes(self.bytes(4 * (lvar - 0xec) as usize)?.to_vec()),
+                    0xf5 => Value::Bytes(self.bytes(48)?.to_vec()),
+                    0xf6 => Value::Bytes(self.bytes(64)?.to_vec()),
+                    _ => return Err(MbusError::InvalidResponse("reserved LVAR value")),
+                }
+            }
+            _ => unreachable!(),
+        })
+    }
+}
+
+/// Little endian two's complement integer of up to 8 bytes
+fn integer(bytes: &[u8]) -> Value {
+    if bytes.is_empty() || bytes.len() > 8 {
+        return Value::Bytes(bytes.to_vec());
+    }
+
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    let shift = 64 - 8 * bytes.len() as u32;
+
+    Value::Integer((i64::from_le_bytes(buf) << shift) >> shift)
+}
+
+/// BCD integer, least significant byte first; a high nibble of `F` in the
+/// last byte marks a negative value
+fn bcd(bytes: &[u8]) -> Value {
+    let mut value = 0i64;
+    let mut negative = false;
+
+    for (i, &b) in bytes.iter().enumerate().rev() {
+        let mut high = b >> 4;
+        if i == bytes.len() - 1 && high == 0x0f {
+            negative = true;
+            high = 0;
+        }
+        if high > 9 || b & 0x0f > 9 || value > i64::MAX / 100 {
+            return Value::Bytes(bytes.to_vec());
+        }
+        value = value * 100 + (high * 10 + (b & 0x0f)) as i64;
+    }
+
+    Value::Integer(if negative { -value } else { value })
+}
+
+/// Text transmitted last character first
+fn text(bytes: &[u8]) -> String {
+    bytes.iter().rev().map(|&b| b as char).collect()
+}
+
+/// Compound date (type G)
+fn date(b: &[u8]) -> Value {
+    Value::Date(Date {
+        year: year(b[0] >> 5 | (b[1] & 0xf0) >> 1),
+        month: b[1] & 0x0f,
+        day: b[0] & 0x1f,
+    })
+}
+
+/// Compound date and time (type F)
+fn date_time(b: &[u8]) -> Value {
+    Value::DateTime(DateTime {
+        date: Date {
+            year: year(b[2] >> 5 | (b[3] & 0xf0) >> 1),
+            month: b[3] & 0x0f,
+            day: b[2] & 0x1f,
+        },
+        hour: b[1] & 0x1f,
+        minute: b[0] & 0x3f,
+        summer_time: b[1] & 0x80 != 0,
+        invalid: b[0] & 0x80 != 0,
+    })
+}
+
+/// Two digit year of types F and G; values above 80 are in the 20th century
+fn year(year: u8) -> u16 {
+    if year > 80 { 1900 + year as u16 } else { 2000 + year as u16 }
+}
+
+/// Unit of a duration coded in the last two bits of a VIF
+fn duration_unit(code: u8) -> Unit {
+    match code & 0x03 {
+        0 => Unit::Second,
+        1 => Unit::Minute,
+        2 => Unit::Hour,
+        _ => Unit::Day,
+    }
+}
+
+/// Unit of a long duration coded in the last two bits of a VIFE
+fn long_duration_unit(code: u8) -> Unit {
+    match code & 0x03 {
+        0 => Unit::Hour,
+        1 => Unit::Day,
+        2 => Unit::Month,
+        _ => Unit::Year,
+    }
+}
+
+/// Primary VIF table (EN 13757-3 table 10)
+fn table_primary(vif: u8) -> (Quantity, Unit, i8) {
+    let n = (vif & 0x07) as i8;
+    let nn = (vif & 0x03) as i8;
+
+    match vif {
+        0x00..=0x07 => (Quantity::Energy, Unit::WattHour, n - 3),
+        0x08..=0x0f => (Quantity::Energy, Unit::Joule, n),
+        0x10..=0x17 => (Quantity::Volume, Unit::CubicMetre, n - 6),
+        0x18..=0x1f => (Quantity::Mass, Unit::Kilogram, n - 3),
+        0x20..=0x23 => (Quantity::OnTime, duration_unit(vif), 0),
+        0x24..=0x27 => (Quantity::OperatingTime, duration_unit(vif), 0),
+        0x28..=0x2f => (Quantity::Power, Unit::Watt, n - 3),
+        0x30..=0x37 => (Quantity::Power, Unit::JoulePerHour, n),
+        0x38..=0x3f => (Quantity::VolumeFlow, Unit::CubicMetrePerHour, n - 6),
+        0x40..=0x47 => (Quantity::VolumeFlow, Unit::CubicMetrePerMinute, n - 7),
+        0x48..=0x4f => (Quantity::VolumeFlow, Unit::CubicMetrePerSecond, n - 9),
+        0x50..=0x57 => (Quantity::MassFlow, Unit::KilogramPerHour, n - 3),
+        0x58..=0x5b => (Quantity::FlowTemperature, Unit::Celsius, nn - 3),
+        0x5c..=0x5f => (Quantity::ReturnTemperature, Unit::Celsius, nn - 3),
+        0x60..=0x63 => (Quantity::TemperatureDifference, Unit::Kelvin, nn - 3),
+        0x64..=0x67 => (Quantity::ExternalTemperature, Unit::Celsius, nn - 3),
+        0x68..=0x6b => (Quantity::Pressure, Unit::Bar, nn - 3),
+        0x6c => (Quantity::Date, Unit::None, 0),
+        0x6d => (Quantity::DateTime, Unit::None, 0),
+        0x6e => (Quantity::HcaUnits, Unit::None, 0),
+        0x70..=0x73 => (Quantity::AveragingDuration, duration_unit(vif), 0),
+        0x74..=0x77 => (Quantity::ActualityDuration, duration_unit(vif), 0),
+        0x78 => (Quantity::FabricationNumber, Unit::None, 0),
+        0x79 => (Quantity::Identification, Unit::None, 0),
+        0x7a => (Quantity::BusAddress, Unit::None, 0),
+        VIF_ANY => (Quantity::Any, Unit::None, 0),
+        VIF_MANUFACTURER => (Quantity::ManufacturerSpecific, Unit::None, 0),
+        _ => (Quantity::Unknown, Unit::None, 0),
+    }
+}
+
+/// First extension table, VIF `0xfd` (EN 13757-3 table 14)
+fn table_fd(vife: u8) -> (Quantity, Unit, i8) {
+    let nn = (vife & 0x03) as i8;
+    let nnnn = (vife & 0x0f) as i8;
+
+    match vife {
+        0x00..=0x03 => (Quantity::Credit, Unit::Currency, nn - 3),
+        0x04..=0x07 => (Quantity::Debit, Unit::Currency, nn - 3),
+        0x08 => (Quantity::AccessNumber, Unit::None, 0),
+        0x09 => (Quantity::Medium, Unit::None, 0),
+        0x0a => (Quantity::Manufacturer, Unit::None, 0),
+        0x0b => (Quantity::ParameterSet, Unit::None, 0),
+        0x0c => (Quantity::ModelVersion, Unit::None, 0),
+        0x0d => (Quantity::HardwareVersion, Unit::None, 0),
+        0x0e => (Quantity::FirmwareVersion, Unit::None, 0),
+        0x0f => (Quantity::SoftwareVersion, Unit::None, 0),
+        0x10 => (Quantity::CustomerLocation, Unit::None, 0),
+        0x11 => (Quantity::Customer, Unit::None, 0),
+        0x17 => (Quantity::ErrorFlags, Unit::None, 0),
+        0x18 => (Quantity::ErrorMask, Unit::None, 0),
+        0x1a => (Quantity::DigitalOutput, Unit::None, 0),
+        0x1b => (Quantity::DigitalInput, Unit::None, 0),
+        0x1c => (Quantity::BaudRate, Unit::Baud, 0),
+        0x1d => (Quantity::ResponseDelay, Unit::None, 0),
+        0x1e => (Quantity::Retry, Unit::None, 0),
+        0x24..=0x27 => (Quantity::StorageInterval, duration_unit(vife), 0),
+        0x28 => (Quantity::StorageInterval, Unit::Month, 0),
+        0x29 => (Quantity::StorageInterval, Unit::Year, 0),
+        0x2c..=0x2f => (Quantity::DurationSinceReadout, duration_unit(vife), 0),
+        0x3a => (Quantity::Dimensionless, Unit::None, 0),
+        0x40..=0x4f => (Quantity::Voltage, Unit::Volt, nnnn - 9),
+        0x50..=0x5f => (Quantity::Current, Unit::Ampere, nnnn - 12),
+        0x60 => (Quantity::ResetCounter, Unit::None, 0),
+        0x61 => (Quantity::CumulationCounter, Unit::None, 0),
+        0x6c..=0x6f => (Quantity::OperatingTimeBattery, long_duration_unit(vife), 0),
+        _ => (Quantity::Unknown, Unit::None, 0),
+    }
+}
+
+/// Second extension table, VIF `0xfb` (EN 13757-3 table 12)
+fn table_fb(vife: u8) -> (Quantity, Unit, i8) {
+    let n = (vife & 0x01) as i8;
+
+    match vife {
+        0x00 | 0x01 => (Quantity::Energy, Unit::WattHour, n + 5),
+        0x08 | 0x09 => (Quantity::Energy, Unit::Joule, n + 8),
+        0x10 | 0x11 => (Quantity::Volume, Unit::CubicMetre, n + 2),
+        0x18 | 0x19 => (Quantity::Mass, Unit::Kilogram, n + 5),
+        0x28 | 0x29 => (Quantity::Power, Unit::Watt, n + 5),
+        0x30 | 0x31 => (Quantity::Power, Unit::JoulePerHour, n + 8),
+        _ => (Quantity::Unknown, Unit::None, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(data: &[u8]) -> DataRecord {
+        let mut rv = VariableData::decode(ci::RESPONSE_NONE, data).unwrap();
+        assert_eq!(rv.records.len(), 1);
+        rv.records.remove(0)
+    }
+
+    #[test]
+    fn example_rsp_ud() {
+        // user data of the EN 1434-3 example frame, after the CI field
+        let data = [0x78, 0x56, 0x34, 0x12, 0x24, 0x40, 0x01, 0x07, 0x55, 0x00, 0x00, 0x00,
+                    0x03, 0x13, 0x15, 0x31, 0x00, 0xda, 0x02, 0x3b, 0x13, 0x01, 0x8b, 0x60,
+                    0x04, 0x37, 0x18, 0x02];
+        let rv = VariableData::decode(ci::RESPONSE_LONG, &data).unwrap();
+
+        let header = rv.header.unwrap();
+        let address = header.address.unwrap();
+        assert_eq!(address.id, 12345678);
+        assert_eq!(address.manufacturer, 0x4024);
+        assert_eq!(address.version, 0x01);
+        assert_eq!(address.medium, 0x07);
+        assert_eq!(header.access_number, 0x55);
+        assert_eq!(header.status, 0x00);
+        assert_eq!(header.signature, 0x0000);
+        assert!(rv.manufacturer_data.is_empty());
+        assert!(!rv.more_records_follow);
+
+        assert_eq!(rv.records.len(), 3);
+
+        // 12.565 m³, 24 bit integer
+        let volume = &rv.records[0];
+        assert_eq!(volume.function, Function::Instantaneous);
+        assert_eq!(volume.storage, 0);
+        assert_eq!(volume.quantity, Quantity::Volume);
+        assert_eq!(volume.unit, Unit::CubicMetre);
+        assert_eq!(volume.exponent, -3);
+        assert_eq!(volume.value, Value::Integer(12565));
+        assert_eq!(volume.dib, [0x03]);
+        assert_eq!(volume.vib, [0x13]);
+
+        // maximum flow of 0.113 m³/h in storage 5, 4 digit BCD
+        let flow = &rv.records[1];
+        assert_eq!(flow.function, Function::Maximum);
+        assert_eq!(flow.storage, 5);
+        assert_eq!(flow.tariff, 0);
+        assert_eq!(flow.subunit, 0);
+        assert_eq!(flow.quantity, Quantity::VolumeFlow);
+        assert_eq!(flow.unit, Unit::CubicMetrePerHour);
+        assert_eq!(flow.exponent, -3);
+        assert_eq!(flow.value, Value::Integer(113));
+        assert_eq!(flow.dib, [0xda, 0x02]);
+
+        // 218.37 kWh on tariff 2 of subunit 1, 6 digit BCD
+        let energy = &rv.records[2];
+        assert_eq!(energy.function, Function::Instantaneous);
+        assert_eq!(energy.storage, 0);
+        assert_eq!(energy.tariff, 2);
+        assert_eq!(energy.subunit, 1);
+        assert_eq!(energy.quantity, Quantity::Energy);
+        assert_eq!(energy.unit, Unit::WattHour);
+        assert_eq!(energy.exponent, 1);
+        assert_eq!(energy.value, Value::Integer(21837));
+        assert_eq!(energy.scaled_value(), Some(218370.0));
+    }
+
+    #[test]
+    fn date_type_g() {
+        let rv = record(&[0x42, 0x6c, 0x7f, 0x2c]);
+        assert_eq!(rv.storage, 1);
+        assert_eq!(rv.quantity, Quantity::Date);
+        assert_eq!(rv.value,
+                   Value::Date(Date {
+                       year: 2019,
+                       month: 12,
+                       day: 31,
+                   }));
+        assert_eq!(rv.scaled_value(), None);
+    }
+
+    #[test]
+    fn date_time_type_f() {
+        let rv = record(&[0x04, 0x6d, 0x1e, 0x0f, 0x7f, 0x2c]);
+        assert_eq!(rv.quantity, Quantity::DateTime);
+        assert_eq!(rv.value,
+                   Value::DateTime(DateTime {
+                       date: Date {
+                           year: 2019,
+                           month: 12,
+                           day: 31,
+                       },
+                       hour: 15,
+                       minute: 30,
+                       summer_time: false,
+                       invalid: false,
+                   }));
+
+        let rv = record(&[0x04, 0x6d, 0x9e, 0x8f, 0x7f, 0x2c]);
+        match rv.value {
+            Value::DateTime(dt) => {
+                assert!(dt.summer_time);
+                assert!(dt.invalid);
+            }
+            ref v => panic!("unexpected value {:?}", v),
+        }
+    }
+
+    #[test]
+    fn operating_time_battery() {
+        let rv = record(&[0x02, 0xfd, 0x6c, 0x05, 0x00]);
+        assert_eq!(rv.quantity, Quantity::OperatingTimeBattery);
+        assert_eq!(rv.unit, Unit::Hour);
+        assert_eq!(rv.value, Value::Integer(5));
+        assert_eq!(rv.vib, [0xfd, 0x6c]);
+
+        assert_eq!(record(&[0x02, 0xfd, 0x6f, 0x05, 0x00]).unit, Unit::Year);
+    }
+
+    #[test]
+    fn negative_values() {
+        assert_eq!(record(&[0x02, 0x13, 0xfe, 0xff]).value, Value::Integer(-2));
+        assert_eq!(record(&[0x0a, 0x13, 0x34, 0xf2]).value, Value::Integer(-234));
+        assert_eq!(record(&[0x0a, 0x13, 0x3a, 0x12]).value,
+                   Value::Bytes(vec![0x3a, 0x12]));
+    }
+
+    #[test]
+    fn idle_filler_and_manufacturer_data() {
+        let rv = VariableData::decode(ci::RESPONSE_NONE,
+                                      &[0x2f, 0x01, 0x13, 0x07, 0x2f, 0x1f, 0xaa, 0xbb])
+            .unwrap();
+        assert_eq!(rv.records.len(), 1);
+        assert_eq!(rv.records[0].value, Value::Integer(7));
+        assert_eq!(rv.manufacturer_data, [0xaa, 0xbb]);
+        assert!(rv.more_records_follow);
+    }
+}
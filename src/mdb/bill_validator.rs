@@ -0,0 +1,222 @@
+//! Bill validator (address `0x30`)
+
+use super::MdbError;
+
+/// Commands specific to bill validators, combined with
+/// `address::BILL_VALIDATOR`
+pub mod command {
+    /// Set security level per bill type
+    pub const SECURITY: u8 = 0x02;
+    /// Enable bill types for acceptance and escrow
+    pub const BILL_TYPE: u8 = 0x04;
+    /// Stack or return the bill in escrow
+    pub const ESCROW: u8 = 0x05;
+    /// Request stacker status
+    pub const STACKER: u8 = 0x06;
+    /// Level 2 expansion commands
+    pub const EXPANSION: u8 = 0x07;
+}
+
+/// Configuration of a bill validator, as returned by SETUP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Setup {
+    /// Feature level, 1 or 2
+    pub feature_level: u8,
+    /// Currency code, e.g. `0x1978` for euros (ISO 4217 prefixed by 1)
+    pub currency_code: u16,
+    /// Value of one credit unit in the smallest currency unit
+    pub scaling_factor: u16,
+    /// Decimal places of the currency
+    pub decimal_places: u8,
+    /// Number of bills the stacker holds
+    pub stacker_capacity: u16,
+    /// Bill types accepted at high security, bit 0 for type 0
+    pub security_levels: u16,
+    /// Whether bills can be held in escrow
+    pub escrow: bool,
+    /// Value of each bill type in credit units
+    pub bill_credits: Vec<u8>,
+}
+
+impl Setup {
+    /// Decode a SETUP response
+    pub fn decode(data: &[u8]) -> Result<Setup, MdbError> {
+        if data.len() < 11 {
+            return Err(MdbError::InvalidResponse("truncated bill validator setup"));
+        }
+
+        Ok(Setup {
+            feature_level: data[0],
+            currency_code: u16::from_be_bytes([data[1], data[2]]),
+            scaling_factor: u16::from_be_bytes([data[3], data[4]]),
+            decimal_places: data[5],
+            stacker_capacity: u16::from_be_bytes([data[6], data[7]]),
+            security_levels: u16::from_be_bytes([data[8], data[9]]),
+            escrow: data[10] == 0xff,
+            bill_credits: data[11..].to_vec(),
+        })
+    }
+}
+
+/// What happened to a bill
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BillRouting {
+    /// Bill stacked
+    Stacked,
+    /// Bill held in escrow, waiting for ESCROW
+    EscrowPosition,
+    /// Bill returned to the customer
+    Returned,
+    /// Bill sent to the recycler
+    ToRecycler,
+    /// Bill of a disabled type rejected
+    DisabledRejected,
+    /// Bill sent to the recycler by manual fill
+    ToRecyclerManualFill,
+    /// Bill dispensed manually
+    ManualDispense,
+    /// Bill transferred from the recycler to the cash box
+    RecyclerToCashBox,
+}
+
+/// Activity reported in response to POLL
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Event {
+    /// Bill accepted, moved or returned
+    Bill {
+        /// Bill type
+        bill_type: u8,
+        /// What happened to the bill
+        routing: BillRouting,
+    },
+    /// Attempts to insert a bill while the validator was disabled
+    DisabledAttempts(u8),
+    /// Motor defective (0x01)
+    DefectiveMotor,
+    /// Sensor problem (0x02)
+    SensorProblem,
+    /// Validator busy (0x03)
+    Busy,
+    /// ROM checksum error (0x04)
+    RomChecksumError,
+    /// Validator jammed (0x05)
+    Jammed,
+    /// Validator was reset (0x06)
+    JustReset,
+    /// Bill removed from escrow (0x07)
+    BillRemoved,
+    /// Cash box out of position (0x08)
+    CashBoxOutOfPosition,
+    /// Validator disabled (0x09)
+    Disabled,
+    /// ESCROW received without a bill in escrow (0x0A)
+    InvalidEscrowRequest,
+    /// Unknown bill rejected (0x0B)
+    BillRejected,
+    /// Credited bill possibly removed (0x0C)
+    PossibleCreditedBillRemoval,
+    /// Status code not known to this decoder
+    Status(u8),
+}
+
+/// Decode the data of a POLL response into events
+pub fn decode_poll(data: &[u8]) -> Vec<Event> {
+    data.iter()
+        .map(|&b| if b & 0x80 != 0 {
+            let routing = match (b >> 4) & 0x07 {
+                0 => BillRouting::Stacked,
+                1 => BillRouting::EscrowPosition,
+                2 => BillRouting::Returned,
+                3 => BillRouting::ToRecycler,
+                4 => BillRouting::DisabledRejected,
+                5 => BillRouting::ToRecyclerManualFill,
+                6 => BillRouting::ManualDispense,
+                _ => BillRouting::RecyclerToCashBox,
+            };
+            Event::Bill {
+                bill_type: b & 0x0f,
+                routing,
+            }
+        } else if b & 0xe0 == 0x40 {
+            Event::DisabledAttempts(b & 0x1f)
+        } else {
+            match b {
+                0x01 => Event::DefectiveMotor,
+                0x02 => Event::SensorProblem,
+                0x03 => Event::Busy,
+                0x04 => Event::RomChecksumError,
+                0x05 => Event::Jammed,
+                0x06 => Event::JustReset,
+                0x07 => Event::BillRemoved,
+                0x08 => Event::CashBoxOutOfPosition,
+                0x09 => Event::Disabled,
+                0x0a => Event::InvalidEscrowRequest,
+                0x0b => Event::BillRejected,
+                0x0c => Event::PossibleCreditedBillRemoval,
+                code => Event::Status(code),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_bills() {
+        // 1yyyxxxx: bill type 1 in escrow, then stacked
+        assert_eq!(decode_poll(&[0x91, 0x81]),
+                   [Event::Bill {
+                        bill_type: 1,
+                        routing: BillRouting::EscrowPosition,
+                    },
+                    Event::Bill {
+                        bill_type: 1,
+                        routing: BillRouting::Stacked,
+                    }]);
+        assert_eq!(decode_poll(&[0xa2, 0xc3, 0xf4]),
+                   [Event::Bill {
+                        bill_type: 2,
+                        routing: BillRouting::Returned,
+                    },
+                    Event::Bill {
+                        bill_type: 3,
+                        routing: BillRouting::DisabledRejected,
+                    },
+                    Event::Bill {
+                        bill_type: 4,
+                        routing: BillRouting::RecyclerToCashBox,
+                    }]);
+    }
+
+    #[test]
+    fn poll_status() {
+        // 010xxxxx: attempts while disabled
+        assert_eq!(decode_poll(&[0x06, 0x43, 0x09, 0x0b, 0x21]),
+                   [Event::JustReset,
+                    Event::DisabledAttempts(3),
+                    Event::Disabled,
+                    Event::BillRejected,
+                    Event::Status(0x21)]);
+    }
+
+    #[test]
+    fn setup() {
+        // level 1, euros, 1 euro per credit unit, 500 bills, escrow
+        let data = [0x01, 0x19, 0x78, 0x00, 0x64, 0x02, 0x01, 0xf4, 0x00, 0x0c, 0xff, 0x05,
+                    0x0a, 0x14, 0x32];
+        assert_eq!(Setup::decode(&data).unwrap(),
+                   Setup {
+                       feature_level: 1,
+                       currency_code: 0x1978,
+                       scaling_factor: 100,
+                       decimal_places: 2,
+                       stacker_capacity: 500,
+                       security_levels: 0x000c,
+                       escrow: true,
+                       bill_credits: vec![0x05, 0x0a, 0x14, 0x32],
+                   });
+        assert!(matches!(Setup::decode(&data[..10]), Err(MdbError::InvalidResponse(_))));
+    }
+}
@@ -0,0 +1,245 @@
+//! Coin changer (address `0x08`)
+
+use super::MdbError;
+
+/// Commands specific to coin changers, combined with `address::CHANGER`
+pub mod command {
+    /// Request tube fill levels
+    pub const TUBE_STATUS: u8 = 0x02;
+    /// Enable coin types for acceptance and manual dispense
+    pub const COIN_TYPE: u8 = 0x04;
+    /// Dispense coins
+    pub const DISPENSE: u8 = 0x05;
+    /// Level 3 expansion commands
+    pub const EXPANSION: u8 = 0x07;
+}
+
+/// Configuration of a coin changer, as returned by SETUP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Setup {
+    /// Feature level, 2 or 3
+    pub feature_level: u8,
+    /// Currency code, e.g. `0x1978` for euros (ISO 4217 prefixed by 1)
+    pub currency_code: u16,
+    /// Value of one credit unit in the smallest currency unit
+    pub scaling_factor: u8,
+    /// Decimal places of the currency
+    pub decimal_places: u8,
+    /// Coin types that can be routed to the tubes, bit 0 for type 0
+    pub coin_routing: u16,
+    /// Value of each coin type in credit units; `0xff` marks a token
+    pub coin_credits: Vec<u8>,
+}
+
+impl Setup {
+    /// Decode a SETUP response
+    pub fn decode(data: &[u8]) -> Result<Setup, MdbError> {
+        if data.len() < 7 {
+            return Err(MdbError::InvalidResponse("truncated changer setup"));
+        }
+
+        Ok(Setup {
+            feature_level: data[0],
+            currency_code: u16::from_be_bytes([data[1], data[2]]),
+            scaling_factor: data[3],
+            decimal_places: data[4],
+            coin_routing: u16::from_be_bytes([data[5], data[6]]),
+            coin_credits: data[7..].to_vec(),
+        })
+    }
+}
+
+/// Where a deposited coin went
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CoinRouting {
+    /// Cash box
+    CashBox,
+    /// Change tube
+    Tube,
+    /// Not used (`10`)
+    Unused,
+    /// Rejected
+    Reject,
+}
+
+/// Activity reported in response to POLL
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Event {
+    /// Coin deposited
+    CoinDeposited {
+        /// Coin type
+        coin_type: u8,
+        /// Where the coin went
+        routing: CoinRouting,
+        /// Coins in the tube of this type afterwards
+        tube_count: u8,
+    },
+    /// Coins dispensed manually through the changer's buttons
+    CoinsDispensed {
+        /// Coin type
+        coin_type: u8,
+        /// Number of coins dispensed
+        count: u8,
+        /// Coins in the tube of this type afterwards
+        tube_count: u8,
+    },
+    /// Slugs (unrecognized coins) deposited since the last poll
+    Slugs(u8),
+    /// Escrow return lever pressed (0x01)
+    EscrowRequest,
+    /// Changer is paying out (0x02)
+    PayoutBusy,
+    /// A credited coin was not detected by the validator (0x03)
+    NoCredit,
+    /// Tube sensor defective (0x04)
+    DefectiveTubeSensor,
+    /// Two coins arrived too close together (0x05)
+    DoubleArrival,
+    /// Acceptor unplugged (0x06)
+    AcceptorUnplugged,
+    /// Tube jammed during dispense (0x07)
+    TubeJam,
+    /// ROM checksum error (0x08)
+    RomChecksumError,
+    /// Coin went to the wrong destination (0x09)
+    CoinRoutingError,
+    /// Changer busy, cannot answer a detailed command (0x0A)
+    Busy,
+    /// Changer was reset (0x0B)
+    JustReset,
+    /// Coin jam in the acceptance path (0x0C)
+    CoinJam,
+    /// Credited coin possibly removed (0x0D)
+    PossibleCreditedCoinRemoval,
+    /// Status code not known to this decoder
+    Status(u8),
+}
+
+/// Decode the data of a POLL response into events
+///
+/// A truncated two byte event at the end is dropped.
+pub fn decode_poll(data: &[u8]) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut iter = data.iter().cloned();
+
+    while let Some(b) = iter.next() {
+        let event = if b & 0x80 != 0 {
+            match iter.next() {
+                Some(tube_count) => {
+                    Event::CoinsDispensed {
+                        coin_type: b & 0x0f,
+                        count: (b >> 4) & 0x07,
+                        tube_count,
+                    }
+                }
+                None => break,
+            }
+        } else if b & 0x40 != 0 {
+            let routing = match (b >> 4) & 0x03 {
+                0 => CoinRouting::CashBox,
+                1 => CoinRouting::Tube,
+                2 => CoinRouting::Unused,
+                _ => CoinRouting::Reject,
+            };
+            match iter.next() {
+                Some(tube_count) => {
+                    Event::CoinDeposited {
+                        coin_type: b & 0x0f,
+                        routing,
+                        tube_count,
+                    }
+                }
+                None => break,
+            }
+        } else if b & 0x20 != 0 {
+            Event::Slugs(b & 0x1f)
+        } else {
+            match b {
+                0x01 => Event::EscrowRequest,
+                0x02 => Event::PayoutBusy,
+                0x03 => Event::NoCredit,
+                0x04 => Event::DefectiveTubeSensor,
+                0x05 => Event::DoubleArrival,
+                0x06 => Event::AcceptorUnplugged,
+                0x07 => Event::TubeJam,
+                0x08 => Event::RomChecksumError,
+                0x09 => Event::CoinRoutingError,
+                0x0a => Event::Busy,
+                0x0b => Event::JustReset,
+                0x0c => Event::CoinJam,
+                0x0d => Event::PossibleCreditedCoinRemoval,
+                code => Event::Status(code),
+            }
+        };
+
+        events.push(event);
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_coins() {
+        // 01yyxxxx zzzzzzzz: coin type 2 routed to its tube, 5 coins in it
+        // 1yyyxxxx zzzzzzzz: 3 coins of type 1 paid out, 12 left
+        assert_eq!(decode_poll(&[0x52, 0x05, 0xb1, 0x0c]),
+                   [Event::CoinDeposited {
+                        coin_type: 2,
+                        routing: CoinRouting::Tube,
+                        tube_count: 5,
+                    },
+                    Event::CoinsDispensed {
+                        coin_type: 1,
+                        count: 3,
+                        tube_count: 12,
+                    }]);
+        assert_eq!(decode_poll(&[0x43, 0x00, 0x73, 0x00]),
+                   [Event::CoinDeposited {
+                        coin_type: 3,
+                        routing: CoinRouting::CashBox,
+                        tube_count: 0,
+                    },
+                    Event::CoinDeposited {
+                        coin_type: 3,
+                        routing: CoinRouting::Reject,
+                        tube_count: 0,
+                    }]);
+    }
+
+    #[test]
+    fn poll_status() {
+        assert_eq!(decode_poll(&[0x0b, 0x23, 0x02, 0x0d, 0x1e]),
+                   [Event::JustReset,
+                    Event::Slugs(3),
+                    Event::PayoutBusy,
+                    Event::PossibleCreditedCoinRemoval,
+                    Event::Status(0x1e)]);
+        assert_eq!(decode_poll(&[]), []);
+    }
+
+    #[test]
+    fn poll_truncated() {
+        assert_eq!(decode_poll(&[0x01, 0x52]), [Event::EscrowRequest]);
+        assert_eq!(decode_poll(&[0xb1]), []);
+    }
+
+    #[test]
+    fn setup() {
+        // level 3, US dollars, 5 cents per credit unit
+        let data = [0x03, 0x18, 0x40, 0x05, 0x02, 0x00, 0x0f, 0x01, 0x02, 0x05, 0x0a, 0x14];
+        assert_eq!(Setup::decode(&data).unwrap(),
+                   Setup {
+                       feature_level: 3,
+                       currency_code: 0x1840,
+                       scaling_factor: 5,
+                       decimal_places: 2,
+                       coin_routing: 0x000f,
+                       coin_credits: vec![0x01, 0x02, 0x05, 0x0a, 0x14],
+                   });
+        assert!(matches!(Setup::decode(&data[..6]), Err(MdbError::InvalidResponse(_))));
+    }
+}
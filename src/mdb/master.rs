@@ -0,0 +1,450 @@
+//! MDB master (vending machine controller)
+
+use libc;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+use marker::{LineEvent, Unmarker};
+use port::{Parity, Rs485Port};
+use super::{ACK, MAX_BLOCK_LEN, MdbError, NAK, RET, address, checksum, command, line_settings};
+use super::bill_validator;
+use super::changer;
+use sys;
+use {SER_RS485_ADDRB, SerialRs485};
+
+/// Time the transmit line is held active to reset all peripherals
+const BUS_RESET_TIME: Duration = Duration::from_millis(100);
+
+/// How the mode bit is transmitted and received
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModeBitMethod {
+    /// Use the parity bit as mode bit
+    ///
+    /// Address bytes are sent with mark parity, switching the port back to
+    /// space parity for the rest of the command. Received bytes with the mode
+    /// bit set show up as parity errors, which are reported through error
+    /// marking. Works with any UART supporting `CMSPAR`.
+    ///
+    /// The switch has to wait until the address byte has been transmitted,
+    /// leaving a gap before the rest of the command. MDB allows at most 1 ms
+    /// between bytes; depending on how quickly the driver reports the
+    /// transmitter empty, the gap may be longer and peripherals may ignore
+    /// the command. Use `Addrb` where the UART supports it.
+    StickParity,
+    /// Use the kernel's 9-bit addressing mode (`ADDRB`)
+    ///
+    /// Address bytes are sent through the destination address of the RS485
+    /// configuration. The driver must report received bytes with the ninth
+    /// bit set like parity errors. Only available on some UARTs, e.g.
+    /// Synopsys DesignWare 8250 variants.
+    Addrb,
+}
+
+impl Default for ModeBitMethod {
+    #[inline]
+    fn default() -> ModeBitMethod {
+        ModeBitMethod::StickParity
+    }
+}
+
+/// Response of a peripheral to a command
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// Command accepted, no data to report
+    Ack,
+    /// Data, without the checksum; already acknowledged
+    Data(Vec<u8>),
+}
+
+/// MDB master, the vending machine controller
+///
+/// Sends commands to peripherals and acknowledges their responses. Commands
+/// are repeated if a peripheral does not respond or answers with NAK;
+/// responses with checksum errors are requested again with RET.
+#[derive(Debug)]
+pub struct MdbMaster {
+    port: Rs485Port,
+    method: ModeBitMethod,
+    rs485: SerialRs485,
+    response_timeout: Duration,
+    inter_byte_timeout: Duration,
+    retries: u32,
+}
+
+impl MdbMaster {
+    /// Create a master on an already opened port
+    ///
+    /// Reconfigures the port for MDB and enables error marking. With
+    /// `ModeBitMethod::Addrb`, also enables RS485 with 9-bit addressing in the
+    /// driver, failing with `ErrorKind::Unsupported` if it is not supported.
+    /// Defaults to a response timeout of 20 ms, an inter-byte timeout of 5 ms
+    /// and two retries.
+    pub fn new(mut port: Rs485Port, method: ModeBitMethod) -> Result<MdbMaster, MdbError> {
+        let mut settings = line_settings();
+        let mut rs485 = SerialRs485::new();
+
+        if method == ModeBitMethod::Addrb {
+            settings.set_parity(Parity::None);
+            port.reconfigure(&settings)?;
+
+            let fd = port.as_raw_fd();
+            rs485 = SerialRs485::from_fd(fd).map_err(io::Error::from)?;
+            rs485.set_enabled(true).set_addrb(true);
+            rs485.set_on_fd(fd).map_err(io::Error::from)?;
+
+            // the driver silently clears flags it does not support
+            rs485 = SerialRs485::from_fd(fd).map_err(io::Error::from)?;
+            if !rs485.flags().contains(SER_RS485_ADDRB) {
+                return Err(io::Error::new(io::ErrorKind::Unsupported,
+                                          "driver does not support 9-bit addressing")
+                    .into());
+            }
+            port.set_addrb(true)?;
+        } else {
+            port.reconfigure(&settings)?;
+        }
+
+        port.set_mark_errors(true)?;
+        port.discard_input()?;
+
+        Ok(MdbMaster {
+            port,
+            method,
+            rs485,
+            response_timeout: Duration::from_millis(20),
+            inter_byte_timeout: Duration::from_millis(5),
+            retries: 2,
+        })
+    }
+
+    /// Set response timeout
+    ///
+    /// Peripherals must respond within 5 ms; the default of 20 ms leaves room
+    /// for USB serial adapters.
+    #[inline]
+    pub fn set_response_timeout(&mut self, response_timeout: Duration) -> &mut Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    /// Set inter-byte timeout
+    ///
+    /// A response without a mode bit terminated byte is considered complete
+    /// once the line has been silent for this long.
+    #[inline]
+    pub fn set_inter_byte_timeout(&mut self, inter_byte_timeout: Duration) -> &mut Self {
+        self.inter_byte_timeout = inter_byte_timeout;
+        self
+    }
+
+    /// Set number of retries after a timeout, NAK or checksum error
+    #[inline]
+    pub fn set_retries(&mut self, retries: u32) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Mode bit method in use
+    #[inline]
+    pub fn method(&self) -> ModeBitMethod {
+        self.method
+    }
+
+    /// Underlying port
+    #[inline]
+    pub fn port(&mut self) -> &mut Rs485Port {
+        &mut self.port
+    }
+
+    /// Release the underlying port
+    ///
+    /// Error marking and, if used, 9-bit addressing stay enabled.
+    #[inline]
+    pub fn into_port(self) -> Rs485Port {
+        self.port
+    }
+
+    /// Reset all peripherals
+    ///
+    /// Holds the transmit line active for 100 ms. Peripherals may need
+    /// several hundred milliseconds afterwards before they respond.
+    pub fn bus_reset(&mut self) -> Result<(), MdbError> {
+        self.port.set_break(true)?;
+        thread::sleep(BUS_RESET_TIME);
+        self.port.set_break(false)?;
+        Ok(())
+    }
+
+    /// Send a command and wait for the response
+    ///
+    /// `address_command` is the peripheral address combined with the
+    /// command, `data` follows it. Data responses are acknowledged before
+    /// returning.
+    pub fn request(&mut self, address_command: u8, data: &[u8]) -> Result<Response, MdbError> {
+        if data.len() + 2 > MAX_BLOCK_LEN {
+            return Err(MdbError::InvalidRequest("command too long"));
+        }
+
+        let mut block = Vec::with_capacity(data.len() + 2);
+        block.push(address_command);
+        block.extend_from_slice(data);
+        block.push(checksum(&block));
+
+        let mut attempt = 0;
+        let mut retransmit = false;
+
+        loop {
+            if retransmit {
+                self.send(&[RET], false)?;
+            } else {
+                self.send(&block, true)?;
+            }
+
+            let rv = self.receive().and_then(|response| match response[..] {
+                [ACK] => Ok(Response::Ack),
+                [NAK] => Err(MdbError::Nak),
+                [_] => Err(MdbError::InvalidResponse("unexpected single byte response")),
+                _ => {
+                    let (chk, data) = response.split_last().unwrap();
+                    if checksum(data) != *chk {
+                        return Err(MdbError::Checksum);
+                    }
+                    Ok(Response::Data(data.to_vec()))
+                }
+            });
+
+            match rv {
+                Ok(Response::Data(data)) => {
+                    self.send(&[ACK], false)?;
+                    return Ok(Response::Data(data));
+                }
+                Err(MdbError::Checksum) if attempt < self.retries => {
+                    attempt += 1;
+                    retransmit = true;
+                }
+                Err(MdbError::Checksum) => {
+                    self.send(&[NAK], false)?;
+                    return Err(MdbError::Checksum);
+                }
+                Err(MdbError::Timeout) |
+                Err(MdbError::Nak) if attempt < self.retries => {
+                    attempt += 1;
+                    retransmit = false;
+                }
+                rv => return rv,
+            }
+        }
+    }
+
+    /// Reset a peripheral
+    ///
+    /// The peripheral reports the reset on the following polls.
+    pub fn reset(&mut self, peripheral: u8) -> Result<(), MdbError> {
+        match self.request(peripheral | command::RESET, &[])? {
+            Response::Ack => Ok(()),
+            Response::Data(_) => Err(MdbError::InvalidResponse("expected ACK")),
+        }
+    }
+
+    /// Request configuration data of a peripheral
+    ///
+    /// Only for peripherals whose SETUP command takes no data, such as coin
+    /// changers and bill validators.
+    pub fn setup(&mut self, peripheral: u8) -> Result<Vec<u8>, MdbError> {
+        match self.request(peripheral | command::SETUP, &[])? {
+            Response::Data(data) => Ok(data),
+            Response::Ack => Err(MdbError::InvalidResponse("expected setup data")),
+        }
+    }
+
+    /// Poll a peripheral
+    ///
+    /// Returns the activity reported, empty if there was none.
+    pub fn poll(&mut self, peripheral: u8) -> Result<Vec<u8>, MdbError> {
+        match self.request(peripheral | command::POLL, &[])? {
+            Response::Data(data) => Ok(data),
+            Response::Ack => Ok(Vec::new()),
+        }
+    }
+
+    /// Request configuration data of the coin changer
+    #[inline]
+    pub fn changer_setup(&mut self) -> Result<changer::Setup, MdbError> {
+        changer::Setup::decode(&self.setup(address::CHANGER)?)
+    }
+
+    /// Poll the coin changer
+    #[inline]
+    pub fn changer_poll(&mut self) -> Result<Vec<changer::Event>, MdbError> {
+        Ok(changer::decode_poll(&self.poll(address::CHANGER)?))
+    }
+
+    /// Request configuration data of the bill validator
+    #[inline]
+    pub fn bill_validator_setup(&mut self) -> Result<bill_validator::Setup, MdbError> {
+        bill_validator::Setup::decode(&self.setup(address::BILL_VALIDATOR)?)
+    }
+
+    /// Poll the bill validator
+    #[inline]
+    pub fn bill_validator_poll(&mut self) -> Result<Vec<bill_validator::Event>, MdbError> {
+        Ok(bill_validator::decode_poll(&self.poll(address::BILL_VALIDATOR)?))
+    }
+
+    /// Send a block, with the mode bit set on the first byte if `mode`
+    fn send(&mut self, block: &[u8], mode: bool) -> Result<(), MdbError> {
+        self.port.discard_input()?;
+
+        if !mode {
+            self.port.write_all(block)?;
+            self.port.drain()?;
+            return Ok(());
+        }
+
+        match self.method {
+            ModeBitMethod::StickParity => {
+                // toggle between space and mark parity with a single call
+                // each, keeping the gap after the address byte short
+                let fd = self.port.as_raw_fd();
+                let mut tio = sys::tcgetattr(fd)?;
+
+                tio.c_cflag |= libc::PARODD;
+                sys::tcsetattr(fd, &tio)?;
+                self.port.write_all(&block[..1])?;
+                self.port.drain()?;
+
+                tio.c_cflag &= !libc::PARODD;
+                sys::tcsetattr(fd, &tio)?;
+                self.port.write_all(&block[1..])?;
+                self.port.drain()?;
+            }
+            ModeBitMethod::Addrb => {
+                let fd = self.port.as_raw_fd();
+
+                self.rs485.set_addr_dest(Some(block[0]));
+                self.rs485.set_on_fd(fd).map_err(io::Error::from)?;
+                self.port.write_all(&block[1..])?;
+                self.port.drain()?;
+
+                self.rs485.set_addr_dest(None);
+                self.rs485.set_on_fd(fd).map_err(io::Error::from)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Receive a response
+    ///
+    /// Reads until a byte with the mode bit set arrives or the line falls
+    /// silent. Returns the bytes without error markers, see `unmark`.
+    fn receive(&mut self) -> Result<Vec<u8>, MdbError> {
+        let mut unmarker = Unmarker::default();
+        let mut response = Vec::new();
+        let mut buf = [0u8; 2 * MAX_BLOCK_LEN];
+        let mut timeout = self.response_timeout;
+
+        loop {
+            let n = self.port.read_timeout(&mut buf, timeout)?;
+            if n == 0 {
+                break;
+            }
+            timeout = self.inter_byte_timeout;
+
+            let complete = unmark(&mut unmarker, &buf[..n], &mut response);
+            if complete || response.len() > MAX_BLOCK_LEN {
+                break;
+            }
+        }
+
+        if response.is_empty() {
+            return Err(MdbError::Timeout);
+        }
+        if response.len() > MAX_BLOCK_LEN {
+            return Err(MdbError::InvalidResponse("response too long"));
+        }
+
+        Ok(response)
+    }
+}
+
+/// Decode received data with error markers, appending it to `response`
+///
+/// Returns whether a byte with the mode bit set, i.e. the last byte of a
+/// response, was seen. A zero byte with the mode bit set is marked the same
+/// way as a break and decoded as zero: that is an ACK on its own, or the
+/// checksum of a data block adding up to zero.
+fn unmark(unmarker: &mut Unmarker, data: &[u8], response: &mut Vec<u8>) -> bool {
+    let mut complete = false;
+
+    for &byte in data {
+        unmarker.feed(byte, |event| match event {
+            LineEvent::Data(byte) => response.push(byte),
+            LineEvent::Error(byte) => {
+                response.push(byte);
+                complete = true;
+            }
+            LineEvent::Break => {
+                response.push(0);
+                complete = true;
+            }
+        });
+    }
+
+    complete
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(chunks: &[&[u8]]) -> (Vec<u8>, bool) {
+        let mut unmarker = Unmarker::default();
+        let mut response = Vec::new();
+        let mut complete = false;
+        for chunk in chunks {
+            complete = unmark(&mut unmarker, chunk, &mut response);
+        }
+        (response, complete)
+    }
+
+    #[test]
+    fn ack() {
+        assert_eq!(decode(&[&[0xff, 0x00, 0x00]]), (vec![ACK], true));
+    }
+
+    #[test]
+    fn nak() {
+        assert_eq!(decode(&[&[0xff, 0x00, NAK]]), (vec![NAK], true));
+    }
+
+    #[test]
+    fn data_block() {
+        let (response, complete) = decode(&[&[0x01, 0xff, 0xff, 0x02], &[0xff, 0x00, 0x02]]);
+        assert_eq!(response, [0x01, 0xff, 0x02, 0x02]);
+        assert!(complete);
+        assert_eq!(checksum(&response[..3]), response[3]);
+    }
+
+    #[test]
+    fn zero_checksum() {
+        let (response, complete) = decode(&[&[0x80, 0x80, 0xff, 0x00, 0x00]]);
+        assert_eq!(response, [0x80, 0x80, 0x00]);
+        assert!(complete);
+        assert_eq!(checksum(&response[..2]), 0);
+    }
+
+    #[test]
+    fn zero_checksum_split() {
+        let (response, complete) = decode(&[&[0x80, 0x80, 0xff], &[0x00], &[0x00]]);
+        assert_eq!(response, [0x80, 0x80, 0x00]);
+        assert!(complete);
+    }
+
+    #[test]
+    fn incomplete() {
+        assert_eq!(decode(&[&[0x01, 0x02]]), (vec![0x01, 0x02], false));
+    }
+}
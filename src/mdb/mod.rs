@@ -0,0 +1,153 @@
+//! MDB (Multi-Drop Bus / ICP) vending protocol
+//!
+//! MDB connects a vending machine controller (VMC) to peripherals such as
+//! coin changers, bill validators and cashless readers at 9600 baud with 9
+//! bit characters. The ninth, "mode" bit marks the first byte of a command
+//! from the VMC and the last byte of a response from a peripheral.
+//!
+//! Common UARTs lack 9 bit characters; see `ModeBitMethod` for how the mode
+//! bit is emulated. Commands and responses end with a checksum, the sum of
+//! all preceding bytes.
+
+use std::{error, fmt, io};
+
+use port::{Parity, PortSettings};
+
+pub mod bill_validator;
+pub mod changer;
+pub mod master;
+
+pub use self::master::{MdbMaster, ModeBitMethod, Response};
+
+/// MDB baud rate
+pub const BAUD_RATE: u32 = 9600;
+
+/// Acknowledgement, sent by either side
+pub const ACK: u8 = 0x00;
+
+/// Request to retransmit the last response, sent by the VMC
+pub const RET: u8 = 0xaa;
+
+/// Negative acknowledgement, sent by either side
+pub const NAK: u8 = 0xff;
+
+/// Maximum length of a command or response, including the checksum
+pub const MAX_BLOCK_LEN: usize = 36;
+
+/// Peripheral addresses, occupying the upper five bits of the address byte
+pub mod address {
+    /// Coin changer
+    pub const CHANGER: u8 = 0x08;
+    /// Cashless device #1
+    pub const CASHLESS_1: u8 = 0x10;
+    /// Communications gateway
+    pub const GATEWAY: u8 = 0x18;
+    /// Display
+    pub const DISPLAY: u8 = 0x20;
+    /// Energy management system
+    pub const ENERGY_MANAGEMENT: u8 = 0x28;
+    /// Bill validator
+    pub const BILL_VALIDATOR: u8 = 0x30;
+    /// Universal satellite device #1
+    pub const USD_1: u8 = 0x40;
+    /// Universal satellite device #2
+    pub const USD_2: u8 = 0x48;
+    /// Universal satellite device #3
+    pub const USD_3: u8 = 0x50;
+    /// Coin hopper or tube dispenser #1
+    pub const COIN_HOPPER_1: u8 = 0x58;
+    /// Cashless device #2
+    pub const CASHLESS_2: u8 = 0x60;
+    /// Age verification device
+    pub const AGE_VERIFICATION: u8 = 0x68;
+    /// Coin hopper or tube dispenser #2
+    pub const COIN_HOPPER_2: u8 = 0x70;
+}
+
+/// Commands shared by most peripherals, occupying the lower three bits of
+/// the address byte
+pub mod command {
+    /// Reset the peripheral
+    pub const RESET: u8 = 0x00;
+    /// Request configuration data
+    pub const SETUP: u8 = 0x01;
+    /// Request activity since the last poll
+    pub const POLL: u8 = 0x03;
+}
+
+/// Line settings for MDB: 9600 baud, 8 data bits, space parity, 1 stop bit
+///
+/// The parity bit carries the mode bit; see `ModeBitMethod::StickParity`.
+pub fn line_settings() -> PortSettings {
+    let mut settings = PortSettings::new();
+    settings.set_baud_rate(BAUD_RATE).set_parity(Parity::Space);
+    settings
+}
+
+/// Checksum of a block: sum of all bytes, modulo 256
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Error during an MDB transaction
+#[derive(Debug)]
+pub enum MdbError {
+    /// Error on the underlying port
+    Io(io::Error),
+    /// No response was received in time
+    Timeout,
+    /// A response with an invalid checksum was received
+    Checksum,
+    /// The peripheral answered with NAK
+    Nak,
+    /// The request cannot be encoded, e.g. because its data is too long
+    InvalidRequest(&'static str),
+    /// The response was malformed or did not match the request
+    InvalidResponse(&'static str),
+}
+
+impl fmt::Display for MdbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MdbError::Io(ref e) => write!(f, "I/O error: {}", e),
+            MdbError::Timeout => write!(f, "timeout waiting for response"),
+            MdbError::Checksum => write!(f, "checksum mismatch"),
+            MdbError::Nak => write!(f, "negative acknowledgement"),
+            MdbError::InvalidRequest(reason) => write!(f, "invalid request: {}", reason),
+            MdbError::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+        }
+    }
+}
+
+impl error::Error for MdbError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            MdbError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MdbError {
+    fn from(e: io::Error) -> MdbError {
+        if e.kind() == io::ErrorKind::TimedOut {
+            return MdbError::Timeout;
+        }
+
+        MdbError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_checksum() {
+        // RESET and POLL of the changer carry only their address byte
+        assert_eq!(checksum(&[0x08]), 0x08);
+        assert_eq!(checksum(&[0x0b]), 0x0b);
+        // COIN TYPE enabling all coins, overflowing the sum
+        assert_eq!(checksum(&[0x0c, 0xff, 0xff, 0xff, 0xff]), 0x08);
+    }
+}
@@ -0,0 +1,141 @@
+//! ASCII framing
+//!
+//! An ASCII frame starts with a colon, followed by the unit address, the PDU
+//! and an LRC, each byte as two uppercase hex digits, and ends with CR LF.
+//! Characters of a frame may be up to one second apart.
+
+use std::time::Duration;
+
+use super::ModbusError;
+use super::pdu::MAX_PDU_LEN;
+
+/// Start of frame character
+pub const START: u8 = b':';
+
+/// End of frame sequence
+pub const END: &[u8] = b"\r\n";
+
+/// Minimum size of an ASCII frame: start, unit, function code, LRC and end
+pub const MIN_FRAME_LEN: usize = 9;
+
+/// Maximum size of an ASCII frame
+pub const MAX_FRAME_LEN: usize = 1 + 2 * (MAX_PDU_LEN + 2) + 2;
+
+/// Maximum gap between characters of a frame
+pub const INTER_CHAR_TIMEOUT: Duration = Duration::from_secs(1);
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Longitudinal redundancy check: two's complement of the byte sum
+pub fn lrc(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)).wrapping_neg()
+}
+
+/// Encode an ASCII frame
+pub fn encode_frame(unit: u8, pdu: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(2 * (pdu.len() + 2) + 3);
+    frame.push(START);
+
+    let lrc = lrc(pdu).wrapping_sub(unit);
+    for &byte in Some(&unit).into_iter().chain(pdu).chain(Some(&lrc)) {
+        frame.push(HEX_DIGITS[(byte >> 4) as usize]);
+        frame.push(HEX_DIGITS[(byte & 0xf) as usize]);
+    }
+
+    frame.extend_from_slice(END);
+    frame
+}
+
+/// Decode an ASCII frame into unit address and PDU
+///
+/// Anything before the last start character is ignored, as the
+/// specification requires a receiver to restart on every colon. Checks
+/// length, hex digits and LRC.
+pub fn decode_frame(frame: &[u8]) -> Result<(u8, Vec<u8>), ModbusError> {
+    let start = frame.iter()
+        .rposition(|&c| c == START)
+        .ok_or(ModbusError::InvalidResponse("missing start of frame"))?;
+    let frame = &frame[start..];
+
+    if frame.len() < MIN_FRAME_LEN {
+        return Err(ModbusError::InvalidResponse("frame too short"));
+    }
+    if !frame.ends_with(END) {
+        return Err(ModbusError::InvalidResponse("missing end of frame"));
+    }
+
+    let hex = &frame[1..frame.len() - END.len()];
+    if !hex.len().is_multiple_of(2) {
+        return Err(ModbusError::InvalidResponse("odd number of hex digits"));
+    }
+
+    let mut body = Vec::with_capacity(hex.len() / 2);
+    for pair in hex.chunks(2) {
+        match (hex_value(pair[0]), hex_value(pair[1])) {
+            (Some(hi), Some(lo)) => body.push(hi << 4 | lo),
+            _ => return Err(ModbusError::InvalidResponse("invalid hex digit")),
+        }
+    }
+
+    // the LRC over all bytes including itself sums to zero
+    if lrc(&body) != 0 {
+        return Err(ModbusError::Checksum);
+    }
+
+    body.pop();
+    let unit = body.remove(0);
+    Ok((unit, body))
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lrc_published_vectors() {
+        // example from the Modbus over serial line specification
+        assert_eq!(lrc(&[0xf7, 0x03, 0x13, 0x89, 0x00, 0x0a]), 0x60);
+        assert_eq!(lrc(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]), 0xfb);
+        assert_eq!(lrc(&[]), 0x00);
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = encode_frame(0xf7, &[0x03, 0x13, 0x89, 0x00, 0x0a]);
+        assert_eq!(frame, b":F7031389000A60\r\n".to_vec());
+
+        let (unit, pdu) = decode_frame(&frame).unwrap();
+        assert_eq!(unit, 0xf7);
+        assert_eq!(pdu, [0x03, 0x13, 0x89, 0x00, 0x0a]);
+    }
+
+    #[test]
+    fn decode_restarts_on_colon() {
+        let (unit, pdu) = decode_frame(b":0103:010300000001FB\r\n").unwrap();
+        assert_eq!(unit, 0x01);
+        assert_eq!(pdu, [0x03, 0x00, 0x00, 0x00, 0x01]);
+
+        // lowercase hex digits are accepted
+        assert!(decode_frame(b":f7031389000a60\r\n").is_ok());
+    }
+
+    #[test]
+    fn corrupt_frames() {
+        assert!(matches!(decode_frame(b":010300000001FC\r\n"), Err(ModbusError::Checksum)));
+        assert!(matches!(decode_frame(b":010300000001FB"),
+                         Err(ModbusError::InvalidResponse(_))));
+        assert!(matches!(decode_frame(b":0103000000G1FB\r\n"),
+                         Err(ModbusError::InvalidResponse(_))));
+        assert!(matches!(decode_frame(b"010300000001FB\r\n"),
+                         Err(ModbusError::InvalidResponse(_))));
+    }
+}
@@ -0,0 +1,456 @@
+//! Modbus master (client)
+
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use port::Rs485Port;
+use timing::LineTiming;
+use super::{BROADCAST_UNIT, Framing, ModbusError};
+use super::ascii;
+use super::pdu::{self, DeviceIdentification, Request, Response};
+
+// lower bound for the inter-character timeout; Linux userspace (and USB
+// serial adapters in particular) cannot reliably resolve the sub-millisecond
+// gaps the specification requires at higher baud rates
+const MIN_INTER_CHAR_TIMEOUT: Duration = Duration::from_millis(5);
+
+/// Modbus master on a serial line
+///
+/// Sends requests to servers (slaves) and waits for their responses, retrying
+/// on timeouts and checksum errors.
+#[derive(Debug)]
+pub struct Master {
+    port: Rs485Port,
+    framing: Framing,
+    timeout: Duration,
+    retries: u32,
+    inter_char_timeout: Duration,
+    frame_gap: Duration,
+    broadcast_delay: Duration,
+    // earliest time the next request may be sent
+    next_request: Option<Instant>,
+}
+
+impl Master {
+    /// Create a master using RTU framing
+    ///
+    /// Frame timing is derived from the port's line settings. Defaults to a
+    /// response timeout of 1 s, no retries and a turnaround delay of 100 ms
+    /// after broadcasts.
+    #[inline]
+    pub fn new(port: Rs485Port) -> Master {
+        Master::with_framing(port, Framing::Rtu)
+    }
+
+    /// Create a master using the given framing
+    ///
+    /// With ASCII framing, the inter-character timeout defaults to 1 s.
+    pub fn with_framing(port: Rs485Port, framing: Framing) -> Master {
+        let timing = LineTiming::new(port.settings());
+        let inter_char_timeout = match framing {
+            Framing::Rtu => timing.modbus_t35().max(MIN_INTER_CHAR_TIMEOUT),
+            Framing::Ascii => ascii::INTER_CHAR_TIMEOUT,
+        };
+
+        Master {
+            port,
+            framing,
+            timeout: Duration::from_secs(1),
+            retries: 0,
+            inter_char_timeout,
+            frame_gap: timing.modbus_t35(),
+            broadcast_delay: Duration::from_millis(100),
+            next_request: None,
+        }
+    }
+
+    /// Set response timeout
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set number of retries after a timeout or checksum error
+    #[inline]
+    pub fn set_retries(&mut self, retries: u32) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Set inter-character timeout
+    ///
+    /// A response is considered complete once the line has been silent for
+    /// this long. Defaults to t3.5, but at least 5 ms, for RTU and to 1 s for
+    /// ASCII.
+    #[inline]
+    pub fn set_inter_char_timeout(&mut self, inter_char_timeout: Duration) -> &mut Self {
+        self.inter_char_timeout = inter_char_timeout;
+        self
+    }
+
+    /// Set delay after broadcasts
+    ///
+    /// Time given to servers to process a broadcast before the next request.
+    #[inline]
+    pub fn set_broadcast_delay(&mut self, broadcast_delay: Duration) -> &mut Self {
+        self.broadcast_delay = broadcast_delay;
+        self
+    }
+
+    /// Framing in use
+    #[inline]
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Underlying port
+    #[inline]
+    pub fn port(&mut self) -> &mut Rs485Port {
+        &mut self.port
+    }
+
+    /// Release the underlying port
+    #[inline]
+    pub fn into_port(self) -> Rs485Port {
+        self.port
+    }
+
+    /// Send a request and wait for the response
+    ///
+    /// Uses the configured response timeout.
+    #[inline]
+    pub fn request(&mut self, unit: u8, request: &Request) -> Result<Response, ModbusError> {
+        let timeout = self.timeout;
+        self.request_with_timeout(unit, request, timeout)
+    }
+
+    /// Send a request and wait up to `timeout` for the response
+    pub fn request_with_timeout(&mut self,
+                                unit: u8,
+                                request: &Request,
+                                timeout: Duration)
+                                -> Result<Response, ModbusError> {
+        if unit == BROADCAST_UNIT {
+            return Err(ModbusError::InvalidRequest("use broadcast() for unit 0"));
+        }
+
+        let frame = self.framing.encode_frame(unit, &request.encode()?);
+        let mut attempt = 0;
+
+        loop {
+            let rv = self.send(&frame)
+                .and_then(|_| self.receive(timeout))
+                .and_then(|resp| {
+                    let (resp_unit, pdu) = self.framing.decode_frame(&resp)?;
+                    if resp_unit != unit {
+                        return Err(ModbusError::InvalidResponse("unit address mismatch"));
+                    }
+                    let response = Response::decode(request, &pdu)?;
+                    check_echo(request, &response)?;
+                    Ok(response)
+                });
+
+            match rv {
+                Err(ModbusError::Timeout) |
+                Err(ModbusError::Checksum) if attempt < self.retries => attempt += 1,
+                rv => return rv,
+            }
+        }
+    }
+
+    /// Send a request to all servers
+    ///
+    /// Only write requests may be broadcast. No response is expected.
+    pub fn broadcast(&mut self, request: &Request) -> Result<(), ModbusError> {
+        if !request.is_broadcastable() {
+            return Err(ModbusError::InvalidRequest("request cannot be broadcast"));
+        }
+
+        let frame = self.framing.encode_frame(BROADCAST_UNIT, &request.encode()?);
+        self.send(&frame)?;
+        self.next_request = Some(Instant::now() + self.broadcast_delay);
+
+        Ok(())
+    }
+
+    /// Read coils
+    pub fn read_coils(&mut self,
+                      unit: u8,
+                      addr: u16,
+                      count: u16)
+                      -> Result<Vec<bool>, ModbusError> {
+        match self.request(unit, &Request::ReadCoils { addr, count })? {
+            Response::ReadCoils(bits) => Ok(bits),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// Read discrete inputs
+    pub fn read_discrete_inputs(&mut self,
+                                unit: u8,
+                                addr: u16,
+                                count: u16)
+                                -> Result<Vec<bool>, ModbusError> {
+        match self.request(unit, &Request::ReadDiscreteInputs { addr, count })? {
+            Response::ReadDiscreteInputs(bits) => Ok(bits),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// Read holding registers
+    pub fn read_holding_registers(&mut self,
+                                  unit: u8,
+                                  addr: u16,
+                                  count: u16)
+                                  -> Result<Vec<u16>, ModbusError> {
+        match self.request(unit, &Request::ReadHoldingRegisters { addr, count })? {
+            Response::ReadHoldingRegisters(regs) => Ok(regs),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// Read input registers
+    pub fn read_input_registers(&mut self,
+                                unit: u8,
+                                addr: u16,
+                                count: u16)
+                                -> Result<Vec<u16>, ModbusError> {
+        match self.request(unit, &Request::ReadInputRegisters { addr, count })? {
+            Response::ReadInputRegisters(regs) => Ok(regs),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// Write a single coil
+    pub fn write_single_coil(&mut self,
+                             unit: u8,
+                             addr: u16,
+                             value: bool)
+                             -> Result<(), ModbusError> {
+        self.request(unit, &Request::WriteSingleCoil { addr, value }).map(|_| ())
+    }
+
+    /// Write a single holding register
+    pub fn write_single_register(&mut self,
+                                 unit: u8,
+                                 addr: u16,
+                                 value: u16)
+                                 -> Result<(), ModbusError> {
+        self.request(unit, &Request::WriteSingleRegister { addr, value }).map(|_| ())
+    }
+
+    /// Write multiple coils
+    pub fn write_multiple_coils(&mut self,
+                                unit: u8,
+                                addr: u16,
+                                values: &[bool])
+                                -> Result<(), ModbusError> {
+        let values = values.to_vec();
+        self.request(unit, &Request::WriteMultipleCoils { addr, values }).map(|_| ())
+    }
+
+    /// Write multiple holding registers
+    pub fn write_multiple_registers(&mut self,
+                                    unit: u8,
+                                    addr: u16,
+                                    values: &[u16])
+                                    -> Result<(), ModbusError> {
+        let values = values.to_vec();
+        self.request(unit, &Request::WriteMultipleRegisters { addr, values }).map(|_| ())
+    }
+
+    /// Modify a holding register through AND and OR masks
+    pub fn mask_write_register(&mut self,
+                               unit: u8,
+                               addr: u16,
+                               and_mask: u16,
+                               or_mask: u16)
+                               -> Result<(), ModbusError> {
+        let req = Request::MaskWriteRegister {
+            addr,
+            and_mask,
+            or_mask,
+        };
+        self.request(unit, &req).map(|_| ())
+    }
+
+    /// Write, then read holding registers in one transaction
+    pub fn read_write_multiple_registers(&mut self,
+                                         unit: u8,
+                                         read_addr: u16,
+                                         read_count: u16,
+                                         write_addr: u16,
+                                         values: &[u16])
+                                         -> Result<Vec<u16>, ModbusError> {
+        let req = Request::ReadWriteMultipleRegisters {
+            read_addr,
+            read_count,
+            write_addr,
+            values: values.to_vec(),
+        };
+
+        match self.request(unit, &req)? {
+            Response::ReadWriteMultipleRegisters(regs) => Ok(regs),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// Read device identification
+    ///
+    /// For stream access (`read_code` 1 to 3), follows up with further
+    /// requests until all objects have been read.
+    pub fn read_device_identification(&mut self,
+                                      unit: u8,
+                                      read_code: u8)
+                                      -> Result<DeviceIdentification, ModbusError> {
+        let mut object_id = 0;
+        let mut id: Option<DeviceIdentification> = None;
+
+        loop {
+            let part = match self.request(unit,
+                         &Request::ReadDeviceIdentification {
+                             read_code,
+                             object_id,
+                         })? {
+                Response::ReadDeviceIdentification(part) => part,
+                _ => return Err(unexpected()),
+            };
+
+            let more = part.more_follows && read_code != 4 && part.next_object_id > object_id;
+            object_id = part.next_object_id;
+
+            id = Some(match id {
+                None => part,
+                Some(mut id) => {
+                    id.objects.extend(part.objects);
+                    id.more_follows = part.more_follows;
+                    id.next_object_id = part.next_object_id;
+                    id
+                }
+            });
+
+            if !more {
+                break;
+            }
+        }
+
+        Ok(id.unwrap_or_default())
+    }
+
+    /// Write a frame, respecting the inter-frame gap
+    fn send(&mut self, frame: &[u8]) -> Result<(), ModbusError> {
+        if let Some(next) = self.next_request {
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+        }
+
+        self.port.discard_input()?;
+        self.port.write_all(frame)?;
+        self.port.drain()?;
+        // with receiving during transmission, the request comes back and
+        // would be taken for the response
+        self.port.discard_input()?;
+        self.next_request = Some(Instant::now() + self.frame_gap);
+
+        Ok(())
+    }
+
+    /// Receive a response frame
+    ///
+    /// Reads until the frame is complete according to its header or end
+    /// marker, or the line falls silent.
+    fn receive(&mut self, timeout: Duration) -> Result<Vec<u8>, ModbusError> {
+        let mut buf = vec![0u8; self.framing.max_frame_len()];
+        let mut len = self.port.read_timeout(&mut buf, timeout)?;
+
+        if len == 0 {
+            return Err(ModbusError::Timeout);
+        }
+
+        loop {
+            if self.framing.is_complete(&buf[..len], pdu::expected_response_len) {
+                break;
+            }
+
+            if len == buf.len() {
+                break;
+            }
+
+            let n = self.port.read_timeout(&mut buf[len..], self.inter_char_timeout)?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+
+        self.next_request = Some(Instant::now() + self.frame_gap);
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+#[inline]
+fn unexpected() -> ModbusError {
+    ModbusError::InvalidResponse("unexpected response type")
+}
+
+/// Checks that write confirmations match the request
+fn check_echo(request: &Request, response: &Response) -> Result<(), ModbusError> {
+    let ok = match (request, response) {
+        (&Request::WriteSingleCoil { addr, value },
+         &Response::WriteSingleCoil { addr: raddr, value: rvalue }) => {
+            addr == raddr && value == rvalue
+        }
+        (&Request::WriteSingleRegister { addr, value },
+         &Response::WriteSingleRegister { addr: raddr, value: rvalue }) => {
+            addr == raddr && value == rvalue
+        }
+        (&Request::WriteMultipleCoils { addr, ref values },
+         &Response::WriteMultipleCoils { addr: raddr, count }) => {
+            addr == raddr && values.len() == count as usize
+        }
+        (&Request::WriteMultipleRegisters { addr, ref values },
+         &Response::WriteMultipleRegisters { addr: raddr, count }) => {
+            addr == raddr && values.len() == count as usize
+        }
+        (&Request::MaskWriteRegister { addr, and_mask, or_mask },
+         &Response::MaskWriteRegister { addr: raddr, and_mask: rand, or_mask: ror }) => {
+            addr == raddr && and_mask == rand && or_mask == ror
+        }
+        _ => true,
+    };
+
+    if !ok {
+        return Err(ModbusError::InvalidResponse("write confirmation does not match request"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::{Loopback, pty_port};
+
+    #[test]
+    fn request_echo_discarded() {
+        let (master, mut port) = pty_port();
+        let loopback = Loopback::new(master);
+        let sent = loopback.sent.clone();
+        port.set_direction_control(loopback);
+
+        let mut master = Master::new(port);
+        master.set_timeout(Duration::from_millis(100));
+
+        // the echo of a write request looks exactly like its confirmation
+        match master.write_single_register(0x11, 0x0001, 0x0003) {
+            Err(ModbusError::Timeout) => (),
+            rv => panic!("unexpected result {:?}", rv),
+        }
+        assert_eq!(*sent.lock().unwrap(), [0x11, 0x06, 0x00, 0x01, 0x00, 0x03, 0x9a, 0x9b]);
+    }
+}
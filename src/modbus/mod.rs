@@ -0,0 +1,223 @@
+//! Modbus over serial line
+//!
+//! Implements the Modbus application protocol (`pdu`) and its RTU (`rtu`)
+//! and ASCII (`ascii`) framings on top of an `Rs485Port`. `Master` acts as a
+//! client on the bus, `Slave` as a server answering requests from a
+//! `RegisterMap`. The MBAP framing of Modbus TCP (`tcp`) is provided for
+//! gateways.
+
+use std::{error, fmt, io};
+use std::borrow::Cow;
+
+pub mod ascii;
+pub mod master;
+pub mod pdu;
+pub mod rtu;
+pub mod slave;
+pub mod tcp;
+
+pub use self::master::Master;
+pub use self::pdu::{DeviceIdentification, Request, Response};
+pub use self::slave::{RegisterMap, Slave};
+
+/// Address used for broadcast requests
+pub const BROADCAST_UNIT: u8 = 0;
+
+/// Serial line transmission mode
+///
+/// Both framings carry the same PDUs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Framing {
+    /// Binary frames with CRC-16, delimited by line silence
+    Rtu,
+    /// Hex-encoded frames with LRC, delimited by `:` and CR LF
+    Ascii,
+}
+
+impl Default for Framing {
+    #[inline]
+    fn default() -> Framing {
+        Framing::Rtu
+    }
+}
+
+impl Framing {
+    /// Encode a frame
+    pub fn encode_frame(self, unit: u8, pdu: &[u8]) -> Vec<u8> {
+        match self {
+            Framing::Rtu => rtu::encode_frame(unit, pdu),
+            Framing::Ascii => ascii::encode_frame(unit, pdu),
+        }
+    }
+
+    /// Decode a frame into unit address and PDU
+    pub fn decode_frame(self, frame: &[u8]) -> Result<(u8, Cow<'_, [u8]>), ModbusError> {
+        match self {
+            Framing::Rtu => rtu::decode_frame(frame).map(|(unit, pdu)| (unit, Cow::Borrowed(pdu))),
+            Framing::Ascii => ascii::decode_frame(frame).map(|(unit, pdu)| (unit, Cow::Owned(pdu))),
+        }
+    }
+
+    /// Maximum size of a frame
+    pub fn max_frame_len(self) -> usize {
+        match self {
+            Framing::Rtu => rtu::MAX_FRAME_LEN,
+            Framing::Ascii => ascii::MAX_FRAME_LEN,
+        }
+    }
+
+    /// Whether a partially received frame is complete
+    ///
+    /// RTU frames are checked against the PDU length announced in their
+    /// header, as computed by `pdu_len`. `None` means the length cannot be
+    /// determined and the end of the frame must be detected by silence.
+    fn is_complete(self, frame: &[u8], pdu_len: fn(&[u8]) -> Option<usize>) -> bool {
+        match self {
+            // unit address precedes the PDU, CRC follows it
+            Framing::Rtu => {
+                frame.len() > 1 &&
+                pdu_len(&frame[1..]).is_some_and(|n| frame.len() >= n + 3)
+            }
+            Framing::Ascii => frame.ends_with(ascii::END),
+        }
+    }
+}
+
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Framing::Rtu => "RTU",
+            Framing::Ascii => "ASCII",
+        })
+    }
+}
+
+/// Exception code returned by a Modbus server
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ExceptionCode {
+    /// Function code not supported (0x01)
+    IllegalFunction,
+    /// Address out of range (0x02)
+    IllegalDataAddress,
+    /// Value in request not allowed (0x03)
+    IllegalDataValue,
+    /// Unrecoverable error in the server (0x04)
+    ServerDeviceFailure,
+    /// Request accepted, but will take long to process (0x05)
+    Acknowledge,
+    /// Server busy processing another request (0x06)
+    ServerDeviceBusy,
+    /// Parity error in extended memory (0x08)
+    MemoryParityError,
+    /// Gateway could not allocate a path to the target (0x0A)
+    GatewayPathUnavailable,
+    /// Target behind gateway did not respond (0x0B)
+    GatewayTargetFailedToRespond,
+    /// Any other exception code
+    Other(u8),
+}
+
+impl ExceptionCode {
+    /// Exception code value
+    pub fn code(self) -> u8 {
+        match self {
+            ExceptionCode::IllegalFunction => 0x01,
+            ExceptionCode::IllegalDataAddress => 0x02,
+            ExceptionCode::IllegalDataValue => 0x03,
+            ExceptionCode::ServerDeviceFailure => 0x04,
+            ExceptionCode::Acknowledge => 0x05,
+            ExceptionCode::ServerDeviceBusy => 0x06,
+            ExceptionCode::MemoryParityError => 0x08,
+            ExceptionCode::GatewayPathUnavailable => 0x0a,
+            ExceptionCode::GatewayTargetFailedToRespond => 0x0b,
+            ExceptionCode::Other(code) => code,
+        }
+    }
+}
+
+impl From<u8> for ExceptionCode {
+    fn from(code: u8) -> ExceptionCode {
+        match code {
+            0x01 => ExceptionCode::IllegalFunction,
+            0x02 => ExceptionCode::IllegalDataAddress,
+            0x03 => ExceptionCode::IllegalDataValue,
+            0x04 => ExceptionCode::ServerDeviceFailure,
+            0x05 => ExceptionCode::Acknowledge,
+            0x06 => ExceptionCode::ServerDeviceBusy,
+            0x08 => ExceptionCode::MemoryParityError,
+            0x0a => ExceptionCode::GatewayPathUnavailable,
+            0x0b => ExceptionCode::GatewayTargetFailedToRespond,
+            code => ExceptionCode::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for ExceptionCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = match *self {
+            ExceptionCode::IllegalFunction => "illegal function",
+            ExceptionCode::IllegalDataAddress => "illegal data address",
+            ExceptionCode::IllegalDataValue => "illegal data value",
+            ExceptionCode::ServerDeviceFailure => "server device failure",
+            ExceptionCode::Acknowledge => "acknowledge",
+            ExceptionCode::ServerDeviceBusy => "server device busy",
+            ExceptionCode::MemoryParityError => "memory parity error",
+            ExceptionCode::GatewayPathUnavailable => "gateway path unavailable",
+            ExceptionCode::GatewayTargetFailedToRespond => {
+                "gateway target device failed to respond"
+            }
+            ExceptionCode::Other(_) => "unknown exception",
+        };
+
+        write!(f, "{} (0x{:02x})", desc, self.code())
+    }
+}
+
+/// Error during a Modbus transaction
+#[derive(Debug)]
+pub enum ModbusError {
+    /// Error on the underlying port
+    Io(io::Error),
+    /// No (valid) response was received in time
+    Timeout,
+    /// A frame with an invalid checksum was received
+    Checksum,
+    /// The server returned an exception response
+    Exception(ExceptionCode),
+    /// The request cannot be encoded, e.g. because a count is out of range
+    InvalidRequest(&'static str),
+    /// The response was malformed or did not match the request
+    InvalidResponse(&'static str),
+}
+
+impl fmt::Display for ModbusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ModbusError::Io(ref e) => write!(f, "I/O error: {}", e),
+            ModbusError::Timeout => write!(f, "timeout waiting for response"),
+            ModbusError::Checksum => write!(f, "checksum mismatch"),
+            ModbusError::Exception(code) => write!(f, "exception response: {}", code),
+            ModbusError::InvalidRequest(reason) => write!(f, "invalid request: {}", reason),
+            ModbusError::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+        }
+    }
+}
+
+impl error::Error for ModbusError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ModbusError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ModbusError {
+    fn from(e: io::Error) -> ModbusError {
+        if e.kind() == io::ErrorKind::TimedOut {
+            return ModbusError::Timeout;
+        }
+
+        ModbusError::Io(e)
+    }
+}
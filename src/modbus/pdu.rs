@@ -0,0 +1,1014 @@
+//! Modbus protocol data units
+//!
+//! The PDU (function code and data) is independent of the framing used to
+//! transport it and shared by all transports.
+
+use super::{ExceptionCode, ModbusError};
+
+/// Function codes
+pub mod function {
+    /// Read coils
+    pub const READ_COILS: u8 = 0x01;
+    /// Read discrete inputs
+    pub const READ_DISCRETE_INPUTS: u8 = 0x02;
+    /// Read holding registers
+    pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+    /// Read input registers
+    pub const READ_INPUT_REGISTERS: u8 = 0x04;
+    /// Write single coil
+    pub const WRITE_SINGLE_COIL: u8 = 0x05;
+    /// Write single register
+    pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+    /// Write multiple coils
+    pub const WRITE_MULTIPLE_COILS: u8 = 0x0f;
+    /// Write multiple registers
+    pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+    /// Mask write register
+    pub const MASK_WRITE_REGISTER: u8 = 0x16;
+    /// Read/write multiple registers
+    pub const READ_WRITE_MULTIPLE_REGISTERS: u8 = 0x17;
+    /// Encapsulated interface transport
+    pub const ENCAPSULATED_INTERFACE: u8 = 0x2b;
+
+    /// MEI type of read device identification
+    pub const MEI_READ_DEVICE_ID: u8 = 0x0e;
+    /// Flag set in the function code of exception responses
+    pub const EXCEPTION_FLAG: u8 = 0x80;
+}
+
+use self::function::*;
+
+// quantity limits from the Modbus application protocol specification
+const MAX_READ_BITS: u16 = 2000;
+const MAX_READ_REGISTERS: u16 = 125;
+const MAX_WRITE_BITS: u16 = 1968;
+const MAX_WRITE_REGISTERS: u16 = 123;
+const MAX_RW_WRITE_REGISTERS: u16 = 121;
+
+/// Maximum size of a PDU
+pub const MAX_PDU_LEN: usize = 253;
+
+/// A Modbus request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Read `count` coils starting at `addr`
+    ReadCoils {
+        /// Starting address
+        addr: u16,
+        /// Number of coils
+        count: u16,
+    },
+    /// Read `count` discrete inputs starting at `addr`
+    ReadDiscreteInputs {
+        /// Starting address
+        addr: u16,
+        /// Number of inputs
+        count: u16,
+    },
+    /// Read `count` holding registers starting at `addr`
+    ReadHoldingRegisters {
+        /// Starting address
+        addr: u16,
+        /// Number of registers
+        count: u16,
+    },
+    /// Read `count` input registers starting at `addr`
+    ReadInputRegisters {
+        /// Starting address
+        addr: u16,
+        /// Number of registers
+        count: u16,
+    },
+    /// Write a single coil
+    WriteSingleCoil {
+        /// Coil address
+        addr: u16,
+        /// New state
+        value: bool,
+    },
+    /// Write a single holding register
+    WriteSingleRegister {
+        /// Register address
+        addr: u16,
+        /// New value
+        value: u16,
+    },
+    /// Write consecutive coils starting at `addr`
+    WriteMultipleCoils {
+        /// Starting address
+        addr: u16,
+        /// New states
+        values: Vec<bool>,
+    },
+    /// Write consecutive holding registers starting at `addr`
+    WriteMultipleRegisters {
+        /// Starting address
+        addr: u16,
+        /// New values
+        values: Vec<u16>,
+    },
+    /// Modify a holding register: `(current & and_mask) | (or_mask & !and_mask)`
+    MaskWriteRegister {
+        /// Register address
+        addr: u16,
+        /// AND mask
+        and_mask: u16,
+        /// OR mask
+        or_mask: u16,
+    },
+    /// Write, then read holding registers in a single transaction
+    ReadWriteMultipleRegisters {
+        /// Starting address to read from
+        read_addr: u16,
+        /// Number of registers to read
+        read_count: u16,
+        /// Starting address to write to
+        write_addr: u16,
+        /// Values to write
+        values: Vec<u16>,
+    },
+    /// Read device identification objects
+    ReadDeviceIdentification {
+        /// Read device ID code: 1 (basic), 2 (regular), 3 (extended) or 4
+        /// (single object)
+        read_code: u8,
+        /// First object to read
+        object_id: u8,
+    },
+}
+
+/// Device identification returned by function 43/14
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceIdentification {
+    /// Read device ID code of the request
+    pub read_code: u8,
+    /// Conformity level of the device
+    pub conformity_level: u8,
+    /// Whether more objects are available in another request
+    pub more_follows: bool,
+    /// Object to request next if `more_follows` is set
+    pub next_object_id: u8,
+    /// Object IDs and their values
+    pub objects: Vec<(u8, Vec<u8>)>,
+}
+
+impl DeviceIdentification {
+    /// Look up an object by ID
+    pub fn object(&self, id: u8) -> Option<&[u8]> {
+        self.objects.iter().find(|&&(oid, _)| oid == id).map(|(_, v)| &v[..])
+    }
+}
+
+/// A (non-exception) Modbus response
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// Coil states
+    ReadCoils(Vec<bool>),
+    /// Discrete input states
+    ReadDiscreteInputs(Vec<bool>),
+    /// Holding register values
+    ReadHoldingRegisters(Vec<u16>),
+    /// Input register values
+    ReadInputRegisters(Vec<u16>),
+    /// Echo of a single coil write
+    WriteSingleCoil {
+        /// Coil address
+        addr: u16,
+        /// New state
+        value: bool,
+    },
+    /// Echo of a single register write
+    WriteSingleRegister {
+        /// Register address
+        addr: u16,
+        /// New value
+        value: u16,
+    },
+    /// Confirmation of a multiple coil write
+    WriteMultipleCoils {
+        /// Starting address
+        addr: u16,
+        /// Number of coils written
+        count: u16,
+    },
+    /// Confirmation of a multiple register write
+    WriteMultipleRegisters {
+        /// Starting address
+        addr: u16,
+        /// Number of registers written
+        count: u16,
+    },
+    /// Echo of a mask write
+    MaskWriteRegister {
+        /// Register address
+        addr: u16,
+        /// AND mask
+        and_mask: u16,
+        /// OR mask
+        or_mask: u16,
+    },
+    /// Registers read by a read/write request
+    ReadWriteMultipleRegisters(Vec<u16>),
+    /// Device identification objects
+    ReadDeviceIdentification(DeviceIdentification),
+}
+
+#[inline]
+fn push_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.push((v >> 8) as u8);
+    buf.push(v as u8);
+}
+
+#[inline]
+fn get_u16(data: &[u8], offset: usize) -> u16 {
+    (data[offset] as u16) << 8 | data[offset + 1] as u16
+}
+
+fn check_count(count: u16, max: u16) -> Result<(), ModbusError> {
+    if count == 0 || count > max {
+        return Err(ModbusError::InvalidRequest("quantity out of range"));
+    }
+    Ok(())
+}
+
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+
+    for (i, &bit) in bits.iter().enumerate() {
+        if bit {
+            bytes[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    bytes
+}
+
+fn unpack_bits(bytes: &[u8], count: usize) -> Vec<bool> {
+    (0..count).map(|i| bytes[i / 8] & (1 << (i % 8)) != 0).collect()
+}
+
+fn unpack_registers(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks(2).map(|c| (c[0] as u16) << 8 | c[1] as u16).collect()
+}
+
+impl Request {
+    /// Function code of the request
+    pub fn function(&self) -> u8 {
+        match *self {
+            Request::ReadCoils { .. } => READ_COILS,
+            Request::ReadDiscreteInputs { .. } => READ_DISCRETE_INPUTS,
+            Request::ReadHoldingRegisters { .. } => READ_HOLDING_REGISTERS,
+            Request::ReadInputRegisters { .. } => READ_INPUT_REGISTERS,
+            Request::WriteSingleCoil { .. } => WRITE_SINGLE_COIL,
+            Request::WriteSingleRegister { .. } => WRITE_SINGLE_REGISTER,
+            Request::WriteMultipleCoils { .. } => WRITE_MULTIPLE_COILS,
+            Request::WriteMultipleRegisters { .. } => WRITE_MULTIPLE_REGISTERS,
+            Request::MaskWriteRegister { .. } => MASK_WRITE_REGISTER,
+            Request::ReadWriteMultipleRegisters { .. } => READ_WRITE_MULTIPLE_REGISTERS,
+            Request::ReadDeviceIdentification { .. } => ENCAPSULATED_INTERFACE,
+        }
+    }
+
+    /// Whether the request may be sent as a broadcast
+    ///
+    /// Only requests that write data can be broadcast.
+    pub fn is_broadcastable(&self) -> bool {
+        matches!(*self,
+                 Request::WriteSingleCoil { .. } |
+                 Request::WriteSingleRegister { .. } |
+                 Request::WriteMultipleCoils { .. } |
+                 Request::WriteMultipleRegisters { .. } |
+                 Request::MaskWriteRegister { .. })
+    }
+
+    /// Encode request PDU
+    pub fn encode(&self) -> Result<Vec<u8>, ModbusError> {
+        let mut buf = vec![self.function()];
+
+        match *self {
+            Request::ReadCoils { addr, count } |
+            Request::ReadDiscreteInputs { addr, count } => {
+                check_count(count, MAX_READ_BITS)?;
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, count);
+            }
+            Request::ReadHoldingRegisters { addr, count } |
+            Request::ReadInputRegisters { addr, count } => {
+                check_count(count, MAX_READ_REGISTERS)?;
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, count);
+            }
+            Request::WriteSingleCoil { addr, value } => {
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, if value { 0xff00 } else { 0x0000 });
+            }
+            Request::WriteSingleRegister { addr, value } => {
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, value);
+            }
+            Request::WriteMultipleCoils { addr, ref values } => {
+                check_count(values.len().min(u16::MAX as usize) as u16, MAX_WRITE_BITS)?;
+                let bytes = pack_bits(values);
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, values.len() as u16);
+                buf.push(bytes.len() as u8);
+                buf.extend_from_slice(&bytes);
+            }
+            Request::WriteMultipleRegisters { addr, ref values } => {
+                check_count(values.len().min(u16::MAX as usize) as u16,
+                            MAX_WRITE_REGISTERS)?;
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, values.len() as u16);
+                buf.push((values.len() * 2) as u8);
+                for &v in values {
+                    push_u16(&mut buf, v);
+                }
+            }
+            Request::MaskWriteRegister { addr, and_mask, or_mask } => {
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, and_mask);
+                push_u16(&mut buf, or_mask);
+            }
+            Request::ReadWriteMultipleRegisters { read_addr,
+                                                  read_count,
+                                                  write_addr,
+                                                  ref values } => {
+                check_count(read_count, MAX_READ_REGISTERS)?;
+                check_count(values.len().min(u16::MAX as usize) as u16,
+                            MAX_RW_WRITE_REGISTERS)?;
+                push_u16(&mut buf, read_addr);
+                push_u16(&mut buf, read_count);
+                push_u16(&mut buf, write_addr);
+                push_u16(&mut buf, values.len() as u16);
+                buf.push((values.len() * 2) as u8);
+                for &v in values {
+                    push_u16(&mut buf, v);
+                }
+            }
+            Request::ReadDeviceIdentification { read_code, object_id } => {
+                if read_code == 0 || read_code > 4 {
+                    return Err(ModbusError::InvalidRequest("invalid read device ID code"));
+                }
+                buf.push(MEI_READ_DEVICE_ID);
+                buf.push(read_code);
+                buf.push(object_id);
+            }
+        }
+
+        Ok(buf)
+    }
+
+    /// Decode request PDU
+    ///
+    /// Errors are returned as the exception code a server should respond
+    /// with.
+    pub fn decode(data: &[u8]) -> Result<Request, ExceptionCode> {
+        let function = *data.first().ok_or(ExceptionCode::IllegalFunction)?;
+        let len_is = |len: usize| -> Result<(), ExceptionCode> {
+            if data.len() != len {
+                return Err(ExceptionCode::IllegalDataValue);
+            }
+            Ok(())
+        };
+        let count_in = |count: u16, max: u16| -> Result<(), ExceptionCode> {
+            if count == 0 || count > max {
+                return Err(ExceptionCode::IllegalDataValue);
+            }
+            Ok(())
+        };
+
+        match function {
+            READ_COILS | READ_DISCRETE_INPUTS | READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                len_is(5)?;
+                let addr = get_u16(data, 1);
+                let count = get_u16(data, 3);
+
+                Ok(match function {
+                    READ_COILS => {
+                        count_in(count, MAX_READ_BITS)?;
+                        Request::ReadCoils { addr, count }
+                    }
+                    READ_DISCRETE_INPUTS => {
+                        count_in(count, MAX_READ_BITS)?;
+                        Request::ReadDiscreteInputs { addr, count }
+                    }
+                    READ_HOLDING_REGISTERS => {
+                        count_in(count, MAX_READ_REGISTERS)?;
+                        Request::ReadHoldingRegisters { addr, count }
+                    }
+                    _ => {
+                        count_in(count, MAX_READ_REGISTERS)?;
+                        Request::ReadInputRegisters { addr, count }
+                    }
+                })
+            }
+            WRITE_SINGLE_COIL => {
+                len_is(5)?;
+                let value = match get_u16(data, 3) {
+                    0xff00 => true,
+                    0x0000 => false,
+                    _ => return Err(ExceptionCode::IllegalDataValue),
+                };
+                Ok(Request::WriteSingleCoil {
+                    addr: get_u16(data, 1),
+                    value,
+                })
+            }
+            WRITE_SINGLE_REGISTER => {
+                len_is(5)?;
+                Ok(Request::WriteSingleRegister {
+                    addr: get_u16(data, 1),
+                    value: get_u16(data, 3),
+                })
+            }
+            WRITE_MULTIPLE_COILS => {
+                if data.len() < 6 {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                let count = get_u16(data, 3);
+                count_in(count, MAX_WRITE_BITS)?;
+                if data[5] as usize != (count as usize).div_ceil(8) {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                len_is(6 + data[5] as usize)?;
+                Ok(Request::WriteMultipleCoils {
+                    addr: get_u16(data, 1),
+                    values: unpack_bits(&data[6..], count as usize),
+                })
+            }
+            WRITE_MULTIPLE_REGISTERS => {
+                if data.len() < 6 {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                let count = get_u16(data, 3);
+                count_in(count, MAX_WRITE_REGISTERS)?;
+                if data[5] as usize != count as usize * 2 {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                len_is(6 + data[5] as usize)?;
+                Ok(Request::WriteMultipleRegisters {
+                    addr: get_u16(data, 1),
+                    values: unpack_registers(&data[6..]),
+                })
+            }
+            MASK_WRITE_REGISTER => {
+                len_is(7)?;
+                Ok(Request::MaskWriteRegister {
+                    addr: get_u16(data, 1),
+                    and_mask: get_u16(data, 3),
+                    or_mask: get_u16(data, 5),
+                })
+            }
+            READ_WRITE_MULTIPLE_REGISTERS => {
+                if data.len() < 10 {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                let read_count = get_u16(data, 3);
+                let write_count = get_u16(data, 7);
+                count_in(read_count, MAX_READ_REGISTERS)?;
+                count_in(write_count, MAX_RW_WRITE_REGISTERS)?;
+                if data[9] as usize != write_count as usize * 2 {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                len_is(10 + data[9] as usize)?;
+                Ok(Request::ReadWriteMultipleRegisters {
+                    read_addr: get_u16(data, 1),
+                    read_count,
+                    write_addr: get_u16(data, 5),
+                    values: unpack_registers(&data[10..]),
+                })
+            }
+            ENCAPSULATED_INTERFACE => {
+                if data.len() != 4 || data[1] != MEI_READ_DEVICE_ID {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                if data[2] == 0 || data[2] > 4 {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                Ok(Request::ReadDeviceIdentification {
+                    read_code: data[2],
+                    object_id: data[3],
+                })
+            }
+            _ => Err(ExceptionCode::IllegalFunction),
+        }
+    }
+}
+
+/// Encode an exception response PDU
+#[inline]
+pub fn encode_exception(function: u8, code: ExceptionCode) -> Vec<u8> {
+    vec![function | EXCEPTION_FLAG, code.code()]
+}
+
+impl Response {
+    /// Function code of the response
+    pub fn function(&self) -> u8 {
+        match *self {
+            Response::ReadCoils(_) => READ_COILS,
+            Response::ReadDiscreteInputs(_) => READ_DISCRETE_INPUTS,
+            Response::ReadHoldingRegisters(_) => READ_HOLDING_REGISTERS,
+            Response::ReadInputRegisters(_) => READ_INPUT_REGISTERS,
+            Response::WriteSingleCoil { .. } => WRITE_SINGLE_COIL,
+            Response::WriteSingleRegister { .. } => WRITE_SINGLE_REGISTER,
+            Response::WriteMultipleCoils { .. } => WRITE_MULTIPLE_COILS,
+            Response::WriteMultipleRegisters { .. } => WRITE_MULTIPLE_REGISTERS,
+            Response::MaskWriteRegister { .. } => MASK_WRITE_REGISTER,
+            Response::ReadWriteMultipleRegisters(_) => READ_WRITE_MULTIPLE_REGISTERS,
+            Response::ReadDeviceIdentification(_) => ENCAPSULATED_INTERFACE,
+        }
+    }
+
+    /// Encode response PDU
+    ///
+    /// Fails if the response does not fit into a PDU.
+    pub fn encode(&self) -> Result<Vec<u8>, ExceptionCode> {
+        let mut buf = vec![self.function()];
+
+        match *self {
+            Response::ReadCoils(ref bits) |
+            Response::ReadDiscreteInputs(ref bits) => {
+                let bytes = pack_bits(bits);
+                if bytes.len() > MAX_PDU_LEN - 2 {
+                    return Err(ExceptionCode::ServerDeviceFailure);
+                }
+                buf.push(bytes.len() as u8);
+                buf.extend_from_slice(&bytes);
+            }
+            Response::ReadHoldingRegisters(ref regs) |
+            Response::ReadInputRegisters(ref regs) |
+            Response::ReadWriteMultipleRegisters(ref regs) => {
+                if regs.len() * 2 > MAX_PDU_LEN - 2 {
+                    return Err(ExceptionCode::ServerDeviceFailure);
+                }
+                buf.push((regs.len() * 2) as u8);
+                for &r in regs {
+                    push_u16(&mut buf, r);
+                }
+            }
+            Response::WriteSingleCoil { addr, value } => {
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, if value { 0xff00 } else { 0x0000 });
+            }
+            Response::WriteSingleRegister { addr, value } => {
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, value);
+            }
+            Response::WriteMultipleCoils { addr, count } |
+            Response::WriteMultipleRegisters { addr, count } => {
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, count);
+            }
+            Response::MaskWriteRegister { addr, and_mask, or_mask } => {
+                push_u16(&mut buf, addr);
+                push_u16(&mut buf, and_mask);
+                push_u16(&mut buf, or_mask);
+            }
+            Response::ReadDeviceIdentification(ref id) => {
+                buf.push(MEI_READ_DEVICE_ID);
+                buf.push(id.read_code);
+                buf.push(id.conformity_level);
+                buf.push(if id.more_follows { 0xff } else { 0x00 });
+                buf.push(id.next_object_id);
+                buf.push(id.objects.len() as u8);
+                for &(oid, ref value) in &id.objects {
+                    if value.len() > u8::MAX as usize {
+                        return Err(ExceptionCode::ServerDeviceFailure);
+                    }
+                    buf.push(oid);
+                    buf.push(value.len() as u8);
+                    buf.extend_from_slice(value);
+                }
+                if buf.len() > MAX_PDU_LEN {
+                    return Err(ExceptionCode::ServerDeviceFailure);
+                }
+            }
+        }
+
+        Ok(buf)
+    }
+
+    /// Decode the response PDU `data` to `request`
+    ///
+    /// Exception responses are returned as `ModbusError::Exception`.
+    pub fn decode(request: &Request, data: &[u8]) -> Result<Response, ModbusError> {
+        let invalid = ModbusError::InvalidResponse;
+
+        if data.is_empty() {
+            return Err(invalid("empty PDU"));
+        }
+
+        let function = request.function();
+        if data[0] == function | EXCEPTION_FLAG {
+            if data.len() != 2 {
+                return Err(invalid("malformed exception response"));
+            }
+            return Err(ModbusError::Exception(ExceptionCode::from(data[1])));
+        }
+
+        if data[0] != function {
+            return Err(invalid("function code mismatch"));
+        }
+
+        // checks the length of a fixed-length response
+        let fixed = |len: usize| -> Result<(), ModbusError> {
+            if data.len() != len {
+                return Err(invalid("unexpected length"));
+            }
+            Ok(())
+        };
+
+        // checks a byte-count prefixed response, returns the payload
+        let counted = |expected: usize| -> Result<&[u8], ModbusError> {
+            if data.len() < 2 || data.len() != 2 + data[1] as usize {
+                return Err(invalid("byte count mismatch"));
+            }
+            if data[1] as usize != expected {
+                return Err(invalid("unexpected quantity"));
+            }
+            Ok(&data[2..])
+        };
+
+        match *request {
+            Request::ReadCoils { count, .. } => {
+                let bytes = counted((count as usize).div_ceil(8))?;
+                Ok(Response::ReadCoils(unpack_bits(bytes, count as usize)))
+            }
+            Request::ReadDiscreteInputs { count, .. } => {
+                let bytes = counted((count as usize).div_ceil(8))?;
+                Ok(Response::ReadDiscreteInputs(unpack_bits(bytes, count as usize)))
+            }
+            Request::ReadHoldingRegisters { count, .. } => {
+                let bytes = counted(count as usize * 2)?;
+                Ok(Response::ReadHoldingRegisters(unpack_registers(bytes)))
+            }
+            Request::ReadInputRegisters { count, .. } => {
+                let bytes = counted(count as usize * 2)?;
+                Ok(Response::ReadInputRegisters(unpack_registers(bytes)))
+            }
+            Request::ReadWriteMultipleRegisters { read_count, .. } => {
+                let bytes = counted(read_count as usize * 2)?;
+                Ok(Response::ReadWriteMultipleRegisters(unpack_registers(bytes)))
+            }
+            Request::WriteSingleCoil { .. } => {
+                fixed(5)?;
+                let value = match get_u16(data, 3) {
+                    0xff00 => true,
+                    0x0000 => false,
+                    _ => return Err(invalid("invalid coil value")),
+                };
+                Ok(Response::WriteSingleCoil {
+                    addr: get_u16(data, 1),
+                    value,
+                })
+            }
+            Request::WriteSingleRegister { .. } => {
+                fixed(5)?;
+                Ok(Response::WriteSingleRegister {
+                    addr: get_u16(data, 1),
+                    value: get_u16(data, 3),
+                })
+            }
+            Request::WriteMultipleCoils { .. } => {
+                fixed(5)?;
+                Ok(Response::WriteMultipleCoils {
+                    addr: get_u16(data, 1),
+                    count: get_u16(data, 3),
+                })
+            }
+            Request::WriteMultipleRegisters { .. } => {
+                fixed(5)?;
+                Ok(Response::WriteMultipleRegisters {
+                    addr: get_u16(data, 1),
+                    count: get_u16(data, 3),
+                })
+            }
+            Request::MaskWriteRegister { .. } => {
+                fixed(7)?;
+                Ok(Response::MaskWriteRegister {
+                    addr: get_u16(data, 1),
+                    and_mask: get_u16(data, 3),
+                    or_mask: get_u16(data, 5),
+                })
+            }
+            Request::ReadDeviceIdentification { .. } => {
+                decode_device_identification(data).map(Response::ReadDeviceIdentification)
+            }
+        }
+    }
+}
+
+fn decode_device_identification(data: &[u8]) -> Result<DeviceIdentification, ModbusError> {
+    let invalid = ModbusError::InvalidResponse;
+
+    if data.len() < 7 || data[1] != MEI_READ_DEVICE_ID {
+        return Err(invalid("malformed device identification"));
+    }
+
+    let mut id = DeviceIdentification {
+        read_code: data[2],
+        conformity_level: data[3],
+        more_follows: data[4] == 0xff,
+        next_object_id: data[5],
+        objects: Vec::with_capacity(data[6] as usize),
+    };
+
+    let mut rest = &data[7..];
+    for _ in 0..data[6] {
+        if rest.len() < 2 || rest.len() < 2 + rest[1] as usize {
+            return Err(invalid("truncated device identification object"));
+        }
+        let len = rest[1] as usize;
+        id.objects.push((rest[0], rest[2..2 + len].to_vec()));
+        rest = &rest[2 + len..];
+    }
+
+    if !rest.is_empty() {
+        return Err(invalid("trailing data after device identification objects"));
+    }
+
+    Ok(id)
+}
+
+/// Expected length of a response PDU, given its first bytes
+///
+/// Returns `None` if not enough bytes are available yet or the length cannot
+/// be determined from the header (device identification).
+pub fn expected_response_len(data: &[u8]) -> Option<usize> {
+    let function = *data.first()?;
+
+    if function & EXCEPTION_FLAG != 0 {
+        return Some(2);
+    }
+
+    match function {
+        READ_COILS | READ_DISCRETE_INPUTS | READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS |
+        READ_WRITE_MULTIPLE_REGISTERS => data.get(1).map(|&n| 2 + n as usize),
+        WRITE_SINGLE_COIL | WRITE_SINGLE_REGISTER | WRITE_MULTIPLE_COILS |
+        WRITE_MULTIPLE_REGISTERS => Some(5),
+        MASK_WRITE_REGISTER => Some(7),
+        _ => None,
+    }
+}
+
+/// Expected length of a request PDU, given its first bytes
+///
+/// Returns `None` if not enough bytes are available yet or the function code
+/// is unknown.
+pub fn expected_request_len(data: &[u8]) -> Option<usize> {
+    match *data.first()? {
+        READ_COILS | READ_DISCRETE_INPUTS | READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS |
+        WRITE_SINGLE_COIL | WRITE_SINGLE_REGISTER => Some(5),
+        WRITE_MULTIPLE_COILS | WRITE_MULTIPLE_REGISTERS => data.get(5).map(|&n| 6 + n as usize),
+        MASK_WRITE_REGISTER => Some(7),
+        READ_WRITE_MULTIPLE_REGISTERS => data.get(9).map(|&n| 10 + n as usize),
+        ENCAPSULATED_INTERFACE => Some(4),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check encoding and decoding of a request and its response
+    fn round_trip(request: Request, req: &[u8], response: Response, resp: &[u8]) {
+        assert_eq!(request.encode().unwrap(), req);
+        assert_eq!(Request::decode(req).unwrap(), request);
+        assert_eq!(expected_request_len(req), Some(req.len()));
+
+        assert_eq!(response.encode().unwrap(), resp);
+        assert_eq!(Response::decode(&request, resp).unwrap(), response);
+        assert_eq!(response.function(), request.function());
+    }
+
+    fn bits(s: &str) -> Vec<bool> {
+        s.chars().map(|c| c == '1').collect()
+    }
+
+    // the examples below are from the Modbus application protocol
+    // specification V1.1b3
+
+    #[test]
+    fn read_coils() {
+        round_trip(Request::ReadCoils {
+                       addr: 0x0013,
+                       count: 19,
+                   },
+                   &[0x01, 0x00, 0x13, 0x00, 0x13],
+                   Response::ReadCoils(bits("1011001111010110101")),
+                   &[0x01, 0x03, 0xcd, 0x6b, 0x05]);
+    }
+
+    #[test]
+    fn read_discrete_inputs() {
+        round_trip(Request::ReadDiscreteInputs {
+                       addr: 0x00c4,
+                       count: 22,
+                   },
+                   &[0x02, 0x00, 0xc4, 0x00, 0x16],
+                   Response::ReadDiscreteInputs(bits("0011010111011011101011")),
+                   &[0x02, 0x03, 0xac, 0xdb, 0x35]);
+    }
+
+    #[test]
+    fn read_holding_registers() {
+        round_trip(Request::ReadHoldingRegisters {
+                       addr: 0x006b,
+                       count: 3,
+                   },
+                   &[0x03, 0x00, 0x6b, 0x00, 0x03],
+                   Response::ReadHoldingRegisters(vec![0x022b, 0x0000, 0x0064]),
+                   &[0x03, 0x06, 0x02, 0x2b, 0x00, 0x00, 0x00, 0x64]);
+    }
+
+    #[test]
+    fn read_input_registers() {
+        round_trip(Request::ReadInputRegisters {
+                       addr: 0x0008,
+                       count: 1,
+                   },
+                   &[0x04, 0x00, 0x08, 0x00, 0x01],
+                   Response::ReadInputRegisters(vec![0x000a]),
+                   &[0x04, 0x02, 0x00, 0x0a]);
+    }
+
+    #[test]
+    fn write_single_coil() {
+        round_trip(Request::WriteSingleCoil {
+                       addr: 0x00ac,
+                       value: true,
+                   },
+                   &[0x05, 0x00, 0xac, 0xff, 0x00],
+                   Response::WriteSingleCoil {
+                       addr: 0x00ac,
+                       value: true,
+                   },
+                   &[0x05, 0x00, 0xac, 0xff, 0x00]);
+
+        assert_eq!(Request::decode(&[0x05, 0x00, 0xac, 0x12, 0x34]),
+                   Err(ExceptionCode::IllegalDataValue));
+    }
+
+    #[test]
+    fn write_single_register() {
+        round_trip(Request::WriteSingleRegister {
+                       addr: 0x0001,
+                       value: 0x0003,
+                   },
+                   &[0x06, 0x00, 0x01, 0x00, 0x03],
+                   Response::WriteSingleRegister {
+                       addr: 0x0001,
+                       value: 0x0003,
+                   },
+                   &[0x06, 0x00, 0x01, 0x00, 0x03]);
+    }
+
+    #[test]
+    fn write_multiple_coils() {
+        round_trip(Request::WriteMultipleCoils {
+                       addr: 0x0013,
+                       values: bits("1011001110"),
+                   },
+                   &[0x0f, 0x00, 0x13, 0x00, 0x0a, 0x02, 0xcd, 0x01],
+                   Response::WriteMultipleCoils {
+                       addr: 0x0013,
+                       count: 10,
+                   },
+                   &[0x0f, 0x00, 0x13, 0x00, 0x0a]);
+
+        // byte count does not match the quantity
+        assert_eq!(Request::decode(&[0x0f, 0x00, 0x13, 0x00, 0x0a, 0x01, 0xcd]),
+                   Err(ExceptionCode::IllegalDataValue));
+    }
+
+    #[test]
+    fn write_multiple_registers() {
+        round_trip(Request::WriteMultipleRegisters {
+                       addr: 0x0001,
+                       values: vec![0x000a, 0x0102],
+                   },
+                   &[0x10, 0x00, 0x01, 0x00, 0x02, 0x04, 0x00, 0x0a, 0x01, 0x02],
+                   Response::WriteMultipleRegisters {
+                       addr: 0x0001,
+                       count: 2,
+                   },
+                   &[0x10, 0x00, 0x01, 0x00, 0x02]);
+
+        let too_many = Request::WriteMultipleRegisters {
+            addr: 0,
+            values: vec![0; 124],
+        };
+        assert!(matches!(too_many.encode(), Err(ModbusError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn mask_write_register() {
+        round_trip(Request::MaskWriteRegister {
+                       addr: 0x0004,
+                       and_mask: 0x00f2,
+                       or_mask: 0x0025,
+                   },
+                   &[0x16, 0x00, 0x04, 0x00, 0xf2, 0x00, 0x25],
+                   Response::MaskWriteRegister {
+                       addr: 0x0004,
+                       and_mask: 0x00f2,
+                       or_mask: 0x0025,
+                   },
+                   &[0x16, 0x00, 0x04, 0x00, 0xf2, 0x00, 0x25]);
+    }
+
+    #[test]
+    fn read_write_multiple_registers() {
+        round_trip(Request::ReadWriteMultipleRegisters {
+                       read_addr: 0x0003,
+                       read_count: 6,
+                       write_addr: 0x000e,
+                       values: vec![0x00ff, 0x00ff, 0x00ff],
+                   },
+                   &[0x17, 0x00, 0x03, 0x00, 0x06, 0x00, 0x0e, 0x00, 0x03, 0x06, 0x00, 0xff,
+                     0x00, 0xff, 0x00, 0xff],
+                   Response::ReadWriteMultipleRegisters(vec![0x00fe, 0x0acd, 0x0001, 0x0003,
+                                                             0x000d, 0x00ff]),
+                   &[0x17, 0x0c, 0x00, 0xfe, 0x0a, 0xcd, 0x00, 0x01, 0x00, 0x03, 0x00, 0x0d,
+                     0x00, 0xff]);
+    }
+
+    #[test]
+    fn read_device_identification() {
+        let mut resp = vec![0x2b, 0x0e, 0x01, 0x01, 0x00, 0x00, 0x03, 0x00, 0x16];
+        resp.extend_from_slice(b"Company identification");
+        resp.extend_from_slice(&[0x01, 0x0f]);
+        resp.extend_from_slice(b"Product code XX");
+        resp.extend_from_slice(&[0x02, 0x05]);
+        resp.extend_from_slice(b"V2.11");
+
+        let id = DeviceIdentification {
+            read_code: 1,
+            conformity_level: 1,
+            more_follows: false,
+            next_object_id: 0,
+            objects: vec![(0x00, b"Company identification".to_vec()),
+                          (0x01, b"Product code XX".to_vec()),
+                          (0x02, b"V2.11".to_vec())],
+        };
+        assert_eq!(id.object(0x02), Some(&b"V2.11"[..]));
+
+        round_trip(Request::ReadDeviceIdentification {
+                       read_code: 1,
+                       object_id: 0,
+                   },
+                   &[0x2b, 0x0e, 0x01, 0x00],
+                   Response::ReadDeviceIdentification(id),
+                   &resp);
+        assert_eq!(expected_response_len(&resp), None);
+
+        resp.pop();
+        let request = Request::ReadDeviceIdentification {
+            read_code: 1,
+            object_id: 0,
+        };
+        assert!(matches!(Response::decode(&request, &resp),
+                         Err(ModbusError::InvalidResponse(_))));
+    }
+
+    #[test]
+    fn exception_responses() {
+        let request = Request::ReadInputRegisters {
+            addr: 0x0008,
+            count: 1,
+        };
+
+        assert_eq!(encode_exception(0x04, ExceptionCode::IllegalDataAddress), [0x84, 0x02]);
+        assert!(matches!(Response::decode(&request, &[0x84, 0x02]),
+                         Err(ModbusError::Exception(ExceptionCode::IllegalDataAddress))));
+        assert!(matches!(Response::decode(&request, &[0x84, 0x0b]),
+                         Err(ModbusError::Exception(
+                             ExceptionCode::GatewayTargetFailedToRespond))));
+        assert!(matches!(Response::decode(&request, &[0x84, 0x42]),
+                         Err(ModbusError::Exception(ExceptionCode::Other(0x42)))));
+        assert_eq!(expected_response_len(&[0x84]), Some(2));
+
+        // malformed, or the exception of another function
+        assert!(matches!(Response::decode(&request, &[0x84, 0x02, 0x00]),
+                         Err(ModbusError::InvalidResponse(_))));
+        assert!(matches!(Response::decode(&request, &[0x83, 0x02]),
+                         Err(ModbusError::InvalidResponse(_))));
+    }
+
+    #[test]
+    fn invalid_requests() {
+        assert_eq!(Request::decode(&[]), Err(ExceptionCode::IllegalFunction));
+        assert_eq!(Request::decode(&[0x07]), Err(ExceptionCode::IllegalFunction));
+        assert_eq!(expected_request_len(&[0x07]), None);
+        // quantity of zero, or above the limit
+        assert_eq!(Request::decode(&[0x03, 0x00, 0x00, 0x00, 0x00]),
+                   Err(ExceptionCode::IllegalDataValue));
+        assert_eq!(Request::decode(&[0x01, 0x00, 0x00, 0x07, 0xd1]),
+                   Err(ExceptionCode::IllegalDataValue));
+        assert_eq!(Request::decode(&[0x03, 0x00, 0x6b, 0x00]),
+                   Err(ExceptionCode::IllegalDataValue));
+        assert_eq!(Request::decode(&[0x2b, 0x0e, 0x05, 0x00]),
+                   Err(ExceptionCode::IllegalDataValue));
+    }
+}
@@ -0,0 +1,93 @@
+//! RTU framing
+//!
+//! An RTU frame consists of the unit address, the PDU and a CRC-16 in
+//! little-endian byte order. Frames are separated by at least 3.5 character
+//! times of silence.
+
+use super::ModbusError;
+
+/// Minimum size of an RTU frame: unit, function code and CRC
+pub const MIN_FRAME_LEN: usize = 4;
+
+/// Maximum size of an RTU frame
+pub const MAX_FRAME_LEN: usize = 256;
+
+/// Modbus CRC-16 (polynomial 0xA001, reflected, initial value 0xFFFF)
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xa001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+
+    crc
+}
+
+/// Encode an RTU frame
+pub fn encode_frame(unit: u8, pdu: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(pdu.len() + 3);
+    frame.push(unit);
+    frame.extend_from_slice(pdu);
+
+    let crc = crc16(&frame);
+    frame.push(crc as u8);
+    frame.push((crc >> 8) as u8);
+
+    frame
+}
+
+/// Decode an RTU frame into unit address and PDU
+///
+/// Checks length and CRC.
+pub fn decode_frame(frame: &[u8]) -> Result<(u8, &[u8]), ModbusError> {
+    if frame.len() < MIN_FRAME_LEN {
+        return Err(ModbusError::InvalidResponse("frame too short"));
+    }
+
+    let (body, crc) = frame.split_at(frame.len() - 2);
+    if crc16(body) != (crc[0] as u16 | (crc[1] as u16) << 8) {
+        return Err(ModbusError::Checksum);
+    }
+
+    Ok((body[0], &body[1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_published_vectors() {
+        // read 10 holding registers from unit 1
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0a]), 0xcdc5);
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]), 0x0a84);
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = encode_frame(0x01, &[0x03, 0x00, 0x00, 0x00, 0x0a]);
+        assert_eq!(frame, [0x01, 0x03, 0x00, 0x00, 0x00, 0x0a, 0xc5, 0xcd]);
+
+        let (unit, pdu) = decode_frame(&frame).unwrap();
+        assert_eq!(unit, 0x01);
+        assert_eq!(pdu, [0x03, 0x00, 0x00, 0x00, 0x0a]);
+    }
+
+    #[test]
+    fn corrupt_frames() {
+        let mut frame = encode_frame(0x11, &[0x06, 0x00, 0x01, 0x00, 0x03]);
+        frame[3] ^= 0x01;
+        assert!(matches!(decode_frame(&frame), Err(ModbusError::Checksum)));
+
+        assert!(matches!(decode_frame(&[0x01, 0x03, 0x00]),
+                         Err(ModbusError::InvalidResponse(_))));
+    }
+}
@@ -0,0 +1,436 @@
+//! Modbus slave (server)
+
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use port::Rs485Port;
+use timing::LineTiming;
+use super::{BROADCAST_UNIT, ExceptionCode, Framing, ModbusError};
+use super::ascii;
+use super::pdu::{self, DeviceIdentification, Request, Response};
+
+// see `master::MIN_INTER_CHAR_TIMEOUT`
+const MIN_INTER_CHAR_TIMEOUT: Duration = Duration::from_millis(5);
+
+// how long a single `serve` iteration waits for a request
+const SERVE_POLL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Data model of a Modbus server
+///
+/// Implement this to expose coils, discrete inputs, holding registers and
+/// input registers. Every method defaults to answering with an
+/// `IllegalFunction` exception; returning an `ExceptionCode` from any of them
+/// sends the corresponding exception response.
+pub trait RegisterMap {
+    /// Read `count` coils starting at `addr`
+    fn read_coils(&mut self, _addr: u16, _count: u16) -> Result<Vec<bool>, ExceptionCode> {
+        Err(ExceptionCode::IllegalFunction)
+    }
+
+    /// Read `count` discrete inputs starting at `addr`
+    fn read_discrete_inputs(&mut self,
+                            _addr: u16,
+                            _count: u16)
+                            -> Result<Vec<bool>, ExceptionCode> {
+        Err(ExceptionCode::IllegalFunction)
+    }
+
+    /// Read `count` holding registers starting at `addr`
+    fn read_holding_registers(&mut self,
+                              _addr: u16,
+                              _count: u16)
+                              -> Result<Vec<u16>, ExceptionCode> {
+        Err(ExceptionCode::IllegalFunction)
+    }
+
+    /// Read `count` input registers starting at `addr`
+    fn read_input_registers(&mut self,
+                            _addr: u16,
+                            _count: u16)
+                            -> Result<Vec<u16>, ExceptionCode> {
+        Err(ExceptionCode::IllegalFunction)
+    }
+
+    /// Write coils starting at `addr`
+    fn write_coils(&mut self, _addr: u16, _values: &[bool]) -> Result<(), ExceptionCode> {
+        Err(ExceptionCode::IllegalFunction)
+    }
+
+    /// Write holding registers starting at `addr`
+    fn write_registers(&mut self, _addr: u16, _values: &[u16]) -> Result<(), ExceptionCode> {
+        Err(ExceptionCode::IllegalFunction)
+    }
+
+    /// Read device identification objects
+    fn read_device_identification(&mut self,
+                                  _read_code: u8,
+                                  _object_id: u8)
+                                  -> Result<DeviceIdentification, ExceptionCode> {
+        Err(ExceptionCode::IllegalFunction)
+    }
+}
+
+/// Execute a request against a register map
+///
+/// Mask writes and read/write requests are composed from the basic register
+/// map operations.
+pub fn dispatch<M: RegisterMap + ?Sized>(map: &mut M,
+                                         request: &Request)
+                                         -> Result<Response, ExceptionCode> {
+    // register maps must return exactly the requested quantity
+    fn checked<T>(values: Vec<T>, count: u16) -> Result<Vec<T>, ExceptionCode> {
+        if values.len() != count as usize {
+            return Err(ExceptionCode::ServerDeviceFailure);
+        }
+        Ok(values)
+    }
+
+    Ok(match *request {
+        Request::ReadCoils { addr, count } => {
+            Response::ReadCoils(checked(map.read_coils(addr, count)?, count)?)
+        }
+        Request::ReadDiscreteInputs { addr, count } => {
+            Response::ReadDiscreteInputs(checked(map.read_discrete_inputs(addr, count)?, count)?)
+        }
+        Request::ReadHoldingRegisters { addr, count } => {
+            Response::ReadHoldingRegisters(checked(map.read_holding_registers(addr, count)?,
+                                                   count)?)
+        }
+        Request::ReadInputRegisters { addr, count } => {
+            Response::ReadInputRegisters(checked(map.read_input_registers(addr, count)?, count)?)
+        }
+        Request::WriteSingleCoil { addr, value } => {
+            map.write_coils(addr, &[value])?;
+            Response::WriteSingleCoil { addr, value }
+        }
+        Request::WriteSingleRegister { addr, value } => {
+            map.write_registers(addr, &[value])?;
+            Response::WriteSingleRegister { addr, value }
+        }
+        Request::WriteMultipleCoils { addr, ref values } => {
+            map.write_coils(addr, values)?;
+            Response::WriteMultipleCoils {
+                addr,
+                count: values.len() as u16,
+            }
+        }
+        Request::WriteMultipleRegisters { addr, ref values } => {
+            map.write_registers(addr, values)?;
+            Response::WriteMultipleRegisters {
+                addr,
+                count: values.len() as u16,
+            }
+        }
+        Request::MaskWriteRegister { addr, and_mask, or_mask } => {
+            let current = checked(map.read_holding_registers(addr, 1)?, 1)?[0];
+            map.write_registers(addr, &[(current & and_mask) | (or_mask & !and_mask)])?;
+            Response::MaskWriteRegister {
+                addr,
+                and_mask,
+                or_mask,
+            }
+        }
+        Request::ReadWriteMultipleRegisters { read_addr, read_count, write_addr, ref values } => {
+            // the write is performed before the read
+            map.write_registers(write_addr, values)?;
+            Response::ReadWriteMultipleRegisters(checked(map.read_holding_registers(read_addr,
+                                                                                    read_count)?,
+                                                         read_count)?)
+        }
+        Request::ReadDeviceIdentification { read_code, object_id } => {
+            Response::ReadDeviceIdentification(map.read_device_identification(read_code,
+                                                                              object_id)?)
+        }
+    })
+}
+
+/// Build the response PDU to a request PDU
+///
+/// Returns an exception response if the request is invalid or the register
+/// map refuses it.
+pub fn respond<M: RegisterMap + ?Sized>(map: &mut M, request_pdu: &[u8]) -> Vec<u8> {
+    let function = request_pdu.first().cloned().unwrap_or(0);
+
+    Request::decode(request_pdu)
+        .and_then(|req| dispatch(map, &req))
+        .and_then(|resp| resp.encode())
+        .unwrap_or_else(|code| pdu::encode_exception(function, code))
+}
+
+/// Modbus slave on a serial line
+///
+/// Listens for requests addressed to its unit address or to the broadcast
+/// address and answers them from a `RegisterMap`. Broadcasts are executed,
+/// but never answered.
+#[derive(Debug)]
+pub struct Slave {
+    port: Rs485Port,
+    framing: Framing,
+    unit: u8,
+    inter_char_timeout: Duration,
+    turnaround: Duration,
+}
+
+impl Slave {
+    /// Create a slave using RTU framing
+    ///
+    /// The turnaround delay defaults to t3.5 as derived from the port's line
+    /// settings.
+    #[inline]
+    pub fn new(port: Rs485Port, unit: u8) -> Slave {
+        Slave::with_framing(port, unit, Framing::Rtu)
+    }
+
+    /// Create a slave using the given framing
+    ///
+    /// With ASCII framing, the inter-character timeout defaults to 1 s.
+    pub fn with_framing(port: Rs485Port, unit: u8, framing: Framing) -> Slave {
+        let timing = LineTiming::new(port.settings());
+        let inter_char_timeout = match framing {
+            Framing::Rtu => timing.modbus_t35().max(MIN_INTER_CHAR_TIMEOUT),
+            Framing::Ascii => ascii::INTER_CHAR_TIMEOUT,
+        };
+
+        Slave {
+            port,
+            framing,
+            unit,
+            inter_char_timeout,
+            turnaround: timing.modbus_t35(),
+        }
+    }
+
+    /// Set inter-character timeout
+    ///
+    /// A request is considered complete once the line has been silent for
+    /// this long, if its end cannot be determined from its header or end
+    /// marker.
+    #[inline]
+    pub fn set_inter_char_timeout(&mut self, inter_char_timeout: Duration) -> &mut Self {
+        self.inter_char_timeout = inter_char_timeout;
+        self
+    }
+
+    /// Set turnaround delay
+    ///
+    /// Minimum time between the end of a request and the start of the reply,
+    /// giving the master time to switch its transceiver to receive.
+    #[inline]
+    pub fn set_turnaround(&mut self, turnaround: Duration) -> &mut Self {
+        self.turnaround = turnaround;
+        self
+    }
+
+    /// Framing in use
+    #[inline]
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Unit address
+    #[inline]
+    pub fn unit(&self) -> u8 {
+        self.unit
+    }
+
+    /// Underlying port
+    #[inline]
+    pub fn port(&mut self) -> &mut Rs485Port {
+        &mut self.port
+    }
+
+    /// Release the underlying port
+    #[inline]
+    pub fn into_port(self) -> Rs485Port {
+        self.port
+    }
+
+    /// Wait up to `timeout` for a request and answer it
+    ///
+    /// Returns the request handled, or `None` if no request addressed to this
+    /// unit arrived in time. Frames with checksum errors are reported as
+    /// `ModbusError::Checksum` and not answered.
+    pub fn serve_one<M: RegisterMap + ?Sized>(&mut self,
+                                              map: &mut M,
+                                              timeout: Duration)
+                                              -> Result<Option<Request>, ModbusError> {
+        let (frame, received) = match self.receive(timeout)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+
+        let (unit, request_pdu) = self.framing.decode_frame(&frame).map_err(|e| match e {
+                ModbusError::InvalidResponse(reason) => ModbusError::InvalidRequest(reason),
+                e => e,
+            })?;
+
+        if unit != self.unit && unit != BROADCAST_UNIT {
+            return Ok(None);
+        }
+
+        let response = respond(map, &request_pdu);
+        let request = Request::decode(&request_pdu).ok();
+
+        if unit == BROADCAST_UNIT {
+            return Ok(request);
+        }
+
+        let elapsed = received.elapsed();
+        if elapsed < self.turnaround {
+            thread::sleep(self.turnaround - elapsed);
+        }
+
+        self.port.write_all(&self.framing.encode_frame(unit, &response))?;
+        self.port.drain()?;
+        // with receiving during transmission, the reply comes back; replies
+        // to writes equal the request and would be executed again
+        self.port.discard_input()?;
+
+        Ok(request)
+    }
+
+    /// Serve requests forever
+    ///
+    /// Malformed frames are ignored; only I/O errors are returned.
+    pub fn serve<M: RegisterMap + ?Sized>(&mut self, map: &mut M) -> Result<(), ModbusError> {
+        loop {
+            match self.serve_one(map, SERVE_POLL_TIMEOUT) {
+                Ok(_) |
+                Err(ModbusError::Checksum) |
+                Err(ModbusError::InvalidRequest(_)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Receive a frame
+    ///
+    /// Returns the frame and the time its last byte arrived.
+    fn receive(&mut self, timeout: Duration) -> Result<Option<(Vec<u8>, Instant)>, ModbusError> {
+        let mut buf = vec![0u8; self.framing.max_frame_len()];
+        let mut len = self.port.read_timeout(&mut buf, timeout)?;
+
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let mut last = Instant::now();
+        loop {
+            // only RTU requests addressed to us can be parsed reliably;
+            // others may just as well be responses from other slaves
+            let addressed = self.framing == Framing::Ascii || buf[0] == self.unit ||
+                            buf[0] == BROADCAST_UNIT;
+            if addressed && self.framing.is_complete(&buf[..len], pdu::expected_request_len) {
+                break;
+            }
+
+            if len == buf.len() {
+                break;
+            }
+
+            let n = self.port.read_timeout(&mut buf[len..], self.inter_char_timeout)?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+            last = Instant::now();
+        }
+
+        buf.truncate(len);
+        Ok(Some((buf, last)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::{Loopback, pty_port};
+
+    /// Holding registers only, at addresses `0..registers.len()`
+    struct Registers(Vec<u16>);
+
+    impl RegisterMap for Registers {
+        fn read_holding_registers(&mut self,
+                                  addr: u16,
+                                  count: u16)
+                                  -> Result<Vec<u16>, ExceptionCode> {
+            self.0
+                .get(addr as usize..addr as usize + count as usize)
+                .map(|regs| regs.to_vec())
+                .ok_or(ExceptionCode::IllegalDataAddress)
+        }
+
+        fn write_registers(&mut self, addr: u16, values: &[u16]) -> Result<(), ExceptionCode> {
+            let start = addr as usize;
+            match self.0.get_mut(start..start + values.len()) {
+                Some(regs) => {
+                    regs.copy_from_slice(values);
+                    Ok(())
+                }
+                None => Err(ExceptionCode::IllegalDataAddress),
+            }
+        }
+    }
+
+    fn registers() -> Registers {
+        let mut regs = vec![0; 200];
+        regs[107] = 0x022b;
+        regs[109] = 0x0064;
+        Registers(regs)
+    }
+
+    #[test]
+    fn read_holding_registers() {
+        // example from the Modbus application protocol specification
+        let resp = respond(&mut registers(), &[0x03, 0x00, 0x6b, 0x00, 0x03]);
+        assert_eq!(resp, [0x03, 0x06, 0x02, 0x2b, 0x00, 0x00, 0x00, 0x64]);
+    }
+
+    #[test]
+    fn write_single_register() {
+        let mut map = registers();
+        let resp = respond(&mut map, &[0x06, 0x00, 0x01, 0x00, 0x03]);
+        assert_eq!(resp, [0x06, 0x00, 0x01, 0x00, 0x03]);
+        assert_eq!(map.0[1], 0x0003);
+    }
+
+    #[test]
+    fn mask_write_register() {
+        // example from the Modbus application protocol specification
+        let mut map = registers();
+        map.0[4] = 0x12;
+        let resp = respond(&mut map, &[0x16, 0x00, 0x04, 0x00, 0xf2, 0x00, 0x25]);
+        assert_eq!(resp, [0x16, 0x00, 0x04, 0x00, 0xf2, 0x00, 0x25]);
+        assert_eq!(map.0[4], 0x17);
+    }
+
+    #[test]
+    fn reply_echo_discarded() {
+        let (master, mut port) = pty_port();
+        let mut peer = master.try_clone().unwrap();
+        let loopback = Loopback::new(master);
+        let sent = loopback.sent.clone();
+        port.set_direction_control(loopback);
+
+        let mut slave = Slave::new(port, 0x11);
+        let mut map = registers();
+
+        // write single register, whose reply is identical to the request
+        let request = [0x11, 0x06, 0x00, 0x01, 0x00, 0x03, 0x9a, 0x9b];
+        peer.write_all(&request).unwrap();
+        assert!(slave.serve_one(&mut map, Duration::from_secs(1)).unwrap().is_some());
+        assert_eq!(*sent.lock().unwrap(), request);
+
+        assert!(slave.serve_one(&mut map, Duration::from_millis(100)).unwrap().is_none());
+        assert_eq!(sent.lock().unwrap().len(), request.len());
+    }
+
+    #[test]
+    fn exceptions() {
+        // coils are not implemented
+        assert_eq!(respond(&mut registers(), &[0x01, 0x00, 0x00, 0x00, 0x08]), [0x81, 0x01]);
+        // beyond the last register
+        assert_eq!(respond(&mut registers(), &[0x03, 0x00, 0xc7, 0x00, 0x02]), [0x83, 0x02]);
+    }
+}
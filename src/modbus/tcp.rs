@@ -0,0 +1,140 @@
+//! Modbus TCP framing
+//!
+//! A Modbus TCP application data unit consists of the 7 byte MBAP header
+//! followed by the PDU. Unlike the serial framings, the header carries the
+//! frame length and no checksum is used.
+
+use std::io::{self, Read, Write};
+
+use super::pdu::MAX_PDU_LEN;
+
+/// Registered Modbus TCP port
+pub const DEFAULT_PORT: u16 = 502;
+
+/// Size of the MBAP header
+pub const HEADER_LEN: usize = 7;
+
+/// Protocol identifier of Modbus in the MBAP header
+pub const PROTOCOL_ID: u16 = 0;
+
+/// MBAP header
+///
+/// The length field is derived from the PDU when encoding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Header {
+    /// Transaction identifier, echoed in the response
+    pub transaction_id: u16,
+    /// Protocol identifier, `PROTOCOL_ID` for Modbus
+    pub protocol_id: u16,
+    /// Unit identifier of the target behind a gateway
+    pub unit: u8,
+}
+
+impl Header {
+    /// Create a Modbus header
+    #[inline]
+    pub fn new(transaction_id: u16, unit: u8) -> Header {
+        Header {
+            transaction_id,
+            protocol_id: PROTOCOL_ID,
+            unit,
+        }
+    }
+}
+
+/// Encode an application data unit
+pub fn encode_adu(header: &Header, pdu: &[u8]) -> Vec<u8> {
+    let len = pdu.len() as u16 + 1;
+
+    let mut adu = Vec::with_capacity(HEADER_LEN + pdu.len());
+    adu.extend_from_slice(&header.transaction_id.to_be_bytes());
+    adu.extend_from_slice(&header.protocol_id.to_be_bytes());
+    adu.extend_from_slice(&len.to_be_bytes());
+    adu.push(header.unit);
+    adu.extend_from_slice(pdu);
+
+    adu
+}
+
+/// Read an application data unit
+///
+/// Returns `None` if the stream is closed before the first byte of a header.
+/// Headers announcing an impossible length are reported as `InvalidData`.
+pub fn read_adu<R: Read>(reader: &mut R) -> io::Result<Option<(Header, Vec<u8>)>> {
+    let mut hdr = [0u8; HEADER_LEN];
+
+    // distinguish a clean shutdown from a truncated header
+    let n = reader.read(&mut hdr)?;
+    if n == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut hdr[n..])?;
+
+    let header = Header {
+        transaction_id: u16::from_be_bytes([hdr[0], hdr[1]]),
+        protocol_id: u16::from_be_bytes([hdr[2], hdr[3]]),
+        unit: hdr[6],
+    };
+
+    let len = u16::from_be_bytes([hdr[4], hdr[5]]) as usize;
+    if !(2..=MAX_PDU_LEN + 1).contains(&len) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid MBAP length"));
+    }
+
+    let mut pdu = vec![0u8; len - 1];
+    reader.read_exact(&mut pdu)?;
+
+    Ok(Some((header, pdu)))
+}
+
+/// Write an application data unit
+#[inline]
+pub fn write_adu<W: Write>(writer: &mut W, header: &Header, pdu: &[u8]) -> io::Result<()> {
+    writer.write_all(&encode_adu(header, pdu))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+
+    // read 10 holding registers from unit 1, transaction 1
+    const ADU: &[u8] = &[0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x0a];
+
+    #[test]
+    fn encode() {
+        let adu = encode_adu(&Header::new(1, 1), &[0x03, 0x00, 0x00, 0x00, 0x0a]);
+        assert_eq!(adu, ADU);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = Vec::new();
+        let header = Header::new(0xbeef, 0x11);
+        write_adu(&mut buf, &header, &[0x06, 0x00, 0x01, 0x00, 0x03]).unwrap();
+        write_adu(&mut buf, &Header::new(2, 1), &[0x03, 0x00, 0x00, 0x00, 0x0a]).unwrap();
+
+        let mut reader = Cursor::new(buf);
+        let (read_header, pdu) = read_adu(&mut reader).unwrap().unwrap();
+        assert_eq!(read_header, header);
+        assert_eq!(pdu, [0x06, 0x00, 0x01, 0x00, 0x03]);
+
+        let (read_header, pdu) = read_adu(&mut reader).unwrap().unwrap();
+        assert_eq!(read_header, Header::new(2, 1));
+        assert_eq!(pdu, [0x03, 0x00, 0x00, 0x00, 0x0a]);
+
+        assert!(read_adu(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_adus() {
+        let mut short = ADU.to_vec();
+        short[5] = 0x01;
+        let e = read_adu(&mut Cursor::new(short)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let e = read_adu(&mut Cursor::new(&ADU[..9])).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
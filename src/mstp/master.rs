@@ -0,0 +1,674 @@
+//! MS/TP master node
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use port::Rs485Port;
+use super::{BROADCAST_ADDRESS, Frame, FrameDecoder, FrameError, FrameType, MAX_DATA_LEN,
+            MAX_MASTER_ADDRESS, MstpTiming};
+
+// number of tokens received or used before polling for a new master
+const NPOLL: u32 = 50;
+
+// number of retries when passing the token
+const NRETRY_TOKEN: u32 = 1;
+
+// minimum number of octets seen to consider the line active
+const NMIN_OCTETS: u32 = 4;
+
+/// NPDU exchanged with the network layer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Npdu {
+    /// Source address
+    pub source: u8,
+    /// Destination address, `BROADCAST_ADDRESS` for broadcasts
+    pub destination: u8,
+    /// Whether the sender expects a reply
+    pub expecting_reply: bool,
+    /// NPDU
+    pub data: Vec<u8>,
+}
+
+/// State of the master node state machine (clause 9.5.6)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    UseToken,
+    WaitForReply,
+    DoneWithToken,
+    PassToken,
+    NoToken,
+    PollForMaster,
+    AnswerDataRequest {
+        requester: u8,
+        since: Instant,
+    },
+}
+
+/// MS/TP master node
+///
+/// Takes part in token passing and exchanges NPDUs with other nodes. The
+/// state machine only runs inside `receive` and `send`; an application must
+/// call `receive` continuously, e.g. from a dedicated thread, to keep the
+/// node in the token ring.
+///
+/// Outgoing NPDUs are queued until this node holds the token. A reply to a
+/// data request just returned by `receive` is sent immediately instead, as
+/// long as it is sent within Treply_delay; otherwise the requester is told
+/// the reply was postponed and it is queued as well.
+#[derive(Debug)]
+pub struct MasterNode {
+    port: Rs485Port,
+    timing: MstpTiming,
+    station: u8,
+    max_master: u8,
+    max_info_frames: u32,
+
+    state: State,
+    decoder: FrameDecoder,
+    // time of the last octet received or transmitted
+    last_activity: Instant,
+    // octets of our last transmission not yet seen echoed back
+    echo: VecDeque<u8>,
+    next_station: u8,
+    poll_station: u8,
+    token_count: u32,
+    frame_count: u32,
+    retry_count: u32,
+    event_count: u32,
+    sole_master: bool,
+
+    outgoing: VecDeque<Frame>,
+    incoming: VecDeque<Npdu>,
+}
+
+impl MasterNode {
+    /// Create a master node with address `station`
+    ///
+    /// Timing is derived from the port's line settings. Defaults to
+    /// `Nmax_master` 127 and `Nmax_info_frames` 1.
+    pub fn new(port: Rs485Port, station: u8) -> io::Result<MasterNode> {
+        if station > MAX_MASTER_ADDRESS {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "MS/TP master address must be 0 to 127"));
+        }
+
+        let timing = MstpTiming::new(port.settings());
+
+        // INITIALIZE
+        Ok(MasterNode {
+            port,
+            timing,
+            station,
+            max_master: MAX_MASTER_ADDRESS,
+            max_info_frames: 1,
+            state: State::Idle,
+            decoder: FrameDecoder::new(),
+            last_activity: Instant::now(),
+            echo: VecDeque::new(),
+            next_station: station,
+            poll_station: station,
+            token_count: NPOLL,
+            frame_count: 0,
+            retry_count: 0,
+            event_count: 0,
+            sole_master: false,
+            outgoing: VecDeque::new(),
+            incoming: VecDeque::new(),
+        })
+    }
+
+    /// Set timing parameters
+    #[inline]
+    pub fn set_timing(&mut self, timing: MstpTiming) -> &mut Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Set Nmax_master, the highest address polled for masters
+    ///
+    /// Must not be below this node's address.
+    #[inline]
+    pub fn set_max_master(&mut self, max_master: u8) -> &mut Self {
+        self.max_master = max_master.clamp(self.station, MAX_MASTER_ADDRESS);
+        self
+    }
+
+    /// Set Nmax_info_frames, the number of frames sent per token
+    #[inline]
+    pub fn set_max_info_frames(&mut self, max_info_frames: u32) -> &mut Self {
+        self.max_info_frames = max_info_frames.max(1);
+        self
+    }
+
+    /// Timing parameters
+    #[inline]
+    pub fn timing(&self) -> &MstpTiming {
+        &self.timing
+    }
+
+    /// Address of this node
+    #[inline]
+    pub fn station(&self) -> u8 {
+        self.station
+    }
+
+    /// Address the token is passed to, if another master is known
+    #[inline]
+    pub fn next_station(&self) -> Option<u8> {
+        if self.next_station == self.station { None } else { Some(self.next_station) }
+    }
+
+    /// Whether this node is the only master on the line
+    #[inline]
+    pub fn is_sole_master(&self) -> bool {
+        self.sole_master
+    }
+
+    /// Underlying port
+    #[inline]
+    pub fn port(&mut self) -> &mut Rs485Port {
+        &mut self.port
+    }
+
+    /// Release the underlying port
+    ///
+    /// Queued NPDUs are dropped.
+    #[inline]
+    pub fn into_port(self) -> Rs485Port {
+        self.port
+    }
+
+    /// Send an NPDU
+    ///
+    /// If `destination` is waiting for a reply from this node, `data` is sent
+    /// right away as the reply. Otherwise it is queued until this node holds
+    /// the token. Broadcasts never expect a reply.
+    pub fn send(&mut self,
+                destination: u8,
+                data: Vec<u8>,
+                expecting_reply: bool)
+                -> io::Result<()> {
+        if data.len() > MAX_DATA_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "NPDU too long for MS/TP"));
+        }
+
+        let expecting_reply = expecting_reply && destination != BROADCAST_ADDRESS;
+        let frame = Frame {
+            frame_type: if expecting_reply {
+                FrameType::DataExpectingReply
+            } else {
+                FrameType::DataNotExpectingReply
+            },
+            destination,
+            source: self.station,
+            data,
+        };
+
+        match self.state {
+            State::AnswerDataRequest { requester, since }
+                if requester == destination && since.elapsed() < self.timing.reply_delay() => {
+                self.transmit(&frame)?;
+                self.state = State::Idle;
+            }
+            _ => self.outgoing.push_back(frame),
+        }
+
+        Ok(())
+    }
+
+    /// Run the state machine for up to `timeout`, returning the next NPDU
+    /// received
+    ///
+    /// Returns `None` if no NPDU addressed to this node or broadcast arrived
+    /// in time.
+    pub fn receive(&mut self, timeout: Duration) -> io::Result<Option<Npdu>> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 256];
+
+        loop {
+            self.run_active()?;
+
+            if let Some(npdu) = self.incoming.pop_front() {
+                return Ok(Some(npdu));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            let wait = self.next_timeout(now).min(deadline - now);
+            let n = self.port.read_timeout(&mut buf, wait)?;
+
+            if n > 0 {
+                // the echo of our own transmission is no sign of activity
+                let echoed = self.skip_echo(&buf[..n]);
+                if echoed < n {
+                    self.last_activity = Instant::now();
+                    self.event_count = self.event_count.saturating_add((n - echoed) as u32);
+                }
+
+                // SawTokenUser and SawFrame
+                if (self.state == State::PassToken || self.state == State::NoToken) &&
+                   self.event_count > NMIN_OCTETS {
+                    self.state = State::Idle;
+                }
+
+                for &byte in &buf[..n] {
+                    if let Some(result) = self.decoder.feed(byte) {
+                        self.on_frame(result)?;
+                    }
+                }
+            } else if self.decoder.in_frame() &&
+                      self.last_activity.elapsed() >= self.timing.frame_abort() {
+                if let Some(result) = self.decoder.abort() {
+                    self.on_frame(result)?;
+                }
+            }
+
+            self.on_timer()?;
+        }
+    }
+
+    /// Number of leading octets of `data` that echo our last transmission
+    ///
+    /// Stops expecting an echo once other data arrives.
+    fn skip_echo(&mut self, data: &[u8]) -> usize {
+        let mut echoed = 0;
+        while echoed < data.len() && self.echo.front() == Some(&data[echoed]) {
+            self.echo.pop_front();
+            echoed += 1;
+        }
+
+        if echoed < data.len() {
+            self.echo.clear();
+        }
+        echoed
+    }
+
+    /// Time until the next timer driven transition may happen
+    fn next_timeout(&self, now: Instant) -> Duration {
+        let silence = now.saturating_duration_since(self.last_activity);
+        let t = &self.timing;
+
+        let limit = match self.state {
+            State::Idle => t.no_token(),
+            State::WaitForReply => t.reply_timeout(),
+            State::PassToken | State::PollForMaster => t.usage_timeout(),
+            State::NoToken => t.no_token() + t.slot() * self.station as u32,
+            State::AnswerDataRequest { since, .. } => {
+                return (since + t.reply_delay()).saturating_duration_since(now);
+            }
+            State::UseToken | State::DoneWithToken => Duration::from_millis(0),
+        };
+
+        let mut wait = limit.saturating_sub(silence);
+        if self.decoder.in_frame() {
+            wait = wait.min(t.frame_abort().saturating_sub(silence));
+        }
+        wait
+    }
+
+    /// Handle a received frame, or the failure to receive one
+    fn on_frame(&mut self, result: Result<Frame, FrameError>) -> io::Result<()> {
+        // our own transmissions may be echoed back
+        let frame = match result {
+            Ok(ref frame) if frame.source == self.station => return Ok(()),
+            Ok(frame) => Some(frame),
+            Err(_) => None,
+        };
+
+        match self.state {
+            State::Idle => {
+                if let Some(frame) = frame {
+                    self.idle_frame(frame)?;
+                }
+            }
+            State::WaitForReply => {
+                let frame = match frame {
+                    Some(frame) => frame,
+                    // ReceivedInvalidFrame
+                    None => {
+                        self.state = State::DoneWithToken;
+                        return Ok(());
+                    }
+                };
+
+                if frame.destination != self.station {
+                    // ReceivedUnexpectedFrame
+                    self.state = State::Idle;
+                    return Ok(());
+                }
+
+                self.state = match frame.frame_type {
+                    FrameType::DataNotExpectingReply => {
+                        self.indicate(frame, false);
+                        State::DoneWithToken
+                    }
+                    FrameType::TestResponse | FrameType::ReplyPostponed => State::DoneWithToken,
+                    _ => State::Idle,
+                };
+            }
+            State::PollForMaster => {
+                match frame {
+                    Some(ref frame) if frame.destination == self.station &&
+                                       frame.frame_type == FrameType::ReplyToPollForMaster => {
+                        // ReceivedReplyToPFM
+                        self.sole_master = false;
+                        self.next_station = frame.source;
+                        self.poll_station = self.station;
+                        self.token_count = 0;
+                        self.pass_token()?;
+                    }
+                    // ReceivedUnexpectedFrame
+                    Some(_) => self.state = State::Idle,
+                    None => self.poll_done()?,
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Handle a valid frame in the IDLE state
+    fn idle_frame(&mut self, frame: Frame) -> io::Result<()> {
+        let for_us = frame.destination == self.station;
+        let broadcast = frame.destination == BROADCAST_ADDRESS;
+
+        match frame.frame_type {
+            FrameType::Token if for_us => {
+                // ReceivedToken
+                self.frame_count = 0;
+                self.sole_master = false;
+                self.state = State::UseToken;
+            }
+            FrameType::PollForMaster if for_us => {
+                // ReceivedPFM
+                let reply = Frame::control(FrameType::ReplyToPollForMaster,
+                                           frame.source,
+                                           self.station);
+                self.transmit(&reply)?;
+            }
+            FrameType::DataNotExpectingReply if for_us || broadcast => self.indicate(frame, false),
+            FrameType::DataExpectingReply if for_us => {
+                // ReceivedDataNeedingReply
+                self.state = State::AnswerDataRequest {
+                    requester: frame.source,
+                    since: Instant::now(),
+                };
+                self.indicate(frame, true);
+            }
+            FrameType::TestRequest if for_us => {
+                let reply = Frame {
+                    frame_type: FrameType::TestResponse,
+                    destination: frame.source,
+                    source: self.station,
+                    data: frame.data,
+                };
+                self.transmit(&reply)?;
+            }
+            // ReceivedUnwantedFrame
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Handle timer driven transitions
+    fn on_timer(&mut self) -> io::Result<()> {
+        let silence = self.last_activity.elapsed();
+        let t = self.timing;
+
+        match self.state {
+            State::Idle if silence >= t.no_token() => {
+                // LostToken
+                self.event_count = 0;
+                self.state = State::NoToken;
+            }
+            State::WaitForReply if silence >= t.reply_timeout() => {
+                // ReplyTimeout
+                self.frame_count = self.max_info_frames;
+                self.state = State::DoneWithToken;
+            }
+            State::PassToken if silence >= t.usage_timeout() => {
+                if self.retry_count < NRETRY_TOKEN {
+                    // RetrySendToken
+                    self.retry_count += 1;
+                    self.event_count = 0;
+                    let token = Frame::control(FrameType::Token, self.next_station, self.station);
+                    self.transmit(&token)?;
+                } else {
+                    // FindNewSuccessor
+                    self.poll_station = self.successor(self.next_station);
+                    self.next_station = self.station;
+                    self.token_count = 0;
+                    self.poll_for_master()?;
+                }
+            }
+            // Generating the token is allowed in our slot; being late only
+            // happens if no lower address did either
+            State::NoToken if silence >= t.no_token() + t.slot() * self.station as u32 => {
+                // GenerateToken
+                self.poll_station = self.successor(self.station);
+                self.next_station = self.station;
+                self.token_count = 0;
+                self.poll_for_master()?;
+            }
+            State::PollForMaster if silence >= t.usage_timeout() => self.poll_done()?,
+            State::AnswerDataRequest { requester, since } if since.elapsed() >=
+                                                             t.reply_delay() => {
+                // DeferredReply
+                let postponed = Frame::control(FrameType::ReplyPostponed, requester, self.station);
+                self.transmit(&postponed)?;
+                self.state = State::Idle;
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Run the states that do not wait for the line
+    fn run_active(&mut self) -> io::Result<()> {
+        loop {
+            match self.state {
+                State::UseToken => {
+                    match self.outgoing.pop_front() {
+                        // NothingToSend
+                        None => {
+                            self.frame_count = self.max_info_frames;
+                            self.state = State::DoneWithToken;
+                        }
+                        Some(frame) => {
+                            self.transmit(&frame)?;
+                            self.frame_count += 1;
+                            self.state = if frame.frame_type == FrameType::DataExpectingReply {
+                                State::WaitForReply
+                            } else {
+                                State::DoneWithToken
+                            };
+                        }
+                    }
+                }
+                State::DoneWithToken => self.done_with_token()?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// DONE_WITH_TOKEN transitions
+    fn done_with_token(&mut self) -> io::Result<()> {
+        if self.frame_count < self.max_info_frames {
+            // SendAnotherFrame
+            self.state = State::UseToken;
+        } else if !self.sole_master && self.next_station == self.station {
+            // NextStationUnknown
+            self.poll_station = self.successor(self.station);
+            self.poll_for_master()?;
+        } else if self.token_count < NPOLL - 1 {
+            self.token_count += 1;
+            if self.sole_master && self.next_station != self.successor(self.station) {
+                // SoleMaster
+                self.frame_count = 0;
+                self.state = State::UseToken;
+            } else {
+                // SendToken
+                self.pass_token()?;
+            }
+        } else if self.successor(self.poll_station) != self.next_station {
+            // SendMaintenancePFM
+            self.poll_station = self.successor(self.poll_station);
+            self.poll_for_master()?;
+        } else if !self.sole_master {
+            // ResetMaintenancePFM
+            self.poll_station = self.station;
+            self.token_count = 1;
+            self.pass_token()?;
+        } else {
+            // SoleMasterRestartMaintenancePFM
+            self.poll_station = self.successor(self.next_station);
+            self.next_station = self.station;
+            self.token_count = 1;
+            self.poll_for_master()?;
+        }
+
+        Ok(())
+    }
+
+    /// POLL_FOR_MASTER transitions after a timeout or invalid frame
+    fn poll_done(&mut self) -> io::Result<()> {
+        if self.sole_master {
+            // SoleMaster
+            self.frame_count = 0;
+            self.state = State::UseToken;
+        } else if self.next_station != self.station {
+            // DoneWithPFM
+            self.pass_token()?;
+        } else if self.successor(self.poll_station) != self.station {
+            // SendNextPFM
+            self.poll_station = self.successor(self.poll_station);
+            self.poll_for_master()?;
+        } else {
+            // DeclareSoleMaster
+            self.sole_master = true;
+            self.frame_count = 0;
+            self.state = State::UseToken;
+        }
+
+        Ok(())
+    }
+
+    /// Pass the token to the next station and enter PASS_TOKEN
+    fn pass_token(&mut self) -> io::Result<()> {
+        self.retry_count = 0;
+        self.event_count = 0;
+        let token = Frame::control(FrameType::Token, self.next_station, self.station);
+        self.transmit(&token)?;
+        self.state = State::PassToken;
+        Ok(())
+    }
+
+    /// Poll the poll station for a master and enter POLL_FOR_MASTER
+    fn poll_for_master(&mut self) -> io::Result<()> {
+        self.retry_count = 0;
+        self.event_count = 0;
+        let pfm = Frame::control(FrameType::PollForMaster, self.poll_station, self.station);
+        self.transmit(&pfm)?;
+        self.state = State::PollForMaster;
+        Ok(())
+    }
+
+    /// Address following `address` in the token ring
+    #[inline]
+    fn successor(&self, address: u8) -> u8 {
+        ((address as u16 + 1) % (self.max_master as u16 + 1)) as u8
+    }
+
+    /// Pass a received data frame to the network layer
+    fn indicate(&mut self, frame: Frame, expecting_reply: bool) {
+        self.incoming.push_back(Npdu {
+            source: frame.source,
+            destination: frame.destination,
+            expecting_reply,
+            data: frame.data,
+        });
+    }
+
+    /// Send a frame after the turnaround time
+    fn transmit(&mut self, frame: &Frame) -> io::Result<()> {
+        let encoded = frame.encode()?;
+
+        let ready = self.last_activity + self.timing.turnaround();
+        let now = Instant::now();
+        if ready > now {
+            thread::sleep(ready - now);
+        }
+
+        // input is left alone, the next station may answer within
+        // Tturnaround; an echo of the frame is neither counted as activity
+        // nor handled as a received frame
+        self.port.write_all(&encoded)?;
+        self.port.drain()?;
+        self.last_activity = Instant::now();
+        self.echo = encoded.into_iter().collect();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::{Loopback, pty_port};
+
+    #[test]
+    fn token_retry_with_echo() {
+        let (master, mut port) = pty_port();
+        let loopback = Loopback::new(master);
+        let sent = loopback.sent.clone();
+        port.set_direction_control(loopback);
+
+        let mut node = MasterNode::new(port, 1).unwrap();
+        node.set_max_master(3);
+        node.next_station = 2;
+        node.pass_token().unwrap();
+
+        // station 2 never uses the token: retry once, then look for a new
+        // successor; the echo of our own token must not count as its use
+        node.receive(Duration::from_millis(150)).unwrap();
+
+        let token = Frame::control(FrameType::Token, 2, 1).encode().unwrap();
+        let pfm = Frame::control(FrameType::PollForMaster, 3, 1).encode().unwrap();
+        let sent = sent.lock().unwrap();
+        assert!(sent.len() >= 3 * token.len());
+        assert_eq!(sent[..8], token[..]);
+        assert_eq!(sent[8..16], token[..]);
+        assert_eq!(sent[16..24], pfm[..]);
+        assert_eq!(node.next_station(), None);
+    }
+
+    #[test]
+    fn token_used_by_next_station() {
+        let (master, mut port) = pty_port();
+        let mut peer = master.try_clone().unwrap();
+        let loopback = Loopback::new(master);
+        let sent = loopback.sent.clone();
+        port.set_direction_control(loopback);
+
+        let mut node = MasterNode::new(port, 1).unwrap();
+        node.set_max_master(3);
+        node.next_station = 2;
+        node.pass_token().unwrap();
+
+        // station 2 passes the token on to station 3
+        peer.write_all(&Frame::control(FrameType::Token, 3, 2).encode().unwrap()).unwrap();
+        node.receive(Duration::from_millis(100)).unwrap();
+
+        assert_eq!(node.state, State::Idle);
+        assert_eq!(sent.lock().unwrap().len(), 8);
+    }
+}
@@ -0,0 +1,569 @@
+//! BACnet MS/TP data link layer
+//!
+//! MS/TP (master-slave/token-passing, ASHRAE 135 clause 9) shares an RS485
+//! line between up to 128 master nodes passing a token. Only the holder of
+//! the token may initiate transmissions. Frames carry a CRC-8 over the header
+//! and a CRC-16 over the data.
+//!
+//! `MasterNode` implements the master node state machine and exchanges NPDUs
+//! with the network layer.
+
+use std::{error, fmt, io};
+use std::time::Duration;
+
+use port::PortSettings;
+use timing::LineTiming;
+
+pub mod master;
+
+pub use self::master::{MasterNode, Npdu};
+
+/// Address used for broadcast frames
+pub const BROADCAST_ADDRESS: u8 = 255;
+
+/// Highest address of a master node
+pub const MAX_MASTER_ADDRESS: u8 = 127;
+
+/// Maximum size of the data in a frame
+pub const MAX_DATA_LEN: usize = 501;
+
+/// Size of preamble and header, including the header CRC
+pub const HEADER_LEN: usize = 8;
+
+const PREAMBLE: [u8; 2] = [0x55, 0xff];
+
+// CRC remainders of a correctly received header and data block
+const HEADER_CRC_RESIDUE: u8 = 0x55;
+const DATA_CRC_RESIDUE: u16 = 0xf0b8;
+
+/// Type of an MS/TP frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FrameType {
+    /// Passes the token to the destination (0)
+    Token,
+    /// Asks whether the destination is a master node (1)
+    PollForMaster,
+    /// Answers a poll for master (2)
+    ReplyToPollForMaster,
+    /// Asks the destination to echo the data (3)
+    TestRequest,
+    /// Echoes the data of a test request (4)
+    TestResponse,
+    /// NPDU that requires a reply (5)
+    DataExpectingReply,
+    /// NPDU that requires no reply (6)
+    DataNotExpectingReply,
+    /// Reply to a data request will be sent later (7)
+    ReplyPostponed,
+    /// Any other frame type
+    Other(u8),
+}
+
+impl FrameType {
+    /// Frame type value
+    pub fn code(self) -> u8 {
+        match self {
+            FrameType::Token => 0,
+            FrameType::PollForMaster => 1,
+            FrameType::ReplyToPollForMaster => 2,
+            FrameType::TestRequest => 3,
+            FrameType::TestResponse => 4,
+            FrameType::DataExpectingReply => 5,
+            FrameType::DataNotExpectingReply => 6,
+            FrameType::ReplyPostponed => 7,
+            FrameType::Other(code) => code,
+        }
+    }
+}
+
+impl From<u8> for FrameType {
+    fn from(code: u8) -> FrameType {
+        match code {
+            0 => FrameType::Token,
+            1 => FrameType::PollForMaster,
+            2 => FrameType::ReplyToPollForMaster,
+            3 => FrameType::TestRequest,
+            4 => FrameType::TestResponse,
+            5 => FrameType::DataExpectingReply,
+            6 => FrameType::DataNotExpectingReply,
+            7 => FrameType::ReplyPostponed,
+            code => FrameType::Other(code),
+        }
+    }
+}
+
+/// MS/TP frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Frame type
+    pub frame_type: FrameType,
+    /// Destination address
+    pub destination: u8,
+    /// Source address
+    pub source: u8,
+    /// Data, empty for control frames
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// Create a frame without data
+    #[inline]
+    pub fn control(frame_type: FrameType, destination: u8, source: u8) -> Frame {
+        Frame {
+            frame_type,
+            destination,
+            source,
+            data: Vec::new(),
+        }
+    }
+
+    /// Encode into preamble, header and data
+    ///
+    /// Fails if the data exceeds `MAX_DATA_LEN`.
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        if self.data.len() > MAX_DATA_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "MS/TP frame data too long"));
+        }
+
+        let len = self.data.len() as u16;
+        let mut frame = Vec::with_capacity(HEADER_LEN + self.data.len() + 2);
+        frame.extend_from_slice(&PREAMBLE);
+        frame.push(self.frame_type.code());
+        frame.push(self.destination);
+        frame.push(self.source);
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.push(!header_crc(&frame[2..]));
+
+        if !self.data.is_empty() {
+            frame.extend_from_slice(&self.data);
+            let crc = !data_crc(&self.data);
+            frame.extend_from_slice(&crc.to_le_bytes());
+        }
+
+        Ok(frame)
+    }
+}
+
+/// Header CRC-8 (ASHRAE 135 Annex G.1)
+///
+/// The ones complement of the result is transmitted.
+pub fn header_crc(data: &[u8]) -> u8 {
+    let mut crc = 0xffu8;
+
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0x81;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+
+    crc
+}
+
+/// Data CRC-16 (ASHRAE 135 Annex G.2)
+///
+/// The ones complement of the result is transmitted, least significant byte
+/// first.
+pub fn data_crc(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0x8408;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+
+    crc
+}
+
+/// Reason a frame was rejected
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// Header CRC mismatch
+    HeaderCrc,
+    /// Data CRC mismatch
+    DataCrc,
+    /// Announced length exceeds `MAX_DATA_LEN`; the data was skipped
+    TooLong,
+    /// The line fell silent in the middle of a frame
+    Aborted,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            FrameError::HeaderCrc => "header CRC mismatch",
+            FrameError::DataCrc => "data CRC mismatch",
+            FrameError::TooLong => "frame too long",
+            FrameError::Aborted => "frame aborted",
+        })
+    }
+}
+
+impl error::Error for FrameError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DecoderState {
+    Idle,
+    Preamble,
+    Header,
+    Data,
+    SkipData,
+}
+
+/// Receive state machine for MS/TP frames
+///
+/// Fed one byte at a time; bytes outside of frames are ignored. Aborting
+/// frames after `Tframe_abort` of silence is left to the caller.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    state: DecoderState,
+    header: [u8; 6],
+    index: usize,
+    data: Vec<u8>,
+    remaining: usize,
+}
+
+impl Default for FrameDecoder {
+    #[inline]
+    fn default() -> FrameDecoder {
+        FrameDecoder::new()
+    }
+}
+
+impl FrameDecoder {
+    /// Create a decoder waiting for a preamble
+    pub fn new() -> FrameDecoder {
+        FrameDecoder {
+            state: DecoderState::Idle,
+            header: [0; 6],
+            index: 0,
+            data: Vec::with_capacity(MAX_DATA_LEN + 2),
+            remaining: 0,
+        }
+    }
+
+    /// Whether a frame is partially received
+    #[inline]
+    pub fn in_frame(&self) -> bool {
+        self.state != DecoderState::Idle
+    }
+
+    /// Abort the frame in progress
+    ///
+    /// Returns `FrameError::Aborted` if a frame was in progress.
+    pub fn abort(&mut self) -> Option<Result<Frame, FrameError>> {
+        let was_idle = self.state == DecoderState::Idle;
+        self.state = DecoderState::Idle;
+
+        if was_idle { None } else { Some(Err(FrameError::Aborted)) }
+    }
+
+    /// Feed a received byte
+    ///
+    /// Returns a frame, or the reason it was rejected, once it is complete.
+    pub fn feed(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        match self.state {
+            DecoderState::Idle => {
+                if byte == PREAMBLE[0] {
+                    self.state = DecoderState::Preamble;
+                }
+                None
+            }
+            DecoderState::Preamble => {
+                self.state = match byte {
+                    0xff => {
+                        self.index = 0;
+                        DecoderState::Header
+                    }
+                    0x55 => DecoderState::Preamble,
+                    _ => DecoderState::Idle,
+                };
+                None
+            }
+            DecoderState::Header => {
+                if self.index < self.header.len() - 1 {
+                    self.header[self.index] = byte;
+                    self.index += 1;
+                    return None;
+                }
+                self.header[self.index] = byte;
+                self.state = DecoderState::Idle;
+
+                if header_crc(&self.header) != HEADER_CRC_RESIDUE {
+                    return Some(Err(FrameError::HeaderCrc));
+                }
+
+                let len = u16::from_be_bytes([self.header[3], self.header[4]]) as usize;
+                self.data.clear();
+                self.remaining = len + 2;
+
+                if len == 0 {
+                    return Some(Ok(self.frame()));
+                }
+
+                self.state = if len > MAX_DATA_LEN {
+                    DecoderState::SkipData
+                } else {
+                    DecoderState::Data
+                };
+                None
+            }
+            DecoderState::Data => {
+                self.data.push(byte);
+                self.remaining -= 1;
+                if self.remaining > 0 {
+                    return None;
+                }
+                self.state = DecoderState::Idle;
+
+                if data_crc(&self.data) != DATA_CRC_RESIDUE {
+                    return Some(Err(FrameError::DataCrc));
+                }
+
+                let len = self.data.len() - 2;
+                self.data.truncate(len);
+                Some(Ok(self.frame()))
+            }
+            DecoderState::SkipData => {
+                self.remaining -= 1;
+                if self.remaining > 0 {
+                    return None;
+                }
+                self.state = DecoderState::Idle;
+                Some(Err(FrameError::TooLong))
+            }
+        }
+    }
+
+    fn frame(&self) -> Frame {
+        Frame {
+            frame_type: FrameType::from(self.header[0]),
+            destination: self.header[1],
+            source: self.header[2],
+            data: self.data.clone(),
+        }
+    }
+}
+
+/// MS/TP timing parameters
+///
+/// Defaults follow clause 9.5.3. Parameters given in bit times by the
+/// specification are derived from the line settings, but not below 5 ms, as
+/// Linux userspace cannot reliably resolve shorter gaps.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MstpTiming {
+    frame_abort: Duration,
+    reply_timeout: Duration,
+    reply_delay: Duration,
+    usage_timeout: Duration,
+    no_token: Duration,
+    slot: Duration,
+    turnaround: Duration,
+}
+
+// see above
+const MIN_FRAME_ABORT: Duration = Duration::from_millis(5);
+
+impl MstpTiming {
+    /// Derive timing parameters from line settings
+    pub fn new(settings: &PortSettings) -> MstpTiming {
+        let timing = LineTiming::new(settings);
+
+        MstpTiming {
+            frame_abort: (timing.bit_time() * 60).max(MIN_FRAME_ABORT),
+            reply_timeout: Duration::from_millis(255),
+            reply_delay: Duration::from_millis(250),
+            usage_timeout: Duration::from_millis(20),
+            no_token: Duration::from_millis(500),
+            slot: Duration::from_millis(10),
+            turnaround: timing.bit_time() * 40,
+        }
+    }
+
+    /// Set Tframe_abort
+    ///
+    /// Maximum silence within a frame before it is discarded; 60 bit times to
+    /// 100 ms.
+    #[inline]
+    pub fn set_frame_abort(&mut self, frame_abort: Duration) -> &mut Self {
+        self.frame_abort = frame_abort;
+        self
+    }
+
+    /// Set Treply_timeout
+    ///
+    /// Time to wait for a reply to a data request; 255 to 300 ms.
+    #[inline]
+    pub fn set_reply_timeout(&mut self, reply_timeout: Duration) -> &mut Self {
+        self.reply_timeout = reply_timeout;
+        self
+    }
+
+    /// Set Treply_delay
+    ///
+    /// Time the network layer has to answer a data request before a reply
+    /// postponed frame is sent; at most 250 ms.
+    #[inline]
+    pub fn set_reply_delay(&mut self, reply_delay: Duration) -> &mut Self {
+        self.reply_delay = reply_delay;
+        self
+    }
+
+    /// Set Tusage_timeout
+    ///
+    /// Time to wait for a node to start using a token or to answer a poll for
+    /// master; 20 to 100 ms.
+    #[inline]
+    pub fn set_usage_timeout(&mut self, usage_timeout: Duration) -> &mut Self {
+        self.usage_timeout = usage_timeout;
+        self
+    }
+
+    /// Tframe_abort
+    #[inline]
+    pub fn frame_abort(&self) -> Duration {
+        self.frame_abort
+    }
+
+    /// Treply_timeout
+    #[inline]
+    pub fn reply_timeout(&self) -> Duration {
+        self.reply_timeout
+    }
+
+    /// Treply_delay
+    #[inline]
+    pub fn reply_delay(&self) -> Duration {
+        self.reply_delay
+    }
+
+    /// Tusage_timeout
+    #[inline]
+    pub fn usage_timeout(&self) -> Duration {
+        self.usage_timeout
+    }
+
+    /// Tno_token: silence after which the token is considered lost (500 ms)
+    #[inline]
+    pub fn no_token(&self) -> Duration {
+        self.no_token
+    }
+
+    /// Tslot: time per station to wait before generating a token (10 ms)
+    #[inline]
+    pub fn slot(&self) -> Duration {
+        self.slot
+    }
+
+    /// Tturnaround: minimum silence before transmitting (40 bit times)
+    #[inline]
+    pub fn turnaround(&self) -> Duration {
+        self.turnaround
+    }
+}
+
+impl<'a> From<&'a PortSettings> for MstpTiming {
+    #[inline]
+    fn from(settings: &'a PortSettings) -> MstpTiming {
+        MstpTiming::new(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // header of a token from station 5 to station 16 (ASHRAE 135 Annex G.1)
+    const TOKEN: [u8; 8] = [0x55, 0xff, 0x00, 0x10, 0x05, 0x00, 0x00, 0x8c];
+
+    #[test]
+    fn header_crc_annex_g() {
+        assert_eq!(header_crc(&[0x00, 0x10, 0x05, 0x00, 0x00]), 0x73);
+        assert_eq!(header_crc(&TOKEN[2..]), HEADER_CRC_RESIDUE);
+    }
+
+    #[test]
+    fn data_crc_annex_g() {
+        assert_eq!(data_crc(&[0x01, 0x22, 0x30]), 0x42ef);
+        assert_eq!(data_crc(&[0x01, 0x22, 0x30, 0x10, 0xbd]), DATA_CRC_RESIDUE);
+    }
+
+    #[test]
+    fn encode_token() {
+        let frame = Frame::control(FrameType::Token, 0x10, 0x05);
+        assert_eq!(frame.encode().unwrap(), TOKEN);
+    }
+
+    #[test]
+    fn encode_data_frame() {
+        let frame = Frame {
+            frame_type: FrameType::DataNotExpectingReply,
+            destination: 0x10,
+            source: 0x05,
+            data: vec![0x01, 0x22, 0x30],
+        };
+        let encoded = frame.encode().unwrap();
+        assert_eq!(&encoded[HEADER_LEN..], [0x01, 0x22, 0x30, 0x10, 0xbd]);
+
+        assert!(Frame { data: vec![0; MAX_DATA_LEN + 1], ..frame }.encode().is_err());
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        let frame = Frame {
+            frame_type: FrameType::DataExpectingReply,
+            destination: 0x01,
+            source: 0x7f,
+            data: (0..=255).collect(),
+        };
+        let token = Frame::control(FrameType::Token, 0x10, 0x05);
+
+        // garbage and a repeated preamble byte before the frames are skipped
+        let mut bytes = vec![0x00, 0x55, 0x55];
+        bytes.extend(frame.encode().unwrap());
+        bytes.extend(token.encode().unwrap());
+
+        let mut decoder = FrameDecoder::new();
+        let decoded: Vec<_> = bytes.iter().filter_map(|&b| decoder.feed(b)).collect();
+        assert_eq!(decoded, [Ok(frame), Ok(token)]);
+        assert!(!decoder.in_frame());
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut decoder = FrameDecoder::new();
+
+        let mut token = TOKEN;
+        token[4] = 0x06;
+        assert_eq!(token.iter().filter_map(|&b| decoder.feed(b)).next(),
+                   Some(Err(FrameError::HeaderCrc)));
+
+        let frame = Frame {
+            frame_type: FrameType::DataNotExpectingReply,
+            destination: 0x10,
+            source: 0x05,
+            data: vec![0x01, 0x22, 0x30],
+        };
+        let mut bytes = frame.encode().unwrap();
+        bytes[HEADER_LEN] ^= 0x80;
+        assert_eq!(bytes.iter().filter_map(|&b| decoder.feed(b)).next(),
+                   Some(Err(FrameError::DataCrc)));
+
+        for &b in &TOKEN[..5] {
+            assert_eq!(decoder.feed(b), None);
+        }
+        assert!(decoder.in_frame());
+        assert_eq!(decoder.abort(), Some(Err(FrameError::Aborted)));
+        assert_eq!(decoder.abort(), None);
+    }
+}
@@ -0,0 +1,690 @@
+//! Serial port with RS485 support
+//!
+//! `Rs485Port` opens a serial device, sets it up in raw mode with the line
+//! settings given in `PortSettings` and applies an RS485 configuration, for
+//! tools that do not want to pull in a separate serial crate.
+
+use libc;
+use std::{error, fmt};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::Duration;
+
+use super::{SER_RS485_ENABLED, SerialRs485};
+use direction::{DirectionControl, DirectionMode, KernelRs485, wait_transmitter_empty};
+use sys;
+
+/// Number of data bits per character
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataBits {
+    /// 5 data bits
+    Five,
+    /// 6 data bits
+    Six,
+    /// 7 data bits
+    Seven,
+    /// 8 data bits
+    Eight,
+}
+
+/// Parity checking mode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity bit
+    None,
+    /// Odd parity
+    Odd,
+    /// Even parity
+    Even,
+    /// Parity bit always set (stick parity, `CMSPAR`)
+    Mark,
+    /// Parity bit always cleared (stick parity, `CMSPAR`)
+    Space,
+}
+
+/// Number of stop bits
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    /// One stop bit
+    One,
+    /// Two stop bits
+    Two,
+}
+
+/// Line settings of a serial port
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PortSettings {
+    baud_rate: u32,
+    data_bits: DataBits,
+    parity: Parity,
+    stop_bits: StopBits,
+}
+
+impl Default for PortSettings {
+    #[inline]
+    fn default() -> PortSettings {
+        PortSettings::new()
+    }
+}
+
+impl PortSettings {
+    /// Create new line settings
+    ///
+    /// Defaults to 9600 baud, 8 data bits, no parity and one stop bit.
+    #[inline]
+    pub fn new() -> PortSettings {
+        PortSettings {
+            baud_rate: 9600,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+
+    /// Set baud rate
+    ///
+    /// Besides the standard rates defined by termios (`B50` through
+    /// `B4000000`), arbitrary rates are supported through `termios2`, if the
+    /// driver can generate them.
+    #[inline]
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> &mut Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    /// Set number of data bits
+    #[inline]
+    pub fn set_data_bits(&mut self, data_bits: DataBits) -> &mut Self {
+        self.data_bits = data_bits;
+        self
+    }
+
+    /// Set parity mode
+    #[inline]
+    pub fn set_parity(&mut self, parity: Parity) -> &mut Self {
+        self.parity = parity;
+        self
+    }
+
+    /// Set number of stop bits
+    #[inline]
+    pub fn set_stop_bits(&mut self, stop_bits: StopBits) -> &mut Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Baud rate
+    #[inline]
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    /// Number of data bits
+    #[inline]
+    pub fn data_bits(&self) -> DataBits {
+        self.data_bits
+    }
+
+    /// Parity mode
+    #[inline]
+    pub fn parity(&self) -> Parity {
+        self.parity
+    }
+
+    /// Number of stop bits
+    #[inline]
+    pub fn stop_bits(&self) -> StopBits {
+        self.stop_bits
+    }
+
+    /// Apply settings to a termios structure
+    ///
+    /// Puts the terminal into raw mode, with reads blocking until at least one
+    /// byte is available. Non-standard baud rates are not set, see
+    /// `set_custom_baud_rate`.
+    fn apply_to_termios(&self, tio: &mut libc::termios) -> io::Result<()> {
+        unsafe { libc::cfmakeraw(tio) };
+
+        tio.c_cflag &= !(libc::CSIZE | libc::PARENB | libc::PARODD | libc::CMSPAR |
+                         libc::CSTOPB | libc::CRTSCTS);
+        tio.c_cflag |= libc::CLOCAL | libc::CREAD;
+
+        tio.c_cflag |= match self.data_bits {
+            DataBits::Five => libc::CS5,
+            DataBits::Six => libc::CS6,
+            DataBits::Seven => libc::CS7,
+            DataBits::Eight => libc::CS8,
+        };
+
+        match self.parity {
+            Parity::None => (),
+            Parity::Odd => tio.c_cflag |= libc::PARENB | libc::PARODD,
+            Parity::Even => tio.c_cflag |= libc::PARENB,
+            Parity::Mark => tio.c_cflag |= libc::PARENB | libc::CMSPAR | libc::PARODD,
+            Parity::Space => tio.c_cflag |= libc::PARENB | libc::CMSPAR,
+        }
+
+        if self.stop_bits == StopBits::Two {
+            tio.c_cflag |= libc::CSTOPB;
+        }
+
+        tio.c_cc[libc::VMIN] = 1;
+        tio.c_cc[libc::VTIME] = 0;
+
+        if let Some(speed) = standard_speed(self.baud_rate) {
+            sys::check(unsafe { libc::cfsetispeed(tio, speed) })?;
+            sys::check(unsafe { libc::cfsetospeed(tio, speed) })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Echo of a transmission differs from what was sent
+///
+/// Returned wrapped inside an `io::Error` by writes with echo cancellation
+/// enabled. Usually caused by another node transmitting at the same time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BusCollision {
+    /// Transmitted data
+    pub sent: Vec<u8>,
+    /// Data received back; shorter than `sent` if the echo was incomplete
+    pub echoed: Vec<u8>,
+}
+
+impl fmt::Display for BusCollision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.echoed.len() < self.sent.len() {
+            write!(f,
+                   "bus collision: sent {} bytes, but only {} were echoed",
+                   self.sent.len(),
+                   self.echoed.len())
+        } else {
+            write!(f, "bus collision: echo differs from transmitted data")
+        }
+    }
+}
+
+impl error::Error for BusCollision {}
+
+/// Baud rate set by driver differs from requested rate
+///
+/// Returned wrapped inside an `io::Error` by `Rs485Port::open` and
+/// `Rs485Port::reconfigure`. After `reconfigure`, the port will have been
+/// configured with the `actual` rate; to accept it when opening, open with
+/// the `actual` rate instead.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BaudRateMismatch {
+    /// Requested baud rate
+    pub requested: u32,
+    /// Baud rate reported by the driver
+    pub actual: u32,
+}
+
+impl fmt::Display for BaudRateMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "requested baud rate {}, but driver set {}",
+               self.requested,
+               self.actual)
+    }
+}
+
+impl error::Error for BaudRateMismatch {}
+
+/// Sets an arbitrary baud rate through `termios2` and `BOTHER`
+fn set_custom_baud_rate(fd: RawFd, baud_rate: u32) -> io::Result<()> {
+    let mut tio = sys::tcgets2(fd)?;
+
+    tio.c_cflag &= !(sys::CBAUD | (sys::CBAUD << libc::IBSHIFT));
+    tio.c_cflag |= libc::BOTHER | (libc::BOTHER << libc::IBSHIFT);
+    tio.c_ispeed = baud_rate as libc::speed_t;
+    tio.c_ospeed = baud_rate as libc::speed_t;
+
+    sys::tcsets2(fd, &tio)
+}
+
+/// Reads the output baud rate the driver actually uses
+fn read_baud_rate(fd: RawFd) -> io::Result<u32> {
+    Ok(sys::tcgets2(fd)?.c_ospeed as u32)
+}
+
+// standard baud rates and their termios speed constants
+const STANDARD_SPEEDS: &[(u32, libc::speed_t)] = &[(50, libc::B50),
+                                                    (75, libc::B75),
+                                                    (110, libc::B110),
+                                                    (134, libc::B134),
+                                                    (150, libc::B150),
+                                                    (200, libc::B200),
+                                                    (300, libc::B300),
+                                                    (600, libc::B600),
+                                                    (1200, libc::B1200),
+                                                    (1800, libc::B1800),
+                                                    (2400, libc::B2400),
+                                                    (4800, libc::B4800),
+                                                    (9600, libc::B9600),
+                                                    (19200, libc::B19200),
+                                                    (38400, libc::B38400),
+                                                    (57600, libc::B57600),
+                                                    (115200, libc::B115200),
+                                                    (230400, libc::B230400),
+                                                    (460800, libc::B460800),
+                                                    (500000, libc::B500000),
+                                                    (576000, libc::B576000),
+                                                    (921600, libc::B921600),
+                                                    (1000000, libc::B1000000),
+                                                    (1152000, libc::B1152000),
+                                                    (1500000, libc::B1500000),
+                                                    (2000000, libc::B2000000),
+                                                    (2500000, libc::B2500000),
+                                                    (3000000, libc::B3000000),
+                                                    (3500000, libc::B3500000),
+                                                    (4000000, libc::B4000000)];
+
+/// Maps a baud rate onto its termios speed constant
+fn standard_speed(baud_rate: u32) -> Option<libc::speed_t> {
+    STANDARD_SPEEDS.iter()
+        .find(|&&(rate, _)| rate == baud_rate)
+        .map(|&(_, speed)| speed)
+}
+
+/// Serial port with RS485 configuration
+///
+/// Since it implements `AsRawFd`, all methods of the `Rs485` trait are
+/// available as well.
+///
+/// If the driver lacks RS485 support, direction control falls back to
+/// toggling RTS from userspace around each write. Other strategies can be
+/// selected through `set_direction_mode` or `set_direction_control`.
+pub struct Rs485Port {
+    file: File,
+    settings: PortSettings,
+    direction: Box<dyn DirectionControl + Send>,
+    echo_timeout: Option<Duration>,
+    mark_errors: bool,
+}
+
+impl fmt::Debug for Rs485Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rs485Port")
+            .field("file", &self.file)
+            .field("settings", &self.settings)
+            .field("hardware_managed", &self.direction.is_hardware_managed())
+            .field("echo_timeout", &self.echo_timeout)
+            .field("mark_errors", &self.mark_errors)
+            .finish()
+    }
+}
+
+impl Rs485Port {
+    /// Open and configure a serial device
+    ///
+    /// Opens the device at `path` (without making it the controlling
+    /// terminal), puts it into raw mode using `settings` and applies the RS485
+    /// configuration `rs485` using `DirectionMode::Auto`.
+    ///
+    /// Fails with a `BaudRateMismatch` error if the driver can only
+    /// approximate the baud rate requested.
+    pub fn open<P: AsRef<Path>>(path: P,
+                                settings: &PortSettings,
+                                rs485: &SerialRs485)
+                                -> io::Result<Rs485Port> {
+        Rs485Port::open_with_direction(path, settings, rs485, &DirectionMode::Auto)
+    }
+
+    /// Open and configure a serial device with a given direction control
+    /// strategy
+    ///
+    /// Like `open`, but sets up `mode` instead of `DirectionMode::Auto`. With
+    /// the userspace modes, kernel RS485 support is disabled if the driver
+    /// has it enabled, e.g. from the device tree, so it cannot interfere with
+    /// the line toggled.
+    pub fn open_with_direction<P: AsRef<Path>>(path: P,
+                                               settings: &PortSettings,
+                                               rs485: &SerialRs485,
+                                               mode: &DirectionMode)
+                                               -> io::Result<Rs485Port> {
+        // opening non-blocking avoids hanging on devices waiting for carrier
+        // detect, the flag is cleared again once CLOCAL is set
+        let file = OpenOptions::new().read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(path)?;
+
+        let mut port = Rs485Port {
+            file,
+            settings: *settings,
+            direction: Box::new(KernelRs485),
+            echo_timeout: None,
+            mark_errors: false,
+        };
+
+        port.reconfigure(settings)?;
+
+        let fd = port.as_raw_fd();
+        let fl = sys::check(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+        sys::check(unsafe { libc::fcntl(fd, libc::F_SETFL, fl & !libc::O_NONBLOCK) })?;
+
+        match *mode {
+            DirectionMode::Auto | DirectionMode::Kernel => (),
+            _ => {
+                if let Ok(mut current) = SerialRs485::from_fd(fd) {
+                    if current.flags().contains(SER_RS485_ENABLED) {
+                        current.set_enabled(false);
+                        current.set_on_fd(fd)?;
+                    }
+                }
+            }
+        }
+        port.set_direction_mode(mode, rs485)?;
+
+        Ok(port)
+    }
+
+    /// Apply RS485 configuration
+    ///
+    /// Uses the kernel's RS485 support if available, otherwise falls back to
+    /// toggling RTS from userspace.
+    #[inline]
+    pub fn set_rs485(&mut self, rs485: &SerialRs485) -> io::Result<()> {
+        self.set_direction_mode(&DirectionMode::Auto, rs485)
+    }
+
+    /// Select direction control strategy
+    ///
+    /// See `DirectionMode::setup` for how `rs485` is used.
+    pub fn set_direction_mode(&mut self,
+                              mode: &DirectionMode,
+                              rs485: &SerialRs485)
+                              -> io::Result<()> {
+        self.direction = mode.setup(self.as_raw_fd(), rs485)?;
+        self.direction.set_keep_echo(self.echo_timeout.is_some());
+        Ok(())
+    }
+
+    /// Use a custom direction control strategy
+    #[inline]
+    pub fn set_direction_control<D>(&mut self, direction: D)
+        where D: DirectionControl + Send + 'static
+    {
+        self.direction = Box::new(direction);
+        self.direction.set_keep_echo(self.echo_timeout.is_some());
+    }
+
+    /// Enable local echo cancellation
+    ///
+    /// When receiving during transmission is enabled (see
+    /// `SerialRs485::set_rx_during_tx`), every byte written is received back.
+    /// With echo cancellation, each write reads back and discards the echo,
+    /// failing with a `BusCollision` error if it differs from the data sent.
+    /// `timeout` is the maximum time to wait for each echoed byte, `None`
+    /// disables echo cancellation.
+    ///
+    /// Any input not read before writing is discarded, so late responses
+    /// are not mistaken for a collision.
+    #[inline]
+    pub fn set_echo_cancel(&mut self, timeout: Option<Duration>) {
+        self.echo_timeout = timeout;
+        self.direction.set_keep_echo(timeout.is_some());
+    }
+
+    /// Mark breaks and line errors in the received data
+    ///
+    /// When on, the driver inserts `0xff 0x00 0x00` for a break and
+    /// `0xff 0x00 c` for a character `c` received with a framing or parity
+    /// error. Received `0xff` bytes are doubled. Stays in effect across
+    /// `reconfigure`.
+    pub fn set_mark_errors(&mut self, on: bool) -> io::Result<()> {
+        let fd = self.as_raw_fd();
+        let mut tio = sys::tcgetattr(fd)?;
+
+        if on {
+            tio.c_iflag |= libc::PARMRK | libc::INPCK;
+        } else {
+            tio.c_iflag &= !(libc::PARMRK | libc::INPCK);
+        }
+
+        sys::tcsetattr(fd, &tio)?;
+        self.mark_errors = on;
+        Ok(())
+    }
+
+    /// Enable 9-bit addressing mode (`ADDRB`)
+    ///
+    /// Must be combined with `SerialRs485::set_addrb`. Fails with
+    /// `ErrorKind::Unsupported` if the driver drops the flag. Stays in effect
+    /// across `reconfigure`.
+    pub fn set_addrb(&mut self, on: bool) -> io::Result<()> {
+        let fd = self.as_raw_fd();
+        let mut tio = sys::tcgetattr(fd)?;
+
+        if on {
+            tio.c_cflag |= sys::ADDRB;
+        } else {
+            tio.c_cflag &= !sys::ADDRB;
+        }
+
+        sys::tcsetattr(fd, &tio)?;
+
+        if on && sys::tcgetattr(fd)?.c_cflag & sys::ADDRB == 0 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported,
+                                      "driver does not support 9-bit addressing"));
+        }
+
+        Ok(())
+    }
+
+    /// Whether direction control is handled outside of userspace
+    #[inline]
+    pub fn is_hardware_managed(&self) -> bool {
+        self.direction.is_hardware_managed()
+    }
+
+    /// Change line settings
+    ///
+    /// If the driver cannot generate the exact baud rate requested, a
+    /// `BaudRateMismatch` error is returned.
+    pub fn reconfigure(&mut self, settings: &PortSettings) -> io::Result<()> {
+        let fd = self.as_raw_fd();
+        let mut tio = sys::tcgetattr(fd)?;
+        settings.apply_to_termios(&mut tio)?;
+        if self.mark_errors {
+            tio.c_iflag |= libc::PARMRK | libc::INPCK;
+        }
+        sys::tcsetattr(fd, &tio)?;
+
+        if standard_speed(settings.baud_rate).is_none() {
+            set_custom_baud_rate(fd, settings.baud_rate)?;
+        }
+
+        let actual = read_baud_rate(fd)?;
+        self.settings = *settings;
+        self.settings.baud_rate = actual;
+
+        if actual != settings.baud_rate {
+            return Err(io::Error::other(BaudRateMismatch {
+                requested: settings.baud_rate,
+                actual,
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Baud rate currently used by the driver
+    #[inline]
+    pub fn actual_baud_rate(&self) -> io::Result<u32> {
+        read_baud_rate(self.as_raw_fd())
+    }
+
+    /// Current line settings
+    ///
+    /// The baud rate is the one reported by the driver after the last
+    /// reconfiguration.
+    #[inline]
+    pub fn settings(&self) -> &PortSettings {
+        &self.settings
+    }
+
+    /// Discard any received, but not yet read data
+    #[inline]
+    pub fn discard_input(&self) -> io::Result<()> {
+        sys::check(unsafe { libc::tcflush(self.as_raw_fd(), libc::TCIFLUSH) })?;
+        Ok(())
+    }
+
+    /// Read with a timeout
+    ///
+    /// Waits up to `timeout` for data to arrive, returning `Ok(0)` if none
+    /// did.
+    pub fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        if !sys::poll_readable(self.as_raw_fd(), timeout)? {
+            return Ok(0);
+        }
+
+        self.file.read(buf)
+    }
+
+    /// Read back and compare the echo of `sent`
+    fn consume_echo(&mut self, sent: &[u8], timeout: Duration) -> io::Result<()> {
+        let mut echoed = vec![0; sent.len()];
+        let mut len = 0;
+
+        while len < sent.len() {
+            let n = self.read_timeout(&mut echoed[len..], timeout)?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+
+        echoed.truncate(len);
+        if echoed != sent {
+            return Err(io::Error::other(BusCollision {
+                sent: sent.to_vec(),
+                echoed,
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Start or stop sending a break
+    ///
+    /// While on, the transmit line is held in the space (low) state.
+    #[inline]
+    pub fn set_break(&mut self, on: bool) -> io::Result<()> {
+        sys::set_break(self.as_raw_fd(), on)
+    }
+
+    /// Wait until everything written has left the transmitter
+    ///
+    /// Unlike `flush`, this also waits for the UART's shift register to empty
+    /// if the driver can report it.
+    #[inline]
+    pub fn drain(&mut self) -> io::Result<()> {
+        wait_transmitter_empty(self.as_raw_fd())
+    }
+}
+
+impl AsRawFd for Rs485Port {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Read for Rs485Port {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for Rs485Port {
+    /// Writes data, enabling the line driver around it if required
+    ///
+    /// With userspace direction control or echo cancellation, the whole
+    /// buffer is written and transmitted before returning.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.direction.is_hardware_managed() && self.echo_timeout.is_none() {
+            return self.file.write(buf);
+        }
+
+        if self.echo_timeout.is_some() {
+            self.discard_input()?;
+        }
+
+        let fd = self.file.as_raw_fd();
+        self.direction.begin_transmit(fd)?;
+        let rv = self.file.write_all(buf);
+        let end = self.direction.end_transmit(fd);
+        rv.and(end)?;
+
+        if let Some(timeout) = self.echo_timeout {
+            self.consume_echo(buf, timeout)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Waits until all written data has been transmitted
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        sys::tcdrain(self.as_raw_fd())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::{Loopback, pending, pty_port};
+
+    fn echo_port(corrupt: Option<fn(&mut Vec<u8>)>) -> Rs485Port {
+        let (master, mut port) = pty_port();
+        let mut loopback = Loopback::new(master);
+        loopback.corrupt = corrupt;
+        port.set_direction_control(loopback);
+        port.set_echo_cancel(Some(Duration::from_millis(50)));
+        port
+    }
+
+    fn collision(err: &io::Error) -> &BusCollision {
+        err.get_ref().and_then(|inner| inner.downcast_ref()).expect("not a bus collision")
+    }
+
+    #[test]
+    fn echo_consumed() {
+        let mut port = echo_port(None);
+
+        port.write_all(b"\x01\x02\xff").unwrap();
+        assert_eq!(pending(port.as_raw_fd()), 0);
+    }
+
+    #[test]
+    fn echo_differs() {
+        let mut port = echo_port(Some(|echo| echo[1] ^= 0x10));
+
+        let err = port.write(b"\x01\x02\x03").unwrap_err();
+        assert_eq!(collision(&err),
+                   &BusCollision {
+                       sent: vec![0x01, 0x02, 0x03],
+                       echoed: vec![0x01, 0x12, 0x03],
+                   });
+    }
+
+    #[test]
+    fn echo_incomplete() {
+        let mut port = echo_port(Some(|echo| echo.truncate(1)));
+
+        let err = port.write(b"\x01\x02\x03").unwrap_err();
+        assert_eq!(collision(&err),
+                   &BusCollision {
+                       sent: vec![0x01, 0x02, 0x03],
+                       echoed: vec![0x01],
+                   });
+    }
+}
@@ -0,0 +1,239 @@
+//! Serde support (enabled through the `serde` feature)
+//!
+//! Flags are (de)serialized as named booleans, delays as milliseconds.
+//! Missing fields default to off or zero.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::*;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FlagsRepr {
+    enabled: bool,
+    rts_on_send: bool,
+    rts_after_send: bool,
+    rx_during_tx: bool,
+    terminate_bus: bool,
+    addrb: bool,
+    addr_recv: bool,
+    addr_dest: bool,
+    mode_rs422: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct SerialRs485Repr {
+    enabled: bool,
+    rts_on_send: bool,
+    rts_after_send: bool,
+    rx_during_tx: bool,
+    terminate_bus: bool,
+    addrb: bool,
+    mode_rs422: bool,
+    delay_rts_before_send: u32,
+    delay_rts_after_send: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addr_recv: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addr_dest: Option<u8>,
+}
+
+fn set_flag(flags: &mut Rs485Flags, flag: Rs485Flags, value: bool) {
+    if value {
+        *flags |= flag;
+    } else {
+        *flags &= !flag;
+    }
+}
+
+impl Serialize for Rs485Flags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FlagsRepr {
+                enabled: self.contains(SER_RS485_ENABLED),
+                rts_on_send: self.contains(SER_RS485_RTS_ON_SEND),
+                rts_after_send: self.contains(SER_RS485_RTS_AFTER_SEND),
+                rx_during_tx: self.contains(SER_RS485_RX_DURING_TX),
+                terminate_bus: self.contains(SER_RS485_TERMINATE_BUS),
+                addrb: self.contains(SER_RS485_ADDRB),
+                addr_recv: self.contains(SER_RS485_ADDR_RECV),
+                addr_dest: self.contains(SER_RS485_ADDR_DEST),
+                mode_rs422: self.contains(SER_RS485_MODE_RS422),
+            }
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rs485Flags {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Rs485Flags, D::Error> {
+        let repr = FlagsRepr::deserialize(deserializer)?;
+        let mut flags = Rs485Flags::empty();
+
+        set_flag(&mut flags, SER_RS485_ENABLED, repr.enabled);
+        set_flag(&mut flags, SER_RS485_RTS_ON_SEND, repr.rts_on_send);
+        set_flag(&mut flags, SER_RS485_RTS_AFTER_SEND, repr.rts_after_send);
+        set_flag(&mut flags, SER_RS485_RX_DURING_TX, repr.rx_during_tx);
+        set_flag(&mut flags, SER_RS485_TERMINATE_BUS, repr.terminate_bus);
+        set_flag(&mut flags, SER_RS485_ADDRB, repr.addrb);
+        set_flag(&mut flags, SER_RS485_ADDR_RECV, repr.addr_recv);
+        set_flag(&mut flags, SER_RS485_ADDR_DEST, repr.addr_dest);
+        set_flag(&mut flags, SER_RS485_MODE_RS422, repr.mode_rs422);
+
+        Ok(flags)
+    }
+}
+
+impl Serialize for SerialRs485 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerialRs485Repr {
+                enabled: self.flags.contains(SER_RS485_ENABLED),
+                rts_on_send: self.flags.contains(SER_RS485_RTS_ON_SEND),
+                rts_after_send: self.flags.contains(SER_RS485_RTS_AFTER_SEND),
+                rx_during_tx: self.flags.contains(SER_RS485_RX_DURING_TX),
+                terminate_bus: self.flags.contains(SER_RS485_TERMINATE_BUS),
+                addrb: self.flags.contains(SER_RS485_ADDRB),
+                mode_rs422: self.flags.contains(SER_RS485_MODE_RS422),
+                delay_rts_before_send: self.delay_rts_before_send,
+                delay_rts_after_send: self.delay_rts_after_send,
+                addr_recv: self.addr_recv(),
+                addr_dest: self.addr_dest(),
+            }
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SerialRs485 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SerialRs485, D::Error> {
+        let repr = SerialRs485Repr::deserialize(deserializer)?;
+        let mut conf = SerialRs485::new();
+
+        conf.set_enabled(repr.enabled)
+            .set_rts_on_send(repr.rts_on_send)
+            .set_rts_after_send(repr.rts_after_send)
+            .set_rx_during_tx(repr.rx_during_tx)
+            .set_terminate_bus(repr.terminate_bus)
+            .set_addrb(repr.addrb)
+            .set_mode_rs422(repr.mode_rs422)
+            .delay_rts_before_send_ms(repr.delay_rts_before_send)
+            .delay_rts_after_send_ms(repr.delay_rts_after_send)
+            .set_addr_recv(repr.addr_recv)
+            .set_addr_dest(repr.addr_dest);
+
+        Ok(conf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_test::{Token, assert_de_tokens, assert_de_tokens_error, assert_tokens};
+
+    use super::*;
+
+    #[test]
+    fn flags_round_trip() {
+        let flags = SER_RS485_ENABLED | SER_RS485_ADDR_DEST | SER_RS485_MODE_RS422;
+
+        assert_tokens(&flags,
+                      &[Token::Struct {
+                            name: "FlagsRepr",
+                            len: 9,
+                        },
+                        Token::Str("enabled"),
+                        Token::Bool(true),
+                        Token::Str("rts_on_send"),
+                        Token::Bool(false),
+                        Token::Str("rts_after_send"),
+                        Token::Bool(false),
+                        Token::Str("rx_during_tx"),
+                        Token::Bool(false),
+                        Token::Str("terminate_bus"),
+                        Token::Bool(false),
+                        Token::Str("addrb"),
+                        Token::Bool(false),
+                        Token::Str("addr_recv"),
+                        Token::Bool(false),
+                        Token::Str("addr_dest"),
+                        Token::Bool(true),
+                        Token::Str("mode_rs422"),
+                        Token::Bool(true),
+                        Token::StructEnd]);
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut conf = SerialRs485::new();
+        conf.set_enabled(true)
+            .set_rts_on_send(true)
+            .set_addrb(true)
+            .delay_rts_after_send_ms(5)
+            .set_addr_recv(Some(0))
+            .set_addr_dest(Some(0x2a));
+
+        assert_tokens(&conf,
+                      &[Token::Struct {
+                            name: "SerialRs485Repr",
+                            len: 11,
+                        },
+                        Token::Str("enabled"),
+                        Token::Bool(true),
+                        Token::Str("rts_on_send"),
+                        Token::Bool(true),
+                        Token::Str("rts_after_send"),
+                        Token::Bool(false),
+                        Token::Str("rx_during_tx"),
+                        Token::Bool(false),
+                        Token::Str("terminate_bus"),
+                        Token::Bool(false),
+                        Token::Str("addrb"),
+                        Token::Bool(true),
+                        Token::Str("mode_rs422"),
+                        Token::Bool(false),
+                        Token::Str("delay_rts_before_send"),
+                        Token::U32(0),
+                        Token::Str("delay_rts_after_send"),
+                        Token::U32(5),
+                        Token::Str("addr_recv"),
+                        Token::Some,
+                        Token::U8(0),
+                        Token::Str("addr_dest"),
+                        Token::Some,
+                        Token::U8(0x2a),
+                        Token::StructEnd]);
+    }
+
+    #[test]
+    fn settings_defaults() {
+        let mut conf = SerialRs485::new();
+        conf.set_enabled(true).delay_rts_before_send_ms(2);
+
+        assert_de_tokens(&conf,
+                         &[Token::Struct {
+                               name: "SerialRs485Repr",
+                               len: 2,
+                           },
+                           Token::Str("enabled"),
+                           Token::Bool(true),
+                           Token::Str("delay_rts_before_send"),
+                           Token::U32(2),
+                           Token::StructEnd]);
+
+        // the text form and serde agree
+        let text: SerialRs485 = "enabled,delay_before=2".parse().unwrap();
+        assert_eq!(text, conf);
+    }
+
+    #[test]
+    fn settings_unknown_field() {
+        assert_de_tokens_error::<SerialRs485>(&[Token::Struct {
+                                                     name: "SerialRs485Repr",
+                                                     len: 1,
+                                                 },
+                                                 Token::Str("delay_before")],
+                                              "unknown field `delay_before`, expected one of \
+                                               `enabled`, `rts_on_send`, `rts_after_send`, \
+                                               `rx_during_tx`, `terminate_bus`, `addrb`, \
+                                               `mode_rs422`, `delay_rts_before_send`, \
+                                               `delay_rts_after_send`, `addr_recv`, \
+                                               `addr_dest`");
+    }
+}
@@ -0,0 +1,187 @@
+//! Thin wrappers around libc calls used throughout the crate
+
+use libc::{self, c_int};
+use std::{io, mem};
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+use ioctl;
+
+/// Turns a `-1` return value into the last OS error
+#[inline]
+pub fn check(rval: c_int) -> io::Result<c_int> {
+    if rval == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(rval)
+}
+
+/// Reads the terminal attributes of `fd`
+#[inline]
+pub fn tcgetattr(fd: RawFd) -> io::Result<libc::termios> {
+    let mut tio: libc::termios = unsafe { mem::zeroed() };
+    check(unsafe { libc::tcgetattr(fd, &mut tio) })?;
+    Ok(tio)
+}
+
+/// Sets the terminal attributes of `fd` immediately
+#[inline]
+pub fn tcsetattr(fd: RawFd, tio: &libc::termios) -> io::Result<()> {
+    check(unsafe { libc::tcsetattr(fd, libc::TCSANOW, tio) })?;
+    Ok(())
+}
+
+/// Waits until all output written to `fd` has been transmitted
+#[inline]
+pub fn tcdrain(fd: RawFd) -> io::Result<()> {
+    check(unsafe { libc::tcdrain(fd) })?;
+    Ok(())
+}
+
+/// Mask of the baud rate bits in `c_cflag`
+#[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+pub const CBAUD: libc::tcflag_t = 0x0000_100f;
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+pub const CBAUD: libc::tcflag_t = 0x0000_00ff;
+#[cfg(not(any(target_arch = "sparc",
+              target_arch = "sparc64",
+              target_arch = "powerpc",
+              target_arch = "powerpc64")))]
+pub const CBAUD: libc::tcflag_t = 0o010017;
+
+/// Address bit mode flag in `c_cflag` (Linux 6.0+)
+///
+/// Not provided by libc. Unlike `CBAUD`, it comes from
+/// `asm-generic/termbits-common.h`, which every architecture shares.
+pub const ADDRB: libc::tcflag_t = 0x2000_0000;
+
+// must not collide with any `c_cflag` bit of the target
+const _: () = assert!(ADDRB & (CBAUD | libc::CMSPAR | libc::CRTSCTS | libc::CLOCAL | libc::CREAD |
+                               libc::CSIZE | libc::CSTOPB | libc::PARENB | libc::PARODD |
+                               libc::HUPCL) == 0);
+
+/// Terminal attributes including arbitrary baud rates
+#[cfg(not(any(target_arch = "powerpc", target_arch = "powerpc64")))]
+pub type Termios2 = libc::termios2;
+
+/// Terminal attributes including arbitrary baud rates
+///
+/// PowerPC has no `termios2`; its kernel `struct termios` carries the speeds
+/// instead, but differs from the C library's.
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Termios2 {
+    pub c_iflag: libc::tcflag_t,
+    pub c_oflag: libc::tcflag_t,
+    pub c_cflag: libc::tcflag_t,
+    pub c_lflag: libc::tcflag_t,
+    pub c_cc: [libc::cc_t; 19],
+    pub c_line: libc::cc_t,
+    pub c_ispeed: libc::speed_t,
+    pub c_ospeed: libc::speed_t,
+}
+
+#[cfg(not(any(target_arch = "powerpc", target_arch = "powerpc64")))]
+const TCGETS2: libc::c_ulong = libc::TCGETS2 as libc::c_ulong;
+#[cfg(not(any(target_arch = "powerpc", target_arch = "powerpc64")))]
+const TCSETS2: libc::c_ulong = libc::TCSETS2 as libc::c_ulong;
+
+// `TCGETS` and `TCSETS` as defined by the kernel, not the C library
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+const TCGETS2: libc::c_ulong = ioctl::ior::<Termios2>(b't', 19);
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+const TCSETS2: libc::c_ulong = ioctl::iow::<Termios2>(b't', 20);
+
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+const _: () = assert!(TCGETS2 == 0x402c_7413 && TCSETS2 == 0x802c_7414);
+
+/// Reads the terminal attributes of `fd`, including arbitrary baud rates
+#[inline]
+pub fn tcgets2(fd: RawFd) -> io::Result<Termios2> {
+    let mut tio: Termios2 = unsafe { mem::zeroed() };
+    check(unsafe { libc::ioctl(fd, TCGETS2 as _, &mut tio) })?;
+    Ok(tio)
+}
+
+/// Sets the terminal attributes of `fd`, including arbitrary baud rates
+#[inline]
+pub fn tcsets2(fd: RawFd, tio: &Termios2) -> io::Result<()> {
+    check(unsafe { libc::ioctl(fd, TCSETS2 as _, tio) })?;
+    Ok(())
+}
+
+/// Sets (`on == true`) or clears the modem control lines in `bits`
+#[inline]
+pub fn set_modem_lines(fd: RawFd, bits: c_int, on: bool) -> io::Result<()> {
+    let req = if on { libc::TIOCMBIS } else { libc::TIOCMBIC };
+    check(unsafe { libc::ioctl(fd, req, &bits) })?;
+    Ok(())
+}
+
+/// Starts (`on == true`) or stops sending a break
+#[inline]
+pub fn set_break(fd: RawFd, on: bool) -> io::Result<()> {
+    let req = if on { libc::TIOCSBRK } else { libc::TIOCCBRK };
+    check(unsafe { libc::ioctl(fd, req) })?;
+    Ok(())
+}
+
+// `TIOCSER_TEMT` from `linux/serial.h`
+const TIOCSER_TEMT: c_int = 0x01;
+
+/// Checks whether the transmitter of `fd` is empty
+///
+/// Returns `None` if the driver cannot report the line status.
+#[inline]
+pub fn transmitter_empty(fd: RawFd) -> io::Result<Option<bool>> {
+    let mut lsr: c_int = 0;
+
+    if unsafe { libc::ioctl(fd, libc::TIOCSERGETLSR, &mut lsr) } == -1 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOTTY) | Some(libc::EINVAL) => Ok(None),
+            _ => Err(err),
+        };
+    }
+
+    Ok(Some(lsr & TIOCSER_TEMT != 0))
+}
+
+/// Waits up to `timeout` for `fd` to become readable
+///
+/// Returns `false` if the timeout expired. Interruptions by signals are
+/// retried with the remaining time.
+pub fn poll_readable(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        // round up, so short timeouts do not turn into busy loops
+        let ms = remaining.as_micros().div_ceil(1000);
+        let ms = if ms > c_int::MAX as u128 { c_int::MAX } else { ms as c_int };
+
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        match unsafe { libc::poll(&mut pfd, 1, ms) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            0 => {
+                if Instant::now() >= deadline {
+                    return Ok(false);
+                }
+            }
+            _ => return Ok(true),
+        }
+    }
+}
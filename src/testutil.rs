@@ -0,0 +1,115 @@
+//! Pseudo terminal helpers for tests
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use direction::DirectionControl;
+use port::{PortSettings, Rs485Port};
+use {SerialRs485, sys};
+
+// how long to wait for data to cross the pseudo terminal
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(1);
+const QUIET_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Open a pseudo terminal, returning its master and the slave's path
+pub fn pty() -> (File, String) {
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(fd >= 0);
+        assert_eq!(libc::grantpt(fd), 0);
+        assert_eq!(libc::unlockpt(fd), 0);
+        let name = CStr::from_ptr(libc::ptsname(fd)).to_str().unwrap().to_owned();
+        (File::from_raw_fd(fd), name)
+    }
+}
+
+/// Open a port on the slave side of a pseudo terminal at 9600 8N1
+///
+/// Pseudo terminals lack RS485 support, so the port ends up with
+/// `KernelRs485` direction control, i.e. none at all.
+pub fn pty_port() -> (File, Rs485Port) {
+    let (master, path) = pty();
+    let port = Rs485Port::open(&path, &PortSettings::new(), &SerialRs485::new()).unwrap();
+    (master, port)
+}
+
+/// Number of bytes waiting to be read on `fd`
+pub fn pending(fd: RawFd) -> usize {
+    let mut pending: libc::c_int = 0;
+    sys::check(unsafe { libc::ioctl(fd, libc::FIONREAD, &mut pending) }).unwrap();
+    pending as usize
+}
+
+/// Wait until at least `len` bytes can be read from `fd`
+pub fn wait_pending(fd: RawFd, len: usize) {
+    let deadline = Instant::now() + SETTLE_TIMEOUT;
+    while pending(fd) < len {
+        assert!(Instant::now() < deadline, "data did not arrive");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// Direction control looping everything written back to the port
+///
+/// Behaves like a transceiver with receiving during transmission enabled:
+/// once a write returns, its echo is waiting in the port's input. Data
+/// written is recorded in `sent`. `corrupt` is applied to the echo, to
+/// simulate collisions.
+pub struct Loopback {
+    master: File,
+    /// Everything written to the port
+    pub sent: Arc<Mutex<Vec<u8>>>,
+    /// Modifies the echo before it is looped back
+    pub corrupt: Option<fn(&mut Vec<u8>)>,
+}
+
+impl Loopback {
+    /// Loop back data written to the slave side of `master`
+    pub fn new(master: File) -> Loopback {
+        Loopback {
+            master,
+            sent: Arc::new(Mutex::new(Vec::new())),
+            corrupt: None,
+        }
+    }
+}
+
+impl DirectionControl for Loopback {
+    fn begin_transmit(&mut self, _fd: RawFd) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn end_transmit(&mut self, fd: RawFd) -> io::Result<()> {
+        let master = self.master.as_raw_fd();
+        let mut echo = Vec::new();
+        let mut timeout = SETTLE_TIMEOUT;
+
+        while sys::poll_readable(master, timeout)? {
+            let mut buf = [0u8; 256];
+            let n = self.master.read(&mut buf)?;
+            echo.extend_from_slice(&buf[..n]);
+            timeout = QUIET_TIMEOUT;
+        }
+
+        self.sent.lock().unwrap().extend_from_slice(&echo);
+        if let Some(corrupt) = self.corrupt {
+            corrupt(&mut echo);
+        }
+
+        let before = pending(fd);
+        self.master.write_all(&echo)?;
+        wait_pending(fd, before + echo.len());
+        Ok(())
+    }
+
+    fn is_hardware_managed(&self) -> bool {
+        false
+    }
+}
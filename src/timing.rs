@@ -0,0 +1,199 @@
+//! Character and frame timing derived from line settings
+//!
+//! Protocols on RS485 commonly define gaps in multiples of the time it takes
+//! to transmit a character, e.g. Modbus RTU's 1.5 and 3.5 character times.
+
+use std::time::Duration;
+
+use port::{DataBits, Parity, PortSettings, StopBits};
+
+// baud rate above which Modbus uses fixed inter-character and inter-frame
+// gaps, see the Modbus over serial line specification, section 2.5.1.1
+const MODBUS_FIXED_TIMING_BAUD: u32 = 19200;
+const MODBUS_FIXED_T15: Duration = Duration::from_micros(750);
+const MODBUS_FIXED_T35: Duration = Duration::from_micros(1750);
+
+/// Timing of a serial line
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LineTiming {
+    baud_rate: u32,
+    bits_per_char: u32,
+}
+
+impl LineTiming {
+    /// Derive timing from line settings
+    #[inline]
+    pub fn new(settings: &PortSettings) -> LineTiming {
+        let data_bits = match settings.data_bits() {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity_bits = match settings.parity() {
+            Parity::None => 0,
+            _ => 1,
+        };
+        let stop_bits = match settings.stop_bits() {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+
+        LineTiming {
+            baud_rate: settings.baud_rate(),
+            // start bit, data, parity and stop bits
+            bits_per_char: 1 + data_bits + parity_bits + stop_bits,
+        }
+    }
+
+    /// Number of bits per character, including start, parity and stop bits
+    #[inline]
+    pub fn bits_per_char(&self) -> u32 {
+        self.bits_per_char
+    }
+
+    /// Time to transmit `bits / divisor` bits
+    #[inline]
+    fn bits_time_frac(&self, bits: u64, divisor: u64) -> Duration {
+        // round up, so gaps computed from this are never too short
+        let nanos = (bits * 1_000_000_000).div_ceil(self.baud_rate.max(1) as u64 * divisor);
+        Duration::from_nanos(nanos)
+    }
+
+    /// Time to transmit `bits` bits
+    #[inline]
+    fn bits_time(&self, bits: u64) -> Duration {
+        self.bits_time_frac(bits, 1)
+    }
+
+    /// Time to transmit a single bit
+    #[inline]
+    pub fn bit_time(&self) -> Duration {
+        self.bits_time(1)
+    }
+
+    /// Time to transmit a single character
+    #[inline]
+    pub fn char_time(&self) -> Duration {
+        self.bits_time(self.bits_per_char as u64)
+    }
+
+    /// Time to transmit `n` characters back to back
+    #[inline]
+    pub fn frame_time(&self, n: usize) -> Duration {
+        self.bits_time(self.bits_per_char as u64 * n as u64)
+    }
+
+    /// Modbus RTU inter-character timeout (t1.5)
+    ///
+    /// 1.5 character times, fixed at 750 µs above 19200 baud.
+    #[inline]
+    pub fn modbus_t15(&self) -> Duration {
+        if self.baud_rate > MODBUS_FIXED_TIMING_BAUD {
+            return MODBUS_FIXED_T15;
+        }
+
+        self.bits_time_frac(self.bits_per_char as u64 * 3, 2)
+    }
+
+    /// Modbus RTU inter-frame delay (t3.5)
+    ///
+    /// 3.5 character times, fixed at 1750 µs above 19200 baud.
+    #[inline]
+    pub fn modbus_t35(&self) -> Duration {
+        if self.baud_rate > MODBUS_FIXED_TIMING_BAUD {
+            return MODBUS_FIXED_T35;
+        }
+
+        self.bits_time_frac(self.bits_per_char as u64 * 7, 2)
+    }
+
+    /// Recommended `delay_rts_before_send`, in ms
+    ///
+    /// One bit time, rounded up to full milliseconds, giving the transceiver
+    /// time to enable its driver before the start bit.
+    #[inline]
+    pub fn recommended_delay_rts_before_send(&self) -> u32 {
+        duration_ms_ceil(self.bit_time())
+    }
+
+    /// Recommended `delay_rts_after_send`, in ms
+    ///
+    /// One character time, rounded up to full milliseconds, guarding against
+    /// drivers that release RTS before the last stop bit has left the UART.
+    #[inline]
+    pub fn recommended_delay_rts_after_send(&self) -> u32 {
+        duration_ms_ceil(self.char_time())
+    }
+}
+
+impl<'a> From<&'a PortSettings> for LineTiming {
+    #[inline]
+    fn from(settings: &'a PortSettings) -> LineTiming {
+        LineTiming::new(settings)
+    }
+}
+
+#[inline]
+fn duration_ms_ceil(d: Duration) -> u32 {
+    d.as_micros().div_ceil(1000) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(baud_rate: u32, parity: Parity, stop_bits: StopBits) -> LineTiming {
+        let mut settings = PortSettings::new();
+        settings.set_baud_rate(baud_rate).set_parity(parity).set_stop_bits(stop_bits);
+        LineTiming::new(&settings)
+    }
+
+    #[test]
+    fn char_time_8n1() {
+        let t = timing(9600, Parity::None, StopBits::One);
+        assert_eq!(t.bits_per_char(), 10);
+        // 1.0417 ms, rounded up to the nanosecond
+        assert_eq!(t.char_time(), Duration::from_nanos(1_041_667));
+        assert_eq!(t.bit_time(), Duration::from_nanos(104_167));
+        assert_eq!(t.frame_time(8), Duration::from_nanos(8_333_334));
+    }
+
+    #[test]
+    fn eleven_bit_chars() {
+        let even = timing(9600, Parity::Even, StopBits::One);
+        let two_stop = timing(9600, Parity::None, StopBits::Two);
+
+        assert_eq!(even.bits_per_char(), 11);
+        assert_eq!(two_stop.bits_per_char(), 11);
+        assert_eq!(even.char_time(), Duration::from_nanos(1_145_834));
+        assert_eq!(two_stop.char_time(), even.char_time());
+    }
+
+    #[test]
+    fn modbus_fixed_gaps() {
+        for &baud_rate in &[38400, 115200, 1_000_000] {
+            let t = timing(baud_rate, Parity::Even, StopBits::One);
+            assert_eq!(t.modbus_t15(), Duration::from_micros(750));
+            assert_eq!(t.modbus_t35(), Duration::from_micros(1750));
+        }
+    }
+
+    #[test]
+    fn modbus_gaps_from_char_time() {
+        let t = timing(9600, Parity::None, StopBits::One);
+        assert_eq!(t.modbus_t15(), Duration::from_micros(1562) + Duration::from_nanos(500));
+        assert_eq!(t.modbus_t35(), Duration::from_nanos(3_645_834));
+
+        // 19200 baud is the last rate using character times
+        let t = timing(19200, Parity::Even, StopBits::One);
+        assert_eq!(t.modbus_t35(), Duration::from_nanos(2_005_209));
+    }
+
+    #[test]
+    fn recommended_delays() {
+        let t = timing(9600, Parity::None, StopBits::One);
+        assert_eq!(t.recommended_delay_rts_before_send(), 1);
+        assert_eq!(t.recommended_delay_rts_after_send(), 2);
+    }
+}
@@ -0,0 +1,286 @@
+//! Half-duplex request/reply transactions
+//!
+//! Master-side protocols on RS485 almost always follow the same pattern:
+//! write a request, wait for the line to turn around, then read the reply
+//! until the bus falls silent. `Rs485Port::transact` implements this.
+
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use port::Rs485Port;
+
+/// Timing and size limits for receiving a reply
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReplyPolicy {
+    turnaround: Duration,
+    response_timeout: Duration,
+    inter_char_timeout: Duration,
+    max_len: usize,
+}
+
+impl Default for ReplyPolicy {
+    #[inline]
+    fn default() -> ReplyPolicy {
+        ReplyPolicy::new()
+    }
+}
+
+impl ReplyPolicy {
+    /// Create a new reply policy
+    ///
+    /// Defaults to no turnaround delay, a 1 s response timeout, a 20 ms
+    /// inter-character timeout and a maximum reply length of 256 bytes.
+    #[inline]
+    pub fn new() -> ReplyPolicy {
+        ReplyPolicy {
+            turnaround: Duration::from_millis(0),
+            response_timeout: Duration::from_secs(1),
+            inter_char_timeout: Duration::from_millis(20),
+            max_len: 256,
+        }
+    }
+
+    /// Set turnaround delay
+    ///
+    /// Time to wait after the request has been transmitted before listening
+    /// for a reply. Anything received up to the end of the request, e.g. its
+    /// echo, is discarded; a reply arriving during the delay is kept.
+    #[inline]
+    pub fn set_turnaround(&mut self, turnaround: Duration) -> &mut Self {
+        self.turnaround = turnaround;
+        self
+    }
+
+    /// Set response timeout
+    ///
+    /// Maximum time between the end of the request and the first byte of the
+    /// reply.
+    #[inline]
+    pub fn set_response_timeout(&mut self, response_timeout: Duration) -> &mut Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    /// Set inter-character timeout
+    ///
+    /// A reply is considered complete once the line has been silent for this
+    /// long.
+    #[inline]
+    pub fn set_inter_char_timeout(&mut self, inter_char_timeout: Duration) -> &mut Self {
+        self.inter_char_timeout = inter_char_timeout;
+        self
+    }
+
+    /// Set maximum reply length
+    ///
+    /// Reading stops once this many bytes have been received.
+    #[inline]
+    pub fn set_max_len(&mut self, max_len: usize) -> &mut Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Turnaround delay
+    #[inline]
+    pub fn turnaround(&self) -> Duration {
+        self.turnaround
+    }
+
+    /// Response timeout
+    #[inline]
+    pub fn response_timeout(&self) -> Duration {
+        self.response_timeout
+    }
+
+    /// Inter-character timeout
+    #[inline]
+    pub fn inter_char_timeout(&self) -> Duration {
+        self.inter_char_timeout
+    }
+
+    /// Maximum reply length
+    #[inline]
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+}
+
+/// Reason a reply was considered complete
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReplyEnd {
+    /// The inter-character timeout expired
+    Silence,
+    /// The maximum reply length was reached
+    MaxLen,
+}
+
+/// Reply received in a transaction, with timing information
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+    /// Received bytes
+    pub data: Vec<u8>,
+    /// Time taken to transmit the request, including direction control
+    pub tx_duration: Duration,
+    /// Time between the end of the request and the first reply byte
+    pub latency: Duration,
+    /// Time between the first and the last reply byte
+    pub rx_duration: Duration,
+    /// Reason the reply was considered complete
+    pub end: ReplyEnd,
+}
+
+/// Frame received by `read_frame`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Received bytes
+    pub data: Vec<u8>,
+    /// Time the first byte was received
+    pub first: Instant,
+    /// Time the last byte was received
+    pub last: Instant,
+    /// Reason the frame was considered complete
+    pub end: ReplyEnd,
+}
+
+/// Reads a frame until silence or `max_len`
+///
+/// Returns `None` if nothing was received within `first_byte_timeout`.
+pub fn read_frame(port: &mut Rs485Port,
+                  first_byte_timeout: Duration,
+                  inter_char_timeout: Duration,
+                  max_len: usize)
+                  -> io::Result<Option<Frame>> {
+    let mut data = vec![0; max_len];
+
+    let n = port.read_timeout(&mut data, first_byte_timeout)?;
+    if n == 0 {
+        return Ok(None);
+    }
+
+    let first = Instant::now();
+    let mut last = first;
+    let mut len = n;
+
+    while len < max_len {
+        let n = port.read_timeout(&mut data[len..], inter_char_timeout)?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        last = Instant::now();
+    }
+
+    let end = if len >= max_len {
+        ReplyEnd::MaxLen
+    } else {
+        ReplyEnd::Silence
+    };
+
+    data.truncate(len);
+    Ok(Some(Frame {
+        data,
+        first,
+        last,
+        end,
+    }))
+}
+
+impl Rs485Port {
+    /// Send a request and receive the reply
+    ///
+    /// Discards pending input, writes `request` and waits for it to be
+    /// transmitted completely. After the turnaround delay, the reply is read
+    /// until the line falls silent or `max_len` bytes have been received.
+    ///
+    /// Returns an error of kind `TimedOut` if no reply arrives within the
+    /// response timeout.
+    pub fn transact(&mut self, request: &[u8], policy: &ReplyPolicy) -> io::Result<Reply> {
+        self.discard_input()?;
+
+        let start = Instant::now();
+        self.write_all(request)?;
+        self.drain()?;
+        let sent = Instant::now();
+
+        if policy.turnaround > Duration::from_millis(0) {
+            // discarding after the delay would lose a reply sent early
+            self.discard_input()?;
+            thread::sleep(policy.turnaround);
+        }
+
+        let timeout = policy.response_timeout.saturating_sub(sent.elapsed());
+        match read_frame(self, timeout, policy.inter_char_timeout, policy.max_len)? {
+            None => Err(io::Error::new(io::ErrorKind::TimedOut, "no reply received")),
+            Some(frame) => {
+                Ok(Reply {
+                    data: frame.data,
+                    tx_duration: sent - start,
+                    latency: frame.first.saturating_duration_since(sent),
+                    rx_duration: frame.last - frame.first,
+                    end: frame.end,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use testutil::pty_port;
+
+    // answers a request of `len` bytes with `reply` after `delay`
+    fn answer(master: &mut ::std::fs::File, len: usize, delay: Duration, reply: &[u8]) {
+        let mut request = vec![0; len];
+        master.read_exact(&mut request).unwrap();
+        thread::sleep(delay);
+        master.write_all(reply).unwrap();
+    }
+
+    #[test]
+    fn reply_timing() {
+        let (mut master, mut port) = pty_port();
+        let peer = thread::spawn(move || {
+            answer(&mut master, 2, Duration::from_millis(50), b"\x10\x20\x30");
+            master
+        });
+
+        let reply = port.transact(b"\x01\x02", &ReplyPolicy::new()).unwrap();
+        peer.join().unwrap();
+
+        assert_eq!(reply.data, [0x10, 0x20, 0x30]);
+        assert_eq!(reply.end, ReplyEnd::Silence);
+        assert!(reply.latency >= Duration::from_millis(40), "{:?}", reply.latency);
+        assert!(reply.latency < Duration::from_millis(500), "{:?}", reply.latency);
+    }
+
+    #[test]
+    fn reply_during_turnaround() {
+        let (mut master, mut port) = pty_port();
+        let peer = thread::spawn(move || {
+            answer(&mut master, 2, Duration::from_millis(20), b"\x10\x20");
+            master
+        });
+
+        let mut policy = ReplyPolicy::new();
+        policy.set_turnaround(Duration::from_millis(100));
+        let start = Instant::now();
+        let reply = port.transact(b"\x01\x02", &policy).unwrap();
+        peer.join().unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(reply.data, [0x10, 0x20]);
+    }
+
+    #[test]
+    fn no_reply() {
+        let (_master, mut port) = pty_port();
+        let mut policy = ReplyPolicy::new();
+        policy.set_response_timeout(Duration::from_millis(50));
+
+        let err = port.transact(b"\x01", &policy).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}